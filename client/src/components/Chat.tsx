@@ -52,7 +52,11 @@ export function Chat() {
 				{messages.map((msg) => (
 					<div key={msg.id} className="chat-message">
 						<span className="message-author">@{msg.sender_id.slice(0, 8)}</span>
-						<span className="message-content">{msg.content}</span>
+						<span className="message-content">
+							{typeof msg.content === "string"
+								? msg.content
+								: "Encrypted message"}
+						</span>
 						<time className="message-time">
 							{new Date(msg.created_at).toLocaleTimeString()}
 						</time>
@@ -11,6 +11,9 @@ import { useState } from "react";
 import { useSocial } from "../hooks";
 import type { PostVisibility } from "../types";
 
+/** The reaction `like_post` toggles. */
+const LIKE_REACTION = "\u2764\ufe0f";
+
 export function Feed() {
 	const {
 		posts,
@@ -80,7 +83,8 @@ export function Feed() {
 
 			<div className="posts">
 				{posts.map((post) => {
-					const isLiked = peerId && post.likes.includes(peerId);
+					const likes = post.reactions.find((r) => r.emoji === LIKE_REACTION);
+					const isLiked = likes?.reacted ?? false;
 					const isFriend = friends.includes(post.author_id);
 					return (
 						<article key={post.id} className="post">
@@ -125,7 +129,7 @@ export function Feed() {
 									) : (
 										<Heart size={16} />
 									)}
-									<span>{post.likes.length}</span>
+									<span>{likes?.count ?? 0}</span>
 								</button>
 								<button type="button" className="btn-repost" title="Repost">
 									<Repeat2 size={16} />
@@ -1,14 +1,16 @@
 import { useState } from "react";
+import { generatePrivateKey } from "../crypto";
 import { useSocial } from "../hooks";
 
 export function Login() {
 	const { login, connected } = useSocial();
-	const [peerId, setPeerId] = useState("");
+	const [privateKey, setPrivateKey] = useState("");
+	const [invalid, setInvalid] = useState(false);
 
 	const handleSubmit = (e: React.FormEvent) => {
 		e.preventDefault();
-		if (peerId.trim()) {
-			login(peerId.trim());
+		if (privateKey.trim()) {
+			setInvalid(!login(privateKey.trim()));
 		}
 	};
 
@@ -23,14 +25,25 @@ export function Login() {
 				) : (
 					<form onSubmit={handleSubmit}>
 						<input
-							type="text"
-							value={peerId}
-							onChange={(e) => setPeerId(e.target.value)}
-							placeholder="Enter your peer ID"
+							type="password"
+							autoComplete="off"
+							value={privateKey}
+							onChange={(e) => {
+								setPrivateKey(e.target.value);
+								setInvalid(false);
+							}}
+							placeholder="Enter your private key"
 						/>
-						<button type="submit" disabled={!peerId.trim()}>
+						{invalid && <p className="login-error">Not an EdDSA private key</p>}
+						<button type="submit" disabled={!privateKey.trim()}>
 							Connect
 						</button>
+						<button
+							type="button"
+							onClick={() => setPrivateKey(generatePrivateKey())}
+						>
+							Generate new key
+						</button>
 					</form>
 				)}
 			</div>
@@ -0,0 +1,216 @@
+import { chacha20poly1305 } from "@noble/ciphers/chacha";
+import { randomBytes } from "@noble/ciphers/webcrypto";
+import {
+	ed25519,
+	edwardsToMontgomeryPriv,
+	edwardsToMontgomeryPub,
+	x25519,
+} from "@noble/curves/ed25519";
+import { sha512 } from "@noble/hashes/sha2";
+import type { SealedEnvelope, SealedMessage } from "../types";
+
+// Mirrors `gnunet::crypto` on the server, so envelopes sealed here open there
+// and the other way round.
+
+/** The server's `SIGNATURE_PURPOSE_PRIVATE_MESSAGE`. */
+const SIGNATURE_PURPOSE_PRIVATE_MESSAGE = 0x534f434d;
+/** `GNUNET_PUBLIC_KEY_TYPE_EDDSA`, which prefixes keys the identity daemon prints. */
+const KEY_TYPE_EDDSA = 65556;
+const NONCE_SIZE = 12;
+
+const ALPHABET = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ALIASES: Record<string, string> = { O: "0", I: "1", L: "1", U: "V" };
+
+/** Encodes `data` like `GNUNET_STRINGS_data_to_string`. */
+export function encode(data: Uint8Array): string {
+	let out = "";
+	let bits = 0;
+	let vbit = 0;
+	for (const byte of data) {
+		bits = (bits << 8) | byte;
+		vbit += 8;
+		while (vbit >= 5) {
+			out += ALPHABET[(bits >> (vbit - 5)) & 31];
+			vbit -= 5;
+		}
+		bits &= (1 << vbit) - 1;
+	}
+	if (vbit > 0) {
+		out += ALPHABET[(bits << (5 - vbit)) & 31];
+	}
+	return out;
+}
+
+/** Decodes like `GNUNET_STRINGS_string_to_data`; `null` unless exactly `size` bytes. */
+export function decode(
+	encoded: string,
+	size = Math.floor((encoded.length * 5) / 8),
+): Uint8Array | null {
+	if (Math.ceil((size * 8) / 5) !== encoded.length) return null;
+	const out = new Uint8Array(size);
+	let bits = 0;
+	let vbit = 0;
+	let pos = 0;
+	for (const c of encoded.toUpperCase()) {
+		const value = ALPHABET.indexOf(ALIASES[c] ?? c);
+		if (value < 0) return null;
+		bits = ((bits << 5) | value) & 0xfff;
+		vbit += 5;
+		if (vbit >= 8) {
+			if (pos < size) out[pos++] = (bits >> (vbit - 8)) & 0xff;
+			vbit -= 8;
+		}
+	}
+	return pos === size ? out : null;
+}
+
+/** The 32-byte seed of an EdDSA private key, plain or typed. */
+function eddsaSeed(privateKey: string): Uint8Array | null {
+	const data = decode(privateKey.trim());
+	if (data?.length === 32) return data;
+	if (
+		data?.length === 36 &&
+		new DataView(data.buffer).getUint32(0) === KEY_TYPE_EDDSA
+	) {
+		return data.slice(4);
+	}
+	return null;
+}
+
+export function generatePrivateKey(): string {
+	return encode(ed25519.utils.randomPrivateKey());
+}
+
+/** The peer id of `privateKey`, or `null` if it is not an EdDSA key. */
+export function peerIdOf(privateKey: string): string | null {
+	const seed = eddsaSeed(privateKey);
+	return seed && encode(ed25519.getPublicKey(seed));
+}
+
+function concat(...parts: Uint8Array[]): Uint8Array {
+	const out = new Uint8Array(parts.reduce((n, p) => n + p.length, 0));
+	let pos = 0;
+	for (const part of parts) {
+		out.set(part, pos);
+		pos += part.length;
+	}
+	return out;
+}
+
+/** The AEAD key: the first half of the SHA-512 of the shared point, as GNUnet hashes it. */
+function aeadKey(shared: Uint8Array): Uint8Array {
+	return sha512(shared).slice(0, 32);
+}
+
+/** `GNUNET_CRYPTO_ecdh_eddsa` with an ephemeral key, then ChaCha20-Poly1305. */
+function seal(
+	recipient: Uint8Array,
+	plaintext: Uint8Array,
+	aad: Uint8Array,
+): SealedMessage {
+	const ephemeral = x25519.utils.randomPrivateKey();
+	const shared = x25519.getSharedSecret(
+		ephemeral,
+		edwardsToMontgomeryPub(recipient),
+	);
+	const nonce = randomBytes(NONCE_SIZE);
+	const ciphertext = chacha20poly1305(aeadKey(shared), nonce, aad).encrypt(
+		plaintext,
+	);
+	return {
+		ephemeral_key: encode(x25519.getPublicKey(ephemeral)),
+		nonce: encode(nonce),
+		ciphertext: encode(ciphertext),
+	};
+}
+
+/** `GNUNET_CRYPTO_eddsa_ecdh` with the ephemeral key, then ChaCha20-Poly1305. */
+function open(
+	sealed: SealedMessage,
+	seed: Uint8Array,
+	aad: Uint8Array,
+): Uint8Array | null {
+	const ephemeral = decode(sealed.ephemeral_key, 32);
+	const nonce = decode(sealed.nonce, NONCE_SIZE);
+	const ciphertext = decode(sealed.ciphertext);
+	if (!ephemeral || !nonce || !ciphertext) return null;
+	try {
+		const shared = x25519.getSharedSecret(
+			edwardsToMontgomeryPriv(seed),
+			ephemeral,
+		);
+		return chacha20poly1305(aeadKey(shared), nonce, aad).decrypt(ciphertext);
+	} catch {
+		return null;
+	}
+}
+
+/** What `GNUNET_CRYPTO_eddsa_sign_` signs: size and purpose, then `data`. */
+function signatureBlock(data: Uint8Array): Uint8Array {
+	const block = new Uint8Array(8 + data.length);
+	const view = new DataView(block.buffer);
+	view.setUint32(0, block.length);
+	view.setUint32(4, SIGNATURE_PURPOSE_PRIVATE_MESSAGE);
+	block.set(data, 8);
+	return block;
+}
+
+/** The peer ids, then the fields of both copies joined by `.`. */
+function signedData(
+	envelope: Omit<SealedEnvelope, "signature">,
+	aad: Uint8Array,
+): Uint8Array {
+	const copies = [envelope.recipient_copy, envelope.sender_copy]
+		.flatMap((c) => [c.ephemeral_key, c.nonce, c.ciphertext])
+		.join(".");
+	return concat(aad, new TextEncoder().encode(copies));
+}
+
+/** Seals `text` to `recipientId` and to the sender, and signs both copies. */
+export function sealEnvelope(
+	privateKey: string,
+	recipientId: string,
+	text: string,
+): SealedEnvelope | null {
+	const seed = eddsaSeed(privateKey);
+	const recipient = decode(recipientId, 32);
+	if (!seed || !recipient) return null;
+
+	const sender = ed25519.getPublicKey(seed);
+	const aad = concat(sender, recipient);
+	const plaintext = new TextEncoder().encode(text);
+	const copies = {
+		recipient_copy: seal(recipient, plaintext, aad),
+		sender_copy: seal(sender, plaintext, aad),
+	};
+	const signature = ed25519.sign(signatureBlock(signedData(copies, aad)), seed);
+	return { ...copies, signature: encode(signature) };
+}
+
+/** Checks the sender's signature and opens the copy meant for `privateKey`. */
+export function openEnvelope(
+	envelope: SealedEnvelope,
+	privateKey: string,
+	senderId: string,
+	recipientId: string,
+): string | null {
+	const seed = eddsaSeed(privateKey);
+	const sender = decode(senderId, 32);
+	const recipient = decode(recipientId, 32);
+	const signature = decode(envelope.signature, 64);
+	if (!seed || !sender || !recipient || !signature) return null;
+
+	const aad = concat(sender, recipient);
+	const block = signatureBlock(signedData(envelope, aad));
+	if (!ed25519.verify(signature, block, sender)) return null;
+
+	const own = encode(ed25519.getPublicKey(seed));
+	const copy =
+		own === encode(recipient)
+			? envelope.recipient_copy
+			: own === encode(sender)
+				? envelope.sender_copy
+				: null;
+	const plaintext = copy && open(copy, seed, aad);
+	return plaintext && new TextDecoder().decode(plaintext);
+}
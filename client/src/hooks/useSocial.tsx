@@ -4,9 +4,11 @@ import {
 	useCallback,
 	useContext,
 	useEffect,
+	useMemo,
 	useRef,
 	useState,
 } from "react";
+import { openEnvelope, peerIdOf, sealEnvelope } from "../crypto";
 import type {
 	ChatMessage,
 	ChatRoom,
@@ -31,7 +33,8 @@ export interface SocialContextValue {
 	privateMessages: PrivateMessage[];
 	friends: string[];
 	send: (msg: ClientMessage) => boolean;
-	login: (peerId: string) => void;
+	/** Signs in with an EdDSA private key; false if the key is malformed. */
+	login: (privateKey: string) => boolean;
 	createPost: (content: string, visibility?: string) => void;
 	likePost: (postId: string) => void;
 	createRoom: (name: string, isGroup: boolean, isPublic: boolean) => void;
@@ -39,6 +42,8 @@ export interface SocialContextValue {
 	joinRoom: (roomId: string) => void;
 	sendMessage: (roomId: string, content: string) => void;
 	sendPrivateMessage: (recipientId: string, content: string) => void;
+	/** The plaintext of a direct message, or null if it can't be opened. */
+	readPrivateMessage: (message: PrivateMessage) => string | null;
 	setCurrentRoom: (room: ChatRoom | null) => void;
 	requestFriend: (peerId: string) => void;
 	acceptFriend: (peerId: string) => void;
@@ -54,10 +59,10 @@ export interface SocialContextValue {
 
 const SocialContext = createContext<SocialContextValue | null>(null);
 
-const PEER_ID_KEY = "gnunet_peer_id";
+const PRIVATE_KEY_KEY = "gnunet_private_key";
 
-function getStoredPeerId(): string | null {
-	return localStorage.getItem(PEER_ID_KEY);
+function getStoredPrivateKey(): string | null {
+	return localStorage.getItem(PRIVATE_KEY_KEY);
 }
 
 function handleEvent(
@@ -80,6 +85,33 @@ function handleEvent(
 		case "new_private_message":
 			setPrivateMessages((prev) => [...prev, event.message]);
 			break;
+		case "message_receipt": {
+			const { target, peer_id, state, at } = event;
+			const ids = new Set(target.message_ids);
+			if (target.kind === "private_message") {
+				setPrivateMessages((prev) =>
+					prev.map((m) =>
+						ids.has(m.id)
+							? {
+									...m,
+									state,
+									delivered_at: m.delivered_at ?? at,
+									read_at: state === "read" ? (m.read_at ?? at) : m.read_at,
+								}
+							: m,
+					),
+				);
+			} else if (currentRoom?.id === target.room_id) {
+				setRoomMessages((prev) =>
+					prev.map((m) =>
+						ids.has(m.id)
+							? { ...m, receipts: { ...m.receipts, [peer_id]: { state, at } } }
+							: m,
+					),
+				);
+			}
+			break;
+		}
 		case "friend_request":
 			setFriends((prev) =>
 				prev.includes(event.from) ? prev : [...prev, event.from],
@@ -90,6 +122,14 @@ function handleEvent(
 				prev.includes(event.peer_id) ? prev : [...prev, event.peer_id],
 			);
 			break;
+		case "friendship_ended":
+			if (event.friendship.status === "Accepted") {
+				const { requester_id, addressee_id } = event.friendship;
+				setFriends((prev) =>
+					prev.filter((id) => id !== requester_id && id !== addressee_id),
+				);
+			}
+			break;
 	}
 }
 
@@ -98,7 +138,13 @@ export function SocialProvider({ children }: { children: ReactNode }) {
 
 	const { connected, send, subscribe } = useWebSocket(wsUrl);
 	const [authenticated, setAuthenticated] = useState(false);
-	const [peerId, setPeerId] = useState<string | null>(getStoredPeerId);
+	const [privateKey, setPrivateKey] = useState<string | null>(
+		getStoredPrivateKey,
+	);
+	const peerId = useMemo(
+		() => (privateKey ? peerIdOf(privateKey) : null),
+		[privateKey],
+	);
 	const [user, setUser] = useState<User | null>(null);
 	const [users, setUsers] = useState<Map<string, User>>(new Map());
 	const [posts, setPosts] = useState<Post[]>([]);
@@ -180,7 +226,15 @@ export function SocialProvider({ children }: { children: ReactNode }) {
 					}
 					break;
 				case "friend":
-					if (msg.friends) setFriends(msg.friends);
+					if (msg.friendships) {
+						setFriends(
+							msg.friendships
+								.filter((f) => f.status === "Accepted")
+								.map((f) =>
+									f.requester_id === peerId ? f.addressee_id : f.requester_id,
+								),
+						);
+					}
 					break;
 				case "event":
 					handleEvent(
@@ -195,11 +249,13 @@ export function SocialProvider({ children }: { children: ReactNode }) {
 			}
 		});
 		return unsubscribe;
-	}, [subscribe, send]);
+	}, [subscribe, send, peerId]);
 
-	const login = useCallback((newPeerId: string) => {
-		localStorage.setItem(PEER_ID_KEY, newPeerId);
-		setPeerId(newPeerId);
+	const login = useCallback((newPrivateKey: string) => {
+		if (!peerIdOf(newPrivateKey)) return false;
+		localStorage.setItem(PRIVATE_KEY_KEY, newPrivateKey);
+		setPrivateKey(newPrivateKey);
+		return true;
 	}, []);
 
 	const createPost = useCallback(
@@ -258,14 +314,29 @@ export function SocialProvider({ children }: { children: ReactNode }) {
 
 	const sendPrivateMessage = useCallback(
 		(recipientId: string, content: string) => {
+			const envelope =
+				privateKey && sealEnvelope(privateKey, recipientId, content);
+			if (!envelope) return;
 			send({
 				type: "send_private_message",
 				recipient_id: recipientId,
-				content,
+				content: envelope,
 				media_hashes: [],
 			});
 		},
-		[send],
+		[send, privateKey],
+	);
+
+	const readPrivateMessage = useCallback(
+		(message: PrivateMessage) =>
+			privateKey &&
+			openEnvelope(
+				message.content,
+				privateKey,
+				message.sender_id,
+				message.recipient_id,
+			),
+		[privateKey],
 	);
 
 	const requestFriend = useCallback(
@@ -360,6 +431,7 @@ export function SocialProvider({ children }: { children: ReactNode }) {
 				joinRoom,
 				sendMessage,
 				sendPrivateMessage,
+				readPrivateMessage,
 				setCurrentRoom,
 				requestFriend,
 				acceptFriend,
@@ -19,45 +19,206 @@ export interface Post {
 	id: string;
 	author_id: string;
 	content: string;
+	entities: Entity[];
 	media_hashes: string[];
 	reply_to: string | null;
 	repost_of: string | null;
 	visibility: PostVisibility;
 	created_at: string;
-	likes: string[];
+	reactions: ReactionCount[];
 	reposts: number;
+	quotes: number;
+	reply_count: number;
+	edited_at: string | null;
+	history: Revision<string>[];
+	deleted_at: string | null;
 }
 
+/** Offsets count UTF-16 code units. */
+export type Entity = { start: number; end: number } & (
+	| { type: "hashtag"; tag: string }
+	| { type: "mention"; name: string; peer_id: string | null }
+	| { type: "link"; url: string }
+);
+
+export type NotificationKind =
+	| "mention"
+	| "reply"
+	| "repost"
+	| "reaction"
+	| "friend_request"
+	| "friend_accepted"
+	| "room_invite"
+	| "join_request";
+
+export interface Notification {
+	id: string;
+	recipient_id: string;
+	kind: NotificationKind;
+	actor_id: string;
+	post_id: string | null;
+	room_id: string | null;
+	message_id: string | null;
+	emoji: string | null;
+	created_at: string;
+	read_at: string | null;
+}
+
+export type ReactionTarget =
+	| { kind: "post"; post_id: string }
+	| { kind: "message"; message_id: string };
+
+export interface ReactionCount {
+	emoji: string;
+	count: number;
+	reacted: boolean;
+}
+
+export interface Revision<C> {
+	content: C;
+	replaced_at: string;
+}
+
+export type RoomRole = "member" | "moderator" | "admin" | "owner";
+
 export interface ChatRoom {
 	id: string;
 	name: string;
 	description: string | null;
 	owner_id: string;
 	admins: string[];
+	moderators: string[];
 	members: string[];
+	banned: string[];
 	is_group: boolean;
 	is_public: boolean;
+	encrypted: boolean;
+	key_epoch: number;
+	rekey_pending: boolean;
+	created_at: string;
+}
+
+export interface RoomInvite {
+	id: string;
+	room_id: string;
+	inviter_id: string;
+	invitee_id: string;
 	created_at: string;
 }
 
+export interface JoinRequest {
+	room_id: string;
+	peer_id: string;
+	created_at: string;
+}
+
+export interface InviteLink {
+	id: string;
+	room_id: string;
+	created_by: string;
+	created_at: string;
+	expires_at: string | null;
+	max_uses: number | null;
+	uses: number;
+}
+
+export interface SealedMessage {
+	ephemeral_key: string;
+	nonce: string;
+	ciphertext: string;
+}
+
+export interface SealedEnvelope {
+	recipient_copy: SealedMessage;
+	sender_copy: SealedMessage;
+	signature: string;
+}
+
+export interface GroupCiphertext {
+	epoch: number;
+	nonce: string;
+	ciphertext: string;
+}
+
+export type MessageContent = string | GroupCiphertext;
+
+export interface MediaInfo {
+	hash: string;
+	mime_type: string;
+	size: number;
+	created_at: string;
+	width?: number;
+	height?: number;
+	duration_ms?: number;
+	variants?: MediaVariant[];
+}
+
+export interface MediaVariant {
+	kind: "thumbnail" | "preview";
+	hash: string;
+	mime_type: string;
+	width: number;
+	height: number;
+}
+
+export interface UploadStatus {
+	upload_id: string;
+	size: number;
+	received: number;
+	chunk_size: number;
+}
+
+export interface GroupKeyMember {
+	peer_id: string;
+	zone: string | null;
+}
+
+export type DeliveryState = "sent" | "delivered" | "read";
+
+export interface Receipt {
+	state: DeliveryState;
+	at: string;
+}
+
+export type ReceiptTarget =
+	| { kind: "private_message"; message_ids: string[] }
+	| { kind: "room"; room_id: string; message_ids: string[] };
+
 export interface ChatMessage {
 	id: string;
 	room_id: string;
 	sender_id: string;
-	content: string;
+	content: MessageContent;
 	media_hashes: string[];
 	reply_to: string | null;
 	created_at: string;
+	reactions: ReactionCount[];
+	edited_at: string | null;
+	history: Revision<MessageContent>[];
+	deleted_at: string | null;
+	receipts: Record<string, Receipt>;
 }
 
 export interface PrivateMessage {
 	id: string;
 	sender_id: string;
 	recipient_id: string;
-	content: string;
+	content: SealedEnvelope;
 	media_hashes: string[];
 	created_at: string;
+	state: DeliveryState;
+	delivered_at: string | null;
 	read_at: string | null;
+	edited_at: string | null;
+	history: Revision<PrivateMessage["content"]>[];
+	deleted_at: string | null;
+}
+
+export interface ConversationSummary {
+	peer_id: string;
+	last_message: PrivateMessage | null;
+	last_activity: string;
+	unread: number;
 }
 
 export interface Friendship {
@@ -68,6 +229,22 @@ export interface Friendship {
 	updated_at: string;
 }
 
+export type RequestDirection = "incoming" | "outgoing";
+
+export interface Contact {
+	petname: string;
+	zone: string;
+	user: User | null;
+}
+
+export interface EgoInfo {
+	name: string | null;
+	peer_id: string;
+	zone: string | null;
+	active: boolean;
+	user: User | null;
+}
+
 export type ClientMessage =
 	| { type: "auth"; peer_id: string; token?: string }
 	| {
@@ -76,7 +253,12 @@ export type ClientMessage =
 			display_name?: string;
 			bio?: string;
 	  }
-	| { type: "update_user"; display_name?: string; bio?: string }
+	| {
+			type: "update_user";
+			display_name?: string;
+			bio?: string;
+			avatar_hash?: string;
+	  }
 	| {
 			type: "create_post";
 			content: string;
@@ -84,72 +266,378 @@ export type ClientMessage =
 			reply_to?: string;
 			repost_of?: string;
 			visibility: string;
+			ego?: string;
 	  }
 	| { type: "get_feed"; peer_id: string; limit?: number; before?: string }
 	| { type: "get_post"; post_id: string }
+	| {
+			type: "get_hashtag_feed";
+			tag: string;
+			limit?: number;
+			before?: string;
+	  }
+	| {
+			type: "get_thread";
+			post_id: string;
+			depth?: number;
+			limit?: number;
+			after?: string;
+			after_id?: string;
+	  }
+	| { type: "undo_repost"; post_id: string; ego?: string }
+	| {
+			type: "edit_post";
+			post_id: string;
+			content: string;
+			media_hashes?: string[];
+			ego?: string;
+	  }
+	| { type: "delete_post"; post_id: string; ego?: string }
 	| { type: "like_post"; post_id: string; unlike?: boolean }
+	| { type: "react"; target: ReactionTarget; emoji: string; ego?: string }
+	| { type: "unreact"; target: ReactionTarget; emoji: string; ego?: string }
 	| {
 			type: "create_room";
 			name: string;
 			description?: string;
 			is_group: boolean;
 			is_public: boolean;
+			encrypted?: boolean;
+			ego?: string;
 	  }
 	| { type: "get_rooms" }
-	| { type: "join_room"; room_id: string }
-	| { type: "leave_room"; room_id: string }
+	| { type: "join_room"; room_id: string; ego?: string }
+	| { type: "leave_room"; room_id: string; ego?: string }
+	| { type: "invite_to_room"; room_id: string; peer_id: string; ego?: string }
+	| { type: "accept_invite" | "decline_invite"; invite_id: string; ego?: string }
+	| { type: "get_room_invites"; ego?: string }
+	| { type: "request_join_room"; room_id: string; ego?: string }
+	| {
+			type: "approve_join_request" | "deny_join_request";
+			room_id: string;
+			peer_id: string;
+			ego?: string;
+	  }
+	| { type: "get_join_requests"; room_id: string; ego?: string }
+	| {
+			type: "create_invite_link";
+			room_id: string;
+			expires_in?: number;
+			max_uses?: number;
+			ego?: string;
+	  }
+	| { type: "join_with_invite_link"; token: string; ego?: string }
+	| { type: "list_public_rooms"; limit?: number; before?: string }
+	| {
+			type: "kick_member" | "ban_member" | "unban_member" | "transfer_ownership";
+			room_id: string;
+			peer_id: string;
+			ego?: string;
+	  }
+	| {
+			type: "promote_member";
+			room_id: string;
+			peer_id: string;
+			role: RoomRole;
+			ego?: string;
+	  }
+	| {
+			type: "update_room";
+			room_id: string;
+			name?: string;
+			description?: string;
+			is_public?: boolean;
+			ego?: string;
+	  }
+	| { type: "delete_room"; room_id: string; ego?: string }
 	| {
 			type: "send_room_message";
 			room_id: string;
-			content: string;
+			content: MessageContent;
 			media_hashes: string[];
 			reply_to?: string;
+			ego?: string;
 	  }
 	| {
 			type: "get_room_messages";
 			room_id: string;
 			limit?: number;
 			before?: string;
+			ego?: string;
 	  }
-	| { type: "request_friend"; peer_id: string }
-	| { type: "accept_friend"; peer_id: string }
+	| {
+			type: "edit_room_message";
+			message_id: string;
+			content: MessageContent;
+			ego?: string;
+	  }
+	| { type: "delete_room_message"; message_id: string; ego?: string }
+	| { type: "request_friend"; peer_id: string; ego?: string }
+	| { type: "accept_friend"; peer_id: string; ego?: string }
+	| { type: "reject_friend"; peer_id: string; ego?: string }
+	| { type: "cancel_friend_request"; peer_id: string; ego?: string }
+	| { type: "unfriend"; peer_id: string; ego?: string }
 	| { type: "get_friends" }
+	| { type: "get_pending_requests"; direction?: RequestDirection }
+	| {
+			type: "block_user" | "unblock_user" | "mute_user" | "unmute_user";
+			peer_id: string;
+			ego?: string;
+	  }
+	| { type: "get_blocked_users"; ego?: string }
 	| {
 			type: "send_private_message";
 			recipient_id: string;
-			content: string;
+			content: SealedEnvelope;
 			media_hashes: string[];
+			ego?: string;
 	  }
-	| { type: "get_private_messages"; peer_id?: string; limit?: number }
+	| {
+			type: "get_private_messages";
+			peer_id?: string;
+			limit?: number;
+			before?: string;
+	  }
+	| {
+			type: "edit_private_message";
+			message_id: string;
+			content: SealedEnvelope;
+	  }
+	| { type: "delete_private_message"; message_id: string }
+	| { type: "get_conversations"; limit?: number; before?: string }
+	| { type: "mark_conversation_read"; peer_id: string }
+	| { type: "message_read"; target: ReceiptTarget; ego?: string }
+	| { type: "set_read_receipts"; enabled?: boolean; ego?: string }
 	| { type: "get_user"; peer_id: string }
-	| { type: "search_users"; query: string; limit?: number };
+	| { type: "search_users"; query: string; limit?: number }
+	| { type: "search_posts"; query: string; limit?: number }
+	| { type: "search_rooms"; query: string; limit?: number }
+	| { type: "resolve_name"; name: string }
+	| { type: "set_petname"; petname: string; target: string }
+	| { type: "remove_petname"; petname: string }
+	| { type: "list_contacts" }
+	| {
+			type: "create_ego";
+			username: string;
+			display_name?: string;
+			bio?: string;
+			activate?: boolean;
+	  }
+	| { type: "use_ego"; ego: string }
+	| { type: "list_egos" }
+	| {
+			type: "rotate_group_key";
+			room_id: string;
+			epoch: number;
+			keys: Record<string, SealedMessage>;
+			ego?: string;
+	  }
+	| { type: "get_group_keys"; room_id: string; ego?: string }
+	| { type: "upload_media"; size: number; hash?: string; keep_location?: boolean }
+	| { type: "get_media"; hash: string; offset?: number; length?: number }
+	| {
+			type: "get_notifications";
+			limit?: number;
+			before?: string;
+			unread_only?: boolean;
+			ego?: string;
+	  }
+	| { type: "mark_notifications_read"; ids?: string[]; ego?: string }
+	| {
+			type: "set_notification_muted";
+			kind: NotificationKind;
+			muted: boolean;
+			ego?: string;
+	  };
 
 export type ServerMessage =
 	| { type: "auth"; success: boolean; peer_id: string }
 	| { type: "user"; user: User | null }
-	| { type: "post"; post: Post | null }
-	| { type: "feed"; posts: Post[] }
+	| { type: "post"; post: Post | null; originals: Post[]; media: MediaInfo[] }
+	| { type: "feed"; posts: Post[]; originals: Post[]; media: MediaInfo[] }
+	| {
+			type: "thread";
+			post: Post;
+			ancestors: Post[];
+			replies: Post[];
+			has_more: boolean;
+			originals: Post[];
+			media: MediaInfo[];
+	  }
+	| { type: "reactions"; target: ReactionTarget; reactions: ReactionCount[] }
+	| {
+			type: "notifications";
+			notifications: Notification[] | null;
+			has_more: boolean;
+			unread: number;
+			muted: NotificationKind[];
+	  }
 	| { type: "room"; room: ChatRoom | null; rooms?: ChatRoom[] }
+	| {
+			type: "room_invite";
+			invite: RoomInvite | null;
+			invites: RoomInvite[] | null;
+	  }
+	| {
+			type: "join_request";
+			request: JoinRequest | null;
+			requests: JoinRequest[] | null;
+	  }
+	| { type: "invite_link"; link: InviteLink; token: string }
 	| {
 			type: "room_message";
 			message: ChatMessage | null;
 			messages?: ChatMessage[];
 	  }
-	| { type: "friend"; friendship: Friendship | null; friends?: string[] }
+	| {
+			type: "friend";
+			friendship: Friendship | null;
+			friendships: Friendship[] | null;
+	  }
+	| { type: "blocks"; blocked: string[]; muted: string[] }
 	| {
 			type: "private_message";
 			message: PrivateMessage | null;
 			messages?: PrivateMessage[];
 	  }
+	| {
+			type: "conversation";
+			conversation: ConversationSummary | null;
+			conversations: ConversationSummary[] | null;
+			has_more: boolean;
+	  }
+	| { type: "receipt"; target: ReceiptTarget; state: DeliveryState }
+	| { type: "read_receipts"; enabled: boolean }
 	| { type: "error"; code: number; message: string }
 	| { type: "event"; event: EventMessage }
-	| { type: "search_users"; users: User[] };
+	| { type: "search_users"; users: User[] }
+	| {
+			type: "search_posts";
+			posts: Post[];
+			originals: Post[];
+			media: MediaInfo[];
+	  }
+	| { type: "search_rooms"; rooms: ChatRoom[] }
+	| { type: "contact"; contact: Contact | null; contacts?: Contact[] }
+	| { type: "ego"; ego: EgoInfo | null; egos?: EgoInfo[] }
+	| {
+			type: "group_key";
+			room_id: string;
+			epoch: number;
+			rekey_pending: boolean;
+			members: GroupKeyMember[];
+			keys: { epoch: number; key: SealedMessage }[];
+	  }
+	| {
+			type: "media";
+			media: MediaInfo | null;
+			upload: UploadStatus | null;
+			range: { offset: number; length: number } | null;
+	  };
 
 export type EventMessage =
 	| { event: "new_post"; post: Post }
+	| { event: "mentioned"; peer_id: string; post: Post }
+	| { event: "new_reply"; parent_id: string; parent_author: string; post: Post }
+	| {
+			event: "new_repost";
+			original_id: string;
+			original_author: string;
+			post: Post;
+	  }
+	| { event: "post_edited"; post: Post }
+	| {
+			event: "reaction_added" | "reaction_removed";
+			target: ReactionTarget;
+			emoji: string;
+			peer_id: string;
+			reactions: ReactionCount[];
+	  }
+	| { event: "post_deleted"; post_id: string; author_id: string }
+	| { event: "room_message_edited"; room_id: string; message: ChatMessage }
+	| {
+			event: "room_message_deleted";
+			room_id: string;
+			message_id: string;
+			deleted_by: string;
+	  }
+	| { event: "private_message_edited"; message: PrivateMessage }
+	| {
+			event: "message_receipt";
+			target: ReceiptTarget;
+			peer_id: string;
+			state: DeliveryState;
+			at: string;
+	  }
+	| {
+			event: "conversation_read";
+			peer_id: string;
+			sender_id: string;
+			message_ids: string[];
+			read_at: string;
+	  }
+	| {
+			event: "private_message_deleted";
+			message_id: string;
+			sender_id: string;
+			recipient_id: string;
+	  }
 	| { event: "new_room_message"; room_id: string; message: ChatMessage }
+	| { event: "room_member_joined"; room_id: string; peer_id: string }
+	| { event: "room_invited" | "room_invite_declined"; invite: RoomInvite }
+	| { event: "room_join_requested"; request: JoinRequest }
+	| {
+			event: "room_join_request_answered";
+			room_id: string;
+			peer_id: string;
+			approved: boolean;
+			answered_by: string;
+	  }
+	| {
+			event: "room_member_removed";
+			room_id: string;
+			peer_id: string;
+			removed_by: string;
+			banned: boolean;
+	  }
+	| {
+			event: "room_member_unbanned";
+			room_id: string;
+			peer_id: string;
+			unbanned_by: string;
+	  }
+	| {
+			event: "room_role_changed";
+			room_id: string;
+			peer_id: string;
+			role: RoomRole;
+			changed_by: string;
+	  }
+	| { event: "room_updated"; room: ChatRoom; updated_by: string }
+	| { event: "room_deleted"; room_id: string; deleted_by: string }
+	| {
+			event: "room_ownership_transferred";
+			room_id: string;
+			previous_owner: string;
+			owner: string;
+	  }
 	| { event: "new_private_message"; message: PrivateMessage }
 	| { event: "friend_request"; from: string; friendship: Friendship }
 	| { event: "friend_accepted"; peer_id: string }
+	| { event: "friendship_ended"; friendship: Friendship; ended_by: string }
+	| { event: "new_notification"; notification: Notification }
+	| { event: "unread_notifications"; peer_id: string; unread: number }
 	| { event: "user_online"; peer_id: string }
-	| { event: "user_offline"; peer_id: string };
+	| { event: "user_offline"; peer_id: string }
+	| {
+			event: "group_key_rotation_required";
+			room_id: string;
+			epoch: number;
+			members: GroupKeyMember[];
+	  }
+	| {
+			event: "group_key_rotated";
+			room_id: string;
+			epoch: number;
+			distributed_by: string;
+	  };
@@ -1,5 +1,6 @@
-use crate::gnunet::PeerIdentity;
-use crate::social::ChatMessage;
+use crate::gnunet::{HashCode, PeerIdentity};
+use crate::social::{ChatMessage, DeliveryState, ReceiptTarget};
+use chrono::{DateTime, Utc};
 use futures::channel::mpsc::{Receiver, Sender, channel};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -65,6 +66,23 @@ impl CadetService {
         channel
     }
 
+    /// An open channel to `peer` on `port`, created if there is none.
+    pub fn channel_to(&mut self, peer: &PeerIdentity, port: &str) -> Channel {
+        let open = self
+            .channels
+            .values()
+            .find(|c| c.peer == *peer && c.port == port)
+            .cloned();
+        match open {
+            Some(channel) => channel,
+            None => self.create_channel(peer.clone(), port),
+        }
+    }
+
+    pub fn channel(&self, channel_id: ChannelId) -> Option<&Channel> {
+        self.channels.get(&channel_id)
+    }
+
     pub fn destroy_channel(&mut self, channel_id: ChannelId) {
         self.channels.remove(&channel_id);
     }
@@ -108,6 +126,9 @@ pub enum SocialCadetMessage {
         post_id: Uuid,
         author: String,
         content: String,
+        /// Blobs the receiving server fetches over `FILESHARE_PORT`.
+        #[serde(default)]
+        media_hashes: Vec<HashCode>,
     },
     Chat {
         room_id: Uuid,
@@ -122,4 +143,29 @@ pub enum SocialCadetMessage {
     PrivateMessage {
         message: crate::social::PrivateMessage,
     },
+    /// Deletion of something sent earlier; receivers replace it with a
+    /// tombstone.
+    Tombstone {
+        target: TombstoneTarget,
+    },
+    /// The sending peer added or removed a reaction.
+    Reaction {
+        target: crate::social::ReactionTarget,
+        emoji: String,
+        reacted: bool,
+    },
+    /// The sending peer got the target messages as far as `state`.
+    Receipt {
+        target: ReceiptTarget,
+        state: DeliveryState,
+        at: DateTime<Utc>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TombstoneTarget {
+    Post { post_id: Uuid },
+    Chat { room_id: Uuid, message_id: Uuid },
+    PrivateMessage { message_id: Uuid },
 }
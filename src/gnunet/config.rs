@@ -7,6 +7,7 @@ pub struct Config {
     pub peer_identity: Option<String>,
     pub cadet_port: u16,
     pub gns_zone: Option<String>,
+    pub keyring_path: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -16,6 +17,7 @@ impl Default for Config {
             peer_identity: None,
             cadet_port: 0,
             gns_zone: None,
+            keyring_path: None,
         }
     }
 }
@@ -1,40 +1,110 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use serde::{Deserialize, Serialize};
 use std::ffi::{CStr, CString};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct PeerIdentity(String);
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const SIGNATURE_SIZE: usize = 64;
+
+/// Signature purpose of [`SealedEnvelope`]s (`SOCM`), chosen by this
+/// application.
+pub const SIGNATURE_PURPOSE_PRIVATE_MESSAGE: u32 = 0x534f_434d;
+/// Signature purpose of room invite link tokens (`SOCI`).
+pub const SIGNATURE_PURPOSE_INVITE_LINK: u32 = 0x534f_4349;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("invalid key")]
+    InvalidKey,
+    #[error("invalid encoding of {0}")]
+    InvalidEncoding(&'static str),
+    #[error("key exchange failed")]
+    KeyExchange,
+    #[error("encryption failed")]
+    Encryption,
+    #[error("decryption failed")]
+    Decryption,
+    #[error("key derivation failed")]
+    KeyDerivation,
+    #[error("signing failed")]
+    Signing,
+    #[error("invalid signature")]
+    InvalidSignature,
+}
+
+/// A peer's EdDSA public key, as `struct GNUNET_PeerIdentity` holds it.
+///
+/// Serialized as the GNUnet string encoding, so malformed ids are refused when
+/// a message is parsed rather than turning into a zeroed key later on.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerIdentity([u8; PEER_IDENTITY_SIZE]);
+
+pub const PEER_IDENTITY_SIZE: usize = 32;
 
 impl PeerIdentity {
-    pub fn new(id: impl Into<String>) -> Self {
-        Self(id.into())
+    pub fn from_bytes(bytes: [u8; PEER_IDENTITY_SIZE]) -> Self {
+        Self(bytes)
     }
 
-    pub fn as_str(&self) -> &str {
+    pub fn as_bytes(&self) -> &[u8; PEER_IDENTITY_SIZE] {
         &self.0
     }
 
+    /// A fresh identity backed by a newly created (and discarded) EdDSA key.
     pub fn generate() -> Self {
-        Self(uuid::Uuid::new_v4().to_string())
+        let mut key: gnunet_sys::GNUNET_CRYPTO_EddsaPrivateKey = unsafe { std::mem::zeroed() };
+        let mut public: gnunet_sys::GNUNET_CRYPTO_EddsaPublicKey = unsafe { std::mem::zeroed() };
+        unsafe {
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_create(&mut key);
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_get_public(&key, &mut public);
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_clear(&mut key);
+        }
+        Self(public.q_y)
+    }
+
+    /// The identity of the peer whose key is `key`.
+    pub fn from_public_key(key: &PublicKey) -> Result<Self, CryptoError> {
+        let key = key.to_gnunet_eddsa().ok_or(CryptoError::InvalidKey)?;
+        Ok(Self(key.q_y))
     }
 
     pub fn from_gnunet(peer: &gnunet_sys::GNUNET_PeerIdentity) -> Self {
-        unsafe {
-            // GNUNET_i2s returns a pointer to a static buffer, no free needed
-            let cstr = gnunet_sys::GNUNET_i2s(peer);
-            Self(CStr::from_ptr(cstr).to_string_lossy().into_owned())
-        }
+        Self(peer.public_key.q_y)
+    }
+
+    /// The peer's key, to seal messages to.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_gnunet_eddsa(&self.to_gnunet().public_key)
+    }
+
+    /// Checks `signature`, as made by [`PrivateKey::sign`], against this
+    /// peer's key.
+    pub fn verify(&self, purpose: u32, data: &[u8], signature: &str) -> bool {
+        let Ok(bytes) = decode_fixed::<SIGNATURE_SIZE>(signature, "signature") else {
+            return false;
+        };
+        let Ok(block) = signature_block(purpose, data) else {
+            return false;
+        };
+        let mut sig: gnunet_sys::GNUNET_CRYPTO_EddsaSignature = unsafe { std::mem::zeroed() };
+        sig.r.copy_from_slice(&bytes[..32]);
+        sig.s.copy_from_slice(&bytes[32..]);
+        let key = self.to_gnunet().public_key;
+        let ret = unsafe {
+            gnunet_sys::GNUNET_CRYPTO_eddsa_verify_(
+                purpose,
+                block.as_ptr() as *const gnunet_sys::GNUNET_CRYPTO_EccSignaturePurpose,
+                &sig,
+                &key,
+            )
+        };
+        ret == gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK
     }
 
     pub fn to_gnunet(&self) -> gnunet_sys::GNUNET_PeerIdentity {
         let mut peer: gnunet_sys::GNUNET_PeerIdentity = unsafe { std::mem::zeroed() };
-        let cstr = CString::new(self.0.as_str()).unwrap();
-        unsafe {
-            gnunet_sys::GNUNET_CRYPTO_eddsa_public_key_from_string(
-                cstr.as_ptr(),
-                self.0.len(),
-                &mut peer.public_key,
-            );
-        }
+        peer.public_key.q_y = self.0;
         peer
     }
 }
@@ -45,21 +115,52 @@ impl Default for PeerIdentity {
     }
 }
 
+impl std::str::FromStr for PeerIdentity {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_fixed(s, "peer identity").map(Self)
+    }
+}
+
 impl std::fmt::Display for PeerIdentity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        f.write_str(&data_to_string(&self.0))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HashCode(String);
+impl std::fmt::Debug for PeerIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PeerIdentity({})", self)
+    }
+}
+
+impl Serialize for PeerIdentity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PeerIdentity {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A 512-bit `struct GNUNET_HashCode`, serialized as its 103-character
+/// `GNUNET_CRYPTO_hash_to_enc` encoding.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HashCode([u8; HASH_SIZE]);
+
+pub const HASH_SIZE: usize = 64;
 
 impl HashCode {
-    pub fn new(hash: impl Into<String>) -> Self {
-        Self(hash.into())
+    pub fn from_bytes(bytes: [u8; HASH_SIZE]) -> Self {
+        Self(bytes)
     }
 
-    pub fn as_str(&self) -> &str {
+    pub fn as_bytes(&self) -> &[u8; HASH_SIZE] {
         &self.0
     }
 
@@ -72,36 +173,268 @@ impl HashCode {
                 &mut hash,
             );
         }
+        Self::from_gnunet(&hash)
+    }
 
-        let mut encoded: gnunet_sys::GNUNET_CRYPTO_HashAsciiEncoded = unsafe { std::mem::zeroed() };
+    pub fn from_gnunet(hash: &gnunet_sys::GNUNET_HashCode) -> Self {
+        let mut bytes = [0u8; HASH_SIZE];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(hash.bits.iter()) {
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        Self(bytes)
+    }
+
+    pub fn to_gnunet(&self) -> gnunet_sys::GNUNET_HashCode {
+        let mut hash: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
+        for (word, chunk) in hash.bits.iter_mut().zip(self.0.chunks_exact(4)) {
+            *word = u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        hash
+    }
+
+    /// The XOR distance between two hashes.
+    pub fn distance(&self, other: &HashCode) -> HashCode {
+        let mut result: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
         unsafe {
-            gnunet_sys::GNUNET_CRYPTO_hash_to_enc(&hash, &mut encoded);
+            gnunet_sys::GNUNET_CRYPTO_hash_xor(&self.to_gnunet(), &other.to_gnunet(), &mut result);
         }
+        Self::from_gnunet(&result)
+    }
 
-        let s = unsafe {
-            CStr::from_ptr(&encoded.encoding as *const _ as *const libc::c_char)
-                .to_string_lossy()
-                .into_owned()
+    /// Orders `self` and `other` by their XOR distance to `target`; `Less`
+    /// means `self` is closer.
+    pub fn cmp_distance(&self, other: &HashCode, target: &HashCode) -> std::cmp::Ordering {
+        let ret = unsafe {
+            gnunet_sys::GNUNET_CRYPTO_hash_xorcmp(
+                &self.to_gnunet(),
+                &other.to_gnunet(),
+                &target.to_gnunet(),
+            )
         };
-        Self(s)
+        // hash_xorcmp returns -1 when the first hash is closer
+        ret.cmp(&0)
     }
 
-    pub fn from_gnunet(hash: &gnunet_sys::GNUNET_HashCode) -> Self {
-        let mut encoded: gnunet_sys::GNUNET_CRYPTO_HashAsciiEncoded = unsafe { std::mem::zeroed() };
+    /// Bit `bit` counting from the most significant bit of the first byte.
+    /// Panics if `bit` is out of range.
+    pub fn bit_ltr(&self, bit: u32) -> bool {
+        assert!((bit as usize) < HASH_SIZE * 8, "bit index out of range");
+        unsafe { gnunet_sys::GNUNET_CRYPTO_hash_get_bit_ltr(&self.to_gnunet(), bit) == 1 }
+    }
+
+    /// Bit `bit` counting from the least significant bit of the first byte.
+    /// Panics if `bit` is out of range.
+    pub fn bit_rtl(&self, bit: u32) -> bool {
+        assert!((bit as usize) < HASH_SIZE * 8, "bit index out of range");
+        unsafe { gnunet_sys::GNUNET_CRYPTO_hash_get_bit_rtl(&self.to_gnunet(), bit) == 1 }
+    }
+
+    /// Length of the common prefix of both hashes, in bits.
+    pub fn matching_bits(&self, other: &HashCode) -> u32 {
         unsafe {
-            gnunet_sys::GNUNET_CRYPTO_hash_to_enc(hash, &mut encoded);
+            gnunet_sys::GNUNET_CRYPTO_hash_matching_bits(&self.to_gnunet(), &other.to_gnunet())
         }
+    }
+}
 
-        let s = unsafe {
-            CStr::from_ptr(&encoded.encoding as *const _ as *const libc::c_char)
-                .to_string_lossy()
-                .into_owned()
+impl std::str::FromStr for HashCode {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CryptoError::InvalidEncoding("hash code");
+        let cstr = CString::new(s).map_err(|_| invalid())?;
+        let mut hash: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            gnunet_sys::GNUNET_CRYPTO_hash_from_string2(cstr.as_ptr(), s.len(), &mut hash)
         };
-        Self(s)
+        if ret != gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK {
+            return Err(invalid());
+        }
+        Ok(Self::from_gnunet(&hash))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl std::fmt::Display for HashCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hash = self.to_gnunet();
+        let mut encoded: gnunet_sys::GNUNET_CRYPTO_HashAsciiEncoded = unsafe { std::mem::zeroed() };
+        unsafe {
+            gnunet_sys::GNUNET_CRYPTO_hash_to_enc(&hash, &mut encoded);
+        }
+        let s = unsafe { CStr::from_ptr(&encoded.encoding as *const _ as *const libc::c_char) };
+        f.write_str(&s.to_string_lossy())
+    }
+}
+
+impl std::fmt::Debug for HashCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HashCode({})", self)
+    }
+}
+
+impl Serialize for HashCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HashCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Incremental SHA-512 over data that arrives in pieces.
+pub struct HashContext(std::ptr::NonNull<gnunet_sys::GNUNET_HashContext>);
+
+// The context is plain heap state owned by this handle.
+unsafe impl Send for HashContext {}
+
+impl HashContext {
+    pub fn new() -> Self {
+        let ctx = unsafe { gnunet_sys::GNUNET_CRYPTO_hash_context_start() };
+        Self(std::ptr::NonNull::new(ctx).expect("GNUNET_CRYPTO_hash_context_start failed"))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        unsafe {
+            gnunet_sys::GNUNET_CRYPTO_hash_context_read(
+                self.0.as_ptr(),
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+            );
+        }
+    }
+
+    pub fn finish(self) -> HashCode {
+        let mut hash: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
+        let ctx = std::mem::ManuallyDrop::new(self);
+        unsafe {
+            gnunet_sys::GNUNET_CRYPTO_hash_context_finish(ctx.0.as_ptr(), &mut hash);
+        }
+        HashCode::from_gnunet(&hash)
+    }
+}
+
+impl Default for HashContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for HashContext {
+    fn clone(&self) -> Self {
+        let ctx = unsafe { gnunet_sys::GNUNET_CRYPTO_hash_context_copy(self.0.as_ptr()) };
+        Self(std::ptr::NonNull::new(ctx).expect("GNUNET_CRYPTO_hash_context_copy failed"))
+    }
+}
+
+impl Drop for HashContext {
+    fn drop(&mut self) {
+        unsafe {
+            gnunet_sys::GNUNET_CRYPTO_hash_context_abort(self.0.as_ptr());
+        }
+    }
+}
+
+impl std::io::Write for HashContext {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// HMAC-SHA512 of `data` under `key`.
+pub fn hmac(key: &[u8], data: &[u8]) -> HashCode {
+    let mut mac: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
+    unsafe {
+        gnunet_sys::GNUNET_CRYPTO_hmac_raw(
+            key.as_ptr() as *const libc::c_void,
+            key.len(),
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+            &mut mac,
+        );
+    }
+    HashCode::from_gnunet(&mac)
+}
+
+/// Checks `mac` against the HMAC of `data` in constant time.
+pub fn verify_hmac(key: &[u8], data: &[u8], mac: &HashCode) -> bool {
+    let expected = hmac(key, data);
+    expected
+        .as_bytes()
+        .iter()
+        .zip(mac.as_bytes())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Fills `out` with key material derived from `key_material` with
+/// `GNUNET_CRYPTO_kdf` (HKDF with SHA-512 extraction and SHA-256 expansion).
+/// `salt` and `context` separate keys derived from the same secret.
+pub fn kdf(
+    out: &mut [u8],
+    salt: &[u8],
+    key_material: &[u8],
+    context: &[u8],
+) -> Result<(), CryptoError> {
+    let ret = unsafe {
+        gnunet_sys::GNUNET_CRYPTO_kdf(
+            out.as_mut_ptr() as *mut libc::c_void,
+            out.len(),
+            salt.as_ptr() as *const libc::c_void,
+            salt.len(),
+            key_material.as_ptr() as *const libc::c_void,
+            key_material.len(),
+            context.as_ptr() as *const libc::c_void,
+            context.len(),
+            std::ptr::null::<libc::c_void>(),
+        )
+    };
+    if ret != gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK {
+        return Err(CryptoError::KeyDerivation);
+    }
+    Ok(())
+}
+
+/// How much entropy `random_block` draws on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomQuality {
+    /// Fast pseudo-random data, not fit for keys.
+    Weak,
+    /// Suitable for long-term keys.
+    Strong,
+    /// Unpredictable but cheaper than `Strong`; for nonces and IVs.
+    Nonce,
+}
+
+impl RandomQuality {
+    fn to_gnunet(self) -> gnunet_sys::GNUNET_CRYPTO_Quality {
+        match self {
+            Self::Weak => gnunet_sys::GNUNET_CRYPTO_Quality_GNUNET_CRYPTO_QUALITY_WEAK,
+            Self::Strong => gnunet_sys::GNUNET_CRYPTO_Quality_GNUNET_CRYPTO_QUALITY_STRONG,
+            Self::Nonce => gnunet_sys::GNUNET_CRYPTO_Quality_GNUNET_CRYPTO_QUALITY_NONCE,
+        }
+    }
+}
+
+pub fn random_block(quality: RandomQuality, buf: &mut [u8]) {
+    unsafe {
+        gnunet_sys::GNUNET_CRYPTO_random_block(
+            quality.to_gnunet(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PublicKey(String);
 
 impl PublicKey {
@@ -117,10 +450,18 @@ impl PublicKey {
         unsafe {
             let cstr = gnunet_sys::GNUNET_CRYPTO_eddsa_public_key_to_string(key);
             let s = CStr::from_ptr(cstr).to_string_lossy().into_owned();
-            gnunet_sys::GNUNET_xfree_(cstr as *mut libc::c_void, c"crypto.rs".as_ptr(), line!() as libc::c_int);
+            gnunet_sys::GNUNET_xfree_(
+                cstr as *mut libc::c_void,
+                c"crypto.rs".as_ptr(),
+                line!() as libc::c_int,
+            );
             Self(s)
         }
     }
+
+    pub fn to_gnunet_eddsa(&self) -> Option<gnunet_sys::GNUNET_CRYPTO_EddsaPublicKey> {
+        eddsa_key_bytes(&self.0).map(|q_y| gnunet_sys::GNUNET_CRYPTO_EddsaPublicKey { q_y })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,8 +476,43 @@ impl PrivateKey {
         &self.0
     }
 
-    pub fn public_key(&self) -> PublicKey {
-        PublicKey::new(format!("pub:{}", self.0))
+    /// Derives the EdDSA public key; `None` if this is not an encoded EdDSA key.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        let mut key = self.to_gnunet_eddsa()?;
+        let mut public: gnunet_sys::GNUNET_CRYPTO_EddsaPublicKey = unsafe { std::mem::zeroed() };
+        unsafe {
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_get_public(&key, &mut public);
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_clear(&mut key);
+        }
+        Some(PublicKey::from_gnunet_eddsa(&public))
+    }
+
+    pub fn to_gnunet_eddsa(&self) -> Option<gnunet_sys::GNUNET_CRYPTO_EddsaPrivateKey> {
+        let mut bytes = eddsa_key_bytes(&self.0)?;
+        let key = gnunet_sys::GNUNET_CRYPTO_EddsaPrivateKey { d: bytes };
+        bytes.fill(0);
+        Some(key)
+    }
+
+    /// Signs `data` for `purpose` with `GNUNET_CRYPTO_eddsa_sign_`, returning
+    /// the signature in the GNUnet string encoding.
+    pub fn sign(&self, purpose: u32, data: &[u8]) -> Result<String, CryptoError> {
+        let block = signature_block(purpose, data)?;
+        let mut key = self.to_gnunet_eddsa().ok_or(CryptoError::InvalidKey)?;
+        let mut sig: gnunet_sys::GNUNET_CRYPTO_EddsaSignature = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            let ret = gnunet_sys::GNUNET_CRYPTO_eddsa_sign_(
+                &key,
+                block.as_ptr() as *const gnunet_sys::GNUNET_CRYPTO_EccSignaturePurpose,
+                &mut sig,
+            );
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_clear(&mut key);
+            ret
+        };
+        if ret != gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK {
+            return Err(CryptoError::Signing);
+        }
+        Ok(data_to_string(&[sig.r, sig.s].concat()))
     }
 
     pub fn generate_eddsa() -> Self {
@@ -148,9 +524,535 @@ impl PrivateKey {
         unsafe {
             let cstr = gnunet_sys::GNUNET_CRYPTO_eddsa_private_key_to_string(&key);
             let s = CStr::from_ptr(cstr).to_string_lossy().into_owned();
-            gnunet_sys::GNUNET_xfree_(cstr as *mut libc::c_void, c"crypto.rs".as_ptr(), line!() as libc::c_int);
+            gnunet_sys::GNUNET_xfree_(
+                cstr as *mut libc::c_void,
+                c"crypto.rs".as_ptr(),
+                line!() as libc::c_int,
+            );
             gnunet_sys::GNUNET_CRYPTO_eddsa_key_clear(&mut key);
             Self(s)
         }
     }
 }
+
+/// A message encrypted to a single recipient's EdDSA key.
+///
+/// The sender creates an ephemeral ECDHE key and combines it with the
+/// recipient's key (`GNUNET_CRYPTO_ecdh_eddsa`); the recipient reaches the same
+/// secret from its private key (`GNUNET_CRYPTO_eddsa_ecdh`). The first 256
+/// bits of that hash key ChaCha20-Poly1305, with optional associated data.
+/// All fields use the GNUnet string encoding so that clients can relay them
+/// as-is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedMessage {
+    pub ephemeral_key: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+impl SealedMessage {
+    pub fn seal(recipient: &PublicKey, plaintext: &[u8]) -> Result<Self, CryptoError> {
+        Self::seal_with(recipient, plaintext, &[])
+    }
+
+    /// Seals `plaintext`, binding `aad` into the ciphertext; opening then
+    /// needs the same `aad`.
+    pub fn seal_with(
+        recipient: &PublicKey,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<Self, CryptoError> {
+        let recipient = recipient.to_gnunet_eddsa().ok_or(CryptoError::InvalidKey)?;
+
+        let mut ephemeral: gnunet_sys::GNUNET_CRYPTO_EcdhePrivateKey =
+            unsafe { std::mem::zeroed() };
+        let mut ephemeral_public: gnunet_sys::GNUNET_CRYPTO_EcdhePublicKey =
+            unsafe { std::mem::zeroed() };
+        let mut shared: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            gnunet_sys::GNUNET_CRYPTO_ecdhe_key_create(&mut ephemeral);
+            gnunet_sys::GNUNET_CRYPTO_ecdhe_key_get_public(&ephemeral, &mut ephemeral_public);
+            let ret = gnunet_sys::GNUNET_CRYPTO_ecdh_eddsa(&ephemeral, &recipient, &mut shared);
+            gnunet_sys::GNUNET_CRYPTO_ecdhe_key_clear(&mut ephemeral);
+            ret
+        };
+        if ret != gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK {
+            return Err(CryptoError::KeyExchange);
+        }
+
+        let cipher = aead_cipher(&mut shared);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| CryptoError::Encryption)?;
+
+        Ok(Self {
+            ephemeral_key: data_to_string(&ephemeral_public.q_y),
+            nonce: data_to_string(&nonce),
+            ciphertext: data_to_string(&ciphertext),
+        })
+    }
+
+    pub fn open(&self, key: &PrivateKey) -> Result<Vec<u8>, CryptoError> {
+        self.open_with(key, &[])
+    }
+
+    pub fn open_with(&self, key: &PrivateKey, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (ephemeral_public, nonce, ciphertext) = self.decode()?;
+        let mut private = key.to_gnunet_eddsa().ok_or(CryptoError::InvalidKey)?;
+
+        let mut shared: gnunet_sys::GNUNET_HashCode = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            let ret =
+                gnunet_sys::GNUNET_CRYPTO_eddsa_ecdh(&private, &ephemeral_public, &mut shared);
+            gnunet_sys::GNUNET_CRYPTO_eddsa_key_clear(&mut private);
+            ret
+        };
+        if ret != gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK {
+            return Err(CryptoError::KeyExchange);
+        }
+
+        aead_cipher(&mut shared)
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| CryptoError::Decryption)
+    }
+
+    /// Checks that the fields are well-formed without being able to decrypt,
+    /// which is all a relaying server can do.
+    pub fn validate(&self) -> Result<(), CryptoError> {
+        self.decode().map(|_| ())
+    }
+
+    fn decode(
+        &self,
+    ) -> Result<(gnunet_sys::GNUNET_CRYPTO_EcdhePublicKey, Vec<u8>, Vec<u8>), CryptoError> {
+        let mut ephemeral_public: gnunet_sys::GNUNET_CRYPTO_EcdhePublicKey =
+            unsafe { std::mem::zeroed() };
+        let key = string_to_data(&self.ephemeral_key)
+            .filter(|k| k.len() == ephemeral_public.q_y.len())
+            .ok_or(CryptoError::InvalidEncoding("ephemeral key"))?;
+        ephemeral_public.q_y.copy_from_slice(&key);
+
+        let nonce = string_to_data(&self.nonce)
+            .filter(|n| n.len() == NONCE_SIZE)
+            .ok_or(CryptoError::InvalidEncoding("nonce"))?;
+        let ciphertext = string_to_data(&self.ciphertext)
+            .filter(|c| c.len() >= TAG_SIZE)
+            .ok_or(CryptoError::InvalidEncoding("ciphertext"))?;
+        Ok((ephemeral_public, nonce, ciphertext))
+    }
+}
+
+/// A direct message sealed to both of its peers and signed by the sender.
+///
+/// Both copies bind the sender's and recipient's peer ids as associated data,
+/// and the signature covers both copies, so a relaying server can neither
+/// read the message nor pass it off as coming from or going to someone else.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedEnvelope {
+    pub recipient_copy: SealedMessage,
+    /// Sealed to the sender, so their clients can show what they sent.
+    pub sender_copy: SealedMessage,
+    /// The sender's signature over [`SealedEnvelope::signed_data`].
+    pub signature: String,
+}
+
+impl SealedEnvelope {
+    pub fn seal(
+        sender: &PrivateKey,
+        recipient: &PeerIdentity,
+        plaintext: &[u8],
+    ) -> Result<Self, CryptoError> {
+        let sender_key = sender.public_key().ok_or(CryptoError::InvalidKey)?;
+        let sender_id = PeerIdentity::from_public_key(&sender_key)?;
+        let aad = Self::associated_data(&sender_id, recipient);
+
+        let mut envelope = Self {
+            recipient_copy: SealedMessage::seal_with(&recipient.public_key(), plaintext, &aad)?,
+            sender_copy: SealedMessage::seal_with(&sender_key, plaintext, &aad)?,
+            signature: String::new(),
+        };
+        envelope.signature = sender.sign(
+            SIGNATURE_PURPOSE_PRIVATE_MESSAGE,
+            &envelope.signed_data(&sender_id, recipient),
+        )?;
+        Ok(envelope)
+    }
+
+    /// Opens the copy meant for the holder of `key`, after checking the
+    /// signature.
+    pub fn open(
+        &self,
+        key: &PrivateKey,
+        sender: &PeerIdentity,
+        recipient: &PeerIdentity,
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.verify(sender, recipient)?;
+        let own = PeerIdentity::from_public_key(&key.public_key().ok_or(CryptoError::InvalidKey)?)?;
+        let copy = if own == *recipient {
+            &self.recipient_copy
+        } else if own == *sender {
+            &self.sender_copy
+        } else {
+            return Err(CryptoError::InvalidKey);
+        };
+        copy.open_with(key, &Self::associated_data(sender, recipient))
+    }
+
+    /// Checks that both copies are well-formed and that `sender` signed
+    /// them for `recipient`; all a relaying server can do.
+    pub fn verify(
+        &self,
+        sender: &PeerIdentity,
+        recipient: &PeerIdentity,
+    ) -> Result<(), CryptoError> {
+        self.recipient_copy.validate()?;
+        self.sender_copy.validate()?;
+        if !sender.verify(
+            SIGNATURE_PURPOSE_PRIVATE_MESSAGE,
+            &self.signed_data(sender, recipient),
+            &self.signature,
+        ) {
+            return Err(CryptoError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    /// The sender's and then the recipient's peer id.
+    fn associated_data(sender: &PeerIdentity, recipient: &PeerIdentity) -> Vec<u8> {
+        [sender.as_bytes().as_slice(), recipient.as_bytes()].concat()
+    }
+
+    /// The associated data, then the fields of the recipient's and the
+    /// sender's copy as encoded, joined by `.`.
+    pub fn signed_data(&self, sender: &PeerIdentity, recipient: &PeerIdentity) -> Vec<u8> {
+        let copies = [&self.recipient_copy, &self.sender_copy]
+            .iter()
+            .flat_map(|c| [c.ephemeral_key.as_str(), &c.nonce, &c.ciphertext])
+            .collect::<Vec<_>>()
+            .join(".");
+        let mut data = Self::associated_data(sender, recipient);
+        data.extend_from_slice(copies.as_bytes());
+        data
+    }
+}
+
+/// Symmetric key shared by the members of an encrypted room for one key epoch.
+///
+/// Servers never see it: whichever member performs a rotation generates a new
+/// key and seals a copy to each member with [`SealedMessage`]. The epoch is
+/// bound into every ciphertext as associated data.
+pub struct GroupKey([u8; 32]);
+
+impl GroupKey {
+    pub fn generate() -> Self {
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        Self(key.into())
+    }
+
+    pub fn seal_for(&self, member: &PublicKey) -> Result<SealedMessage, CryptoError> {
+        SealedMessage::seal(member, &self.0)
+    }
+
+    pub fn open(sealed: &SealedMessage, key: &PrivateKey) -> Result<Self, CryptoError> {
+        let mut bytes = sealed.open(key)?;
+        let result = <[u8; 32]>::try_from(bytes.as_slice())
+            .map(Self)
+            .map_err(|_| CryptoError::InvalidKey);
+        bytes.fill(0);
+        result
+    }
+
+    pub fn encrypt(&self, epoch: u64, plaintext: &[u8]) -> Result<GroupCiphertext, CryptoError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &epoch.to_be_bytes(),
+                },
+            )
+            .map_err(|_| CryptoError::Encryption)?;
+        Ok(GroupCiphertext {
+            epoch,
+            nonce: data_to_string(&nonce),
+            ciphertext: data_to_string(&ciphertext),
+        })
+    }
+
+    pub fn decrypt(&self, message: &GroupCiphertext) -> Result<Vec<u8>, CryptoError> {
+        let (nonce, ciphertext) = message.decode()?;
+        self.cipher()
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &message.epoch.to_be_bytes(),
+                },
+            )
+            .map_err(|_| CryptoError::Decryption)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+impl Drop for GroupKey {
+    fn drop(&mut self) {
+        self.0.fill(0);
+    }
+}
+
+/// A room message encrypted under the group key of `epoch`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupCiphertext {
+    pub epoch: u64,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+impl GroupCiphertext {
+    pub fn validate(&self) -> Result<(), CryptoError> {
+        self.decode().map(|_| ())
+    }
+
+    fn decode(&self) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        let nonce = string_to_data(&self.nonce)
+            .filter(|n| n.len() == NONCE_SIZE)
+            .ok_or(CryptoError::InvalidEncoding("nonce"))?;
+        let ciphertext = string_to_data(&self.ciphertext)
+            .filter(|c| c.len() >= TAG_SIZE)
+            .ok_or(CryptoError::InvalidEncoding("ciphertext"))?;
+        Ok((nonce, ciphertext))
+    }
+}
+
+/// The block `GNUNET_CRYPTO_eddsa_sign_` signs: its total size and `purpose`,
+/// both big-endian, followed by `data`.
+fn signature_block(purpose: u32, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let size = u32::try_from(data.len() + 8).map_err(|_| CryptoError::Signing)?;
+    let mut block = Vec::with_capacity(size as usize);
+    block.extend_from_slice(&size.to_be_bytes());
+    block.extend_from_slice(&purpose.to_be_bytes());
+    block.extend_from_slice(data);
+    Ok(block)
+}
+
+/// Keys the AEAD from the ECDH hash and wipes the hash.
+fn aead_cipher(shared: &mut gnunet_sys::GNUNET_HashCode) -> ChaCha20Poly1305 {
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(
+            shared as *mut gnunet_sys::GNUNET_HashCode as *mut u8,
+            std::mem::size_of::<gnunet_sys::GNUNET_HashCode>(),
+        )
+    };
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&bytes[..32]));
+    bytes.fill(0);
+    cipher
+}
+
+/// Encodes binary data with `GNUNET_STRINGS_data_to_string`.
+pub(crate) fn data_to_string(data: &[u8]) -> String {
+    unsafe {
+        let cstr = gnunet_sys::GNUNET_STRINGS_data_to_string_alloc(
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+        );
+        // Allocation failures abort, so this only happens for empty input.
+        if cstr.is_null() {
+            return String::new();
+        }
+        let s = CStr::from_ptr(cstr).to_string_lossy().into_owned();
+        gnunet_sys::GNUNET_xfree_(
+            cstr as *mut libc::c_void,
+            c"crypto.rs".as_ptr(),
+            line!() as libc::c_int,
+        );
+        s
+    }
+}
+
+/// Decodes the GNUnet string encoding of exactly `N` bytes.
+fn decode_fixed<const N: usize>(encoded: &str, what: &'static str) -> Result<[u8; N], CryptoError> {
+    let mut data = [0u8; N];
+    let cstr = CString::new(encoded).map_err(|_| CryptoError::InvalidEncoding(what))?;
+    let ret = unsafe {
+        gnunet_sys::GNUNET_STRINGS_string_to_data(
+            cstr.as_ptr(),
+            encoded.len(),
+            data.as_mut_ptr() as *mut libc::c_void,
+            N,
+        )
+    };
+    if ret != gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK {
+        return Err(CryptoError::InvalidEncoding(what));
+    }
+    Ok(data)
+}
+
+/// Decodes an EdDSA key, plain or in the typed form the identity daemon
+/// prints, where a 4-byte key type precedes the key.
+fn eddsa_key_bytes(encoded: &str) -> Option<[u8; 32]> {
+    let mut data = string_to_data(encoded)?;
+    let eddsa = gnunet_sys::GNUNET_CRYPTO_KeyType_GNUNET_PUBLIC_KEY_TYPE_EDDSA.to_be_bytes();
+    let key = match data.len() {
+        32 => <[u8; 32]>::try_from(data.as_slice()).ok(),
+        36 if data[..4] == eddsa => <[u8; 32]>::try_from(&data[4..]).ok(),
+        _ => None,
+    };
+    data.fill(0);
+    key
+}
+
+/// Decodes data encoded with [`data_to_string`]; the length follows from the
+/// five bits each character carries.
+pub(crate) fn string_to_data(encoded: &str) -> Option<Vec<u8>> {
+    let mut data = vec![0u8; encoded.len() * 5 / 8];
+    let cstr = CString::new(encoded).ok()?;
+    let ret = unsafe {
+        gnunet_sys::GNUNET_STRINGS_string_to_data(
+            cstr.as_ptr(),
+            encoded.len(),
+            data.as_mut_ptr() as *mut libc::c_void,
+            data.len(),
+        )
+    };
+    (ret == gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK).then_some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_with_first_byte(byte: u8) -> HashCode {
+        let mut bytes = [0u8; HASH_SIZE];
+        bytes[0] = byte;
+        HashCode::from_bytes(bytes)
+    }
+
+    #[test]
+    fn hash_code_round_trips_through_its_encoding() {
+        let hash = HashCode::generate(b"hello");
+        assert_eq!(hash.to_string().parse::<HashCode>().unwrap(), hash);
+        assert_eq!(HashCode::from_gnunet(&hash.to_gnunet()), hash);
+        assert!("not a hash".parse::<HashCode>().is_err());
+        assert!("".parse::<HashCode>().is_err());
+    }
+
+    #[test]
+    fn hash_context_matches_one_shot_hash() {
+        let mut ctx = HashContext::new();
+        ctx.update(b"hel");
+        let copy = ctx.clone();
+        ctx.update(b"lo");
+        assert_eq!(ctx.finish(), HashCode::generate(b"hello"));
+        assert_eq!(copy.finish(), HashCode::generate(b"hel"));
+    }
+
+    #[test]
+    fn hmac_verifies_only_matching_key_and_data() {
+        let mac = hmac(b"key", b"data");
+        assert_eq!(mac, hmac(b"key", b"data"));
+        assert!(verify_hmac(b"key", b"data", &mac));
+        assert!(!verify_hmac(b"key", b"datA", &mac));
+        assert!(!verify_hmac(b"other", b"data", &mac));
+    }
+
+    #[test]
+    fn kdf_is_deterministic_and_separated_by_context() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        let mut c = [0u8; 32];
+        kdf(&mut a, b"salt", b"secret", b"one").unwrap();
+        kdf(&mut b, b"salt", b"secret", b"one").unwrap();
+        kdf(&mut c, b"salt", b"secret", b"two").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, [0u8; 32]);
+    }
+
+    #[test]
+    fn xor_distance_orders_by_closeness() {
+        let target = hash_with_first_byte(0);
+        let near = hash_with_first_byte(0x01);
+        let far = hash_with_first_byte(0x80);
+        assert_eq!(near.distance(&near), target);
+        assert_eq!(near.distance(&far), far.distance(&near));
+        assert_eq!(near.cmp_distance(&far, &target), std::cmp::Ordering::Less);
+        assert_eq!(
+            far.cmp_distance(&near, &target),
+            std::cmp::Ordering::Greater
+        );
+        assert_eq!(near.cmp_distance(&near, &target), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn bits_count_from_either_end_of_the_first_byte() {
+        let hash = hash_with_first_byte(0x80);
+        assert!(hash.bit_ltr(0));
+        assert!(!hash.bit_ltr(7));
+        assert!(hash.bit_rtl(7));
+        assert!(!hash.bit_rtl(0));
+        assert_eq!(hash.matching_bits(&hash), (HASH_SIZE * 8) as u32);
+        assert_eq!(hash.matching_bits(&hash_with_first_byte(0xc0)), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "bit index out of range")]
+    fn bit_out_of_range_panics() {
+        hash_with_first_byte(0).bit_ltr((HASH_SIZE * 8) as u32);
+    }
+
+    #[test]
+    fn random_blocks_differ() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        random_block(RandomQuality::Nonce, &mut a);
+        random_block(RandomQuality::Nonce, &mut b);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn eddsa_keys_decode_plain_or_typed() {
+        let key = [7u8; 32];
+        let mut typed = gnunet_sys::GNUNET_CRYPTO_KeyType_GNUNET_PUBLIC_KEY_TYPE_EDDSA
+            .to_be_bytes()
+            .to_vec();
+        typed.extend_from_slice(&key);
+        assert_eq!(eddsa_key_bytes(&data_to_string(&key)), Some(key));
+        assert_eq!(eddsa_key_bytes(&data_to_string(&typed)), Some(key));
+
+        typed[0] ^= 0xff;
+        assert_eq!(eddsa_key_bytes(&data_to_string(&typed)), None);
+        assert_eq!(eddsa_key_bytes(&data_to_string(&key[..31])), None);
+    }
+
+    #[test]
+    fn signatures_verify_only_for_their_purpose_and_data() {
+        let key = PrivateKey::generate_eddsa();
+        let peer = PeerIdentity::from_public_key(&key.public_key().unwrap()).unwrap();
+        let purpose = SIGNATURE_PURPOSE_PRIVATE_MESSAGE;
+        let signature = key.sign(purpose, b"data").unwrap();
+
+        assert!(peer.verify(purpose, b"data", &signature));
+        assert!(!peer.verify(purpose, b"datA", &signature));
+        assert!(!peer.verify(purpose + 1, b"data", &signature));
+        assert!(!peer.verify(purpose, b"data", "not a signature"));
+        assert!(!PeerIdentity::generate().verify(purpose, b"data", &signature));
+    }
+}
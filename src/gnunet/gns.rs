@@ -1,17 +1,23 @@
-use crate::gnunet::{PeerIdentity, PublicKey};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GnsRecord {
-    pub record_type: String,
-    pub data: String,
-    pub expiration: u64,
-    pub flags: u32,
+use crate::gnunet::{
+    APEX_LABEL, GnsRecord, NamestoreService, PeerIdentity, PublicKey, RecordData, RecordType,
+};
+use crate::social::User;
+
+/// Top-level domain that roots resolution in the local zone.
+pub const GNS_TLD: &str = "gnu";
+
+#[derive(Debug, thiserror::Error)]
+pub enum GnsError {
+    #[error("no local zone configured")]
+    NoLocalZone,
+    #[error("label `{0}` is already delegated to another zone")]
+    LabelTaken(String),
 }
 
 pub struct GnsService {
     zone_cache: std::collections::HashMap<String, Vec<GnsRecord>>,
     local_zone: Option<PublicKey>,
+    namestore: NamestoreService,
 }
 
 impl Default for GnsService {
@@ -25,6 +31,7 @@ impl GnsService {
         Self {
             zone_cache: std::collections::HashMap::new(),
             local_zone: None,
+            namestore: NamestoreService::new(),
         }
     }
 
@@ -32,65 +39,201 @@ impl GnsService {
         self.local_zone = Some(zone);
     }
 
+    pub fn local_zone(&self) -> Option<&PublicKey> {
+        self.local_zone.as_ref()
+    }
+
+    pub fn namestore(&self) -> &NamestoreService {
+        &self.namestore
+    }
+
+    pub fn namestore_mut(&mut self) -> &mut NamestoreService {
+        &mut self.namestore
+    }
+
     pub fn lookup(
         &self,
         name: &str,
         zone: &PublicKey,
-        record_type: &str,
+        record_type: RecordType,
     ) -> Option<Vec<GnsRecord>> {
-        let key = format!("{}:{}:{}", zone.as_str(), name, record_type);
+        let key = format!("{}:{}:{}", zone.as_str(), name, record_type.as_str());
         self.zone_cache.get(&key).cloned()
     }
 
-    pub fn lookup_in_local_zone(&self, name: &str, record_type: &str) -> Option<Vec<GnsRecord>> {
+    pub fn lookup_in_local_zone(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Option<Vec<GnsRecord>> {
         let zone = self.local_zone.as_ref()?;
         self.lookup(name, zone, record_type)
     }
 
     pub fn store_record(&mut self, name: &str, zone: &PublicKey, record: GnsRecord) {
-        let key = format!("{}:{}:{}", zone.as_str(), name, record.record_type);
+        let key = format!(
+            "{}:{}:{}",
+            zone.as_str(),
+            name,
+            record.record_type().as_str()
+        );
         self.zone_cache.entry(key).or_default().push(record);
     }
 
-    pub fn create_identity_record(&self, peer: &PeerIdentity, username: &str) -> GnsRecord {
-        GnsRecord {
-            record_type: "IDENTITY".to_string(),
-            data: format!("{}:{}", peer.as_str(), username),
-            expiration: u64::MAX,
-            flags: 0,
+    /// Resolves a dotted name such as `alice.gnu` by walking delegations from
+    /// the local zone, right to left. A name ending in a delegation resolves to
+    /// the apex records of the delegated zone.
+    pub fn resolve(&self, name: &str) -> Option<Vec<GnsRecord>> {
+        let zone = self.local_zone.clone()?;
+        self.resolve_in_zone(&zone, name)
+    }
+
+    /// Resolves `name` starting from `zone` instead of the local zone, so
+    /// that `.gnu` names are interpreted relative to that zone's petnames.
+    pub fn resolve_in_zone(&self, zone: &PublicKey, name: &str) -> Option<Vec<GnsRecord>> {
+        let mut zone = zone.clone();
+        let mut labels: Vec<&str> = name.split('.').filter(|l| !l.is_empty()).collect();
+        if labels.last() == Some(&GNS_TLD) {
+            labels.pop();
+        }
+        if labels.is_empty() {
+            labels.push(APEX_LABEL);
+        }
+
+        while let Some(label) = labels.pop() {
+            let records = self.live_records(&zone, label)?;
+            match delegation_target(&records) {
+                Some(delegated) => zone = delegated,
+                None if labels.is_empty() => return Some(records),
+                None => return None,
+            }
         }
+
+        self.live_records(&zone, APEX_LABEL)
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum RecordType {
-    A,
-    AAAA,
-    CNAME,
-    NS,
-    PKEY,
-    EDKEY,
-    GNS2DNS,
-    IDENTITY,
-    SOCIAL,
-    TEXT,
-    BOX,
-}
+    /// Resolves `name` to the peer named by its `SOCIAL` record, trying the
+    /// petnames in `zone` first and then the usernames in the local zone.
+    pub fn resolve_peer(&self, zone: Option<&PublicKey>, name: &str) -> Option<PeerIdentity> {
+        let records = zone
+            .and_then(|zone| self.resolve_in_zone(zone, name))
+            .or_else(|| self.resolve(name))?;
+        records.into_iter().find_map(|r| match r.data {
+            RecordData::Social(peer) => Some(peer),
+            _ => None,
+        })
+    }
+
+    fn live_records(&self, zone: &PublicKey, label: &str) -> Option<Vec<GnsRecord>> {
+        let mut records = self.namestore.lookup_records(zone, label)?;
+        records.retain(|r| !r.is_expired());
+        (!records.is_empty()).then_some(records)
+    }
+
+    /// Returns the zone a label in the local zone delegates to, if any.
+    pub fn delegated_zone(&self, label: &str) -> Option<PublicKey> {
+        let zone = self.local_zone.as_ref()?;
+        let records = self.namestore.lookup_records(zone, label)?;
+        delegation_target(&records)
+    }
+
+    /// Delegates `petname` in `zone` to `target`, so that the owner of
+    /// `zone` can address the target as `petname.gnu`.
+    pub fn set_petname(&mut self, zone: &PublicKey, petname: &str, target: &PublicKey) {
+        let record = self.create_delegation_record(target);
+        self.namestore.store_records(zone, petname, vec![record]);
+    }
+
+    pub fn remove_petname(&mut self, zone: &PublicKey, petname: &str) -> bool {
+        let is_petname = self
+            .namestore
+            .lookup_records(zone, petname)
+            .is_some_and(|records| delegation_target(&records).is_some());
+        is_petname && self.namestore.remove_records(zone, petname)
+    }
+
+    /// Lists the delegations in `zone`, sorted by petname.
+    pub fn petnames(&self, zone: &PublicKey) -> Vec<(String, PublicKey)> {
+        let mut petnames: Vec<(String, PublicKey)> = self
+            .namestore
+            .zone_labels(zone)
+            .into_iter()
+            .filter(|label| label != APEX_LABEL)
+            .filter_map(|label| {
+                let records = self.namestore.lookup_records(zone, &label)?;
+                delegation_target(&records).map(|target| (label, target))
+            })
+            .collect();
+        petnames.sort_by(|a, b| a.0.cmp(&b.0));
+        petnames
+    }
 
-impl RecordType {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Self::A => "A",
-            Self::AAAA => "AAAA",
-            Self::CNAME => "CNAME",
-            Self::NS => "NS",
-            Self::PKEY => "PKEY",
-            Self::EDKEY => "EDKEY",
-            Self::GNS2DNS => "GNS2DNS",
-            Self::IDENTITY => "IDENTITY",
-            Self::SOCIAL => "SOCIAL",
-            Self::TEXT => "TEXT",
-            Self::BOX => "BOX",
+    /// Writes the user's profile into their zone and delegates `username` in
+    /// the local zone to it, so that `username.gnu` resolves to the profile.
+    pub fn publish_profile(&mut self, user: &User) -> Result<(), GnsError> {
+        let local_zone = self.local_zone.clone().ok_or(GnsError::NoLocalZone)?;
+        let user_zone = PublicKey::new(user.gns_zone.clone());
+
+        if let Some(existing) = self.delegated_zone(&user.username)
+            && existing.as_str() != user_zone.as_str()
+        {
+            return Err(GnsError::LabelTaken(user.username.clone()));
+        }
+
+        let mut apex = vec![
+            self.create_identity_record(&user.id, &user.username),
+            self.create_social_record(&user.id),
+        ];
+        if let Some(bio) = &user.bio {
+            apex.push(self.create_text_record(bio));
         }
+
+        self.namestore.store_records(&user_zone, APEX_LABEL, apex);
+        self.namestore.store_records(
+            &local_zone,
+            &user.username,
+            vec![self.create_delegation_record(&user_zone)],
+        );
+        Ok(())
     }
+
+    /// Removes the delegation and profile records published for `user`.
+    pub fn unpublish_profile(&mut self, user: &User) {
+        let user_zone = PublicKey::new(user.gns_zone.clone());
+        if let Some(local_zone) = self.local_zone.clone()
+            && self
+                .delegated_zone(&user.username)
+                .is_some_and(|z| z.as_str() == user_zone.as_str())
+        {
+            self.namestore.remove_records(&local_zone, &user.username);
+        }
+        self.namestore.remove_records(&user_zone, APEX_LABEL);
+    }
+
+    pub fn create_identity_record(&self, peer: &PeerIdentity, username: &str) -> GnsRecord {
+        GnsRecord::new(RecordData::Identity {
+            peer: peer.clone(),
+            username: username.to_string(),
+        })
+    }
+
+    /// Points at the peer whose social profile lives in this zone.
+    pub fn create_social_record(&self, peer: &PeerIdentity) -> GnsRecord {
+        GnsRecord::new(RecordData::Social(peer.clone()))
+    }
+
+    pub fn create_text_record(&self, text: &str) -> GnsRecord {
+        GnsRecord::new(RecordData::Text(text.to_string()))
+    }
+
+    pub fn create_delegation_record(&self, zone: &PublicKey) -> GnsRecord {
+        GnsRecord::new(RecordData::Pkey(zone.clone()))
+    }
+}
+
+fn delegation_target(records: &[GnsRecord]) -> Option<PublicKey> {
+    records.iter().find_map(|r| match &r.data {
+        RecordData::Pkey(zone) | RecordData::Edkey(zone) => Some(zone.clone()),
+        _ => None,
+    })
 }
@@ -0,0 +1,589 @@
+use crate::gnunet::crypto::data_to_string;
+use crate::gnunet::{PEER_IDENTITY_SIZE, PeerIdentity, PublicKey};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+/// `GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION`; derived from [`Expiration`]
+/// rather than exposed in [`RecordFlags`].
+const RELATIVE_EXPIRATION_FLAG: u16 = 1 << 14;
+
+/// Size of the `NetworkRecord` header preceding each record's data.
+const NETWORK_RECORD_HEADER: usize = 16;
+
+const MAX_DNS_LABEL: usize = 63;
+const MAX_DNS_NAME: usize = 253;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GnsRecordError {
+    #[error("unknown record type {0}")]
+    UnknownType(u32),
+    #[error("record data truncated")]
+    Truncated,
+    #[error("record data too large ({0} bytes)")]
+    TooLarge(usize),
+    #[error("invalid {0} record data")]
+    InvalidData(&'static str),
+    #[error("invalid zone key `{0}`")]
+    InvalidZoneKey(String),
+    #[error("invalid DNS name `{0}`")]
+    InvalidName(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RecordType {
+    A,
+    AAAA,
+    CNAME,
+    NS,
+    PKEY,
+    EDKEY,
+    GNS2DNS,
+    IDENTITY,
+    SOCIAL,
+    TEXT,
+    BOX,
+}
+
+impl RecordType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::A => "A",
+            Self::AAAA => "AAAA",
+            Self::CNAME => "CNAME",
+            Self::NS => "NS",
+            Self::PKEY => "PKEY",
+            Self::EDKEY => "EDKEY",
+            Self::GNS2DNS => "GNS2DNS",
+            Self::IDENTITY => "IDENTITY",
+            Self::SOCIAL => "SOCIAL",
+            Self::TEXT => "TEXT",
+            Self::BOX => "BOX",
+        }
+    }
+
+    /// Numeric type as registered with GANA. `TEXT` shares the DNS `TXT`
+    /// number; `IDENTITY` and `SOCIAL` use the DNS private-use range.
+    pub fn number(&self) -> u32 {
+        match self {
+            Self::A => 1,
+            Self::NS => 2,
+            Self::CNAME => 5,
+            Self::TEXT => 16,
+            Self::AAAA => 28,
+            Self::PKEY => 65536,
+            Self::GNS2DNS => 65540,
+            Self::BOX => 65541,
+            Self::EDKEY => 65556,
+            Self::IDENTITY => 65280,
+            Self::SOCIAL => 65281,
+        }
+    }
+
+    pub fn from_number(number: u32) -> Option<Self> {
+        let record_type = match number {
+            1 => Self::A,
+            2 => Self::NS,
+            5 => Self::CNAME,
+            16 => Self::TEXT,
+            28 => Self::AAAA,
+            65536 => Self::PKEY,
+            65540 => Self::GNS2DNS,
+            65541 => Self::BOX,
+            65556 => Self::EDKEY,
+            65280 => Self::IDENTITY,
+            65281 => Self::SOCIAL,
+            _ => return None,
+        };
+        Some(record_type)
+    }
+
+    pub fn is_delegation(&self) -> bool {
+        matches!(self, Self::PKEY | Self::EDKEY)
+    }
+}
+
+bitflags::bitflags! {
+    /// `GNUNET_GNSRECORD_Flags`, numbered as in LSD0001.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+    pub struct RecordFlags: u16 {
+        const CRITICAL = 1;
+        const SHADOW = 1 << 1;
+        const SUPPLEMENTAL = 1 << 2;
+        const PRIVATE = 1 << 15;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Expiration {
+    Never,
+    Absolute(DateTime<Utc>),
+    /// Lifetime counted from the moment the record is published.
+    Relative(Duration),
+}
+
+impl Expiration {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match self {
+            Self::Absolute(at) => *at <= now,
+            Self::Never | Self::Relative(_) => false,
+        }
+    }
+
+    /// Returns the wire value in microseconds and whether it is relative.
+    fn to_wire(self) -> (u64, bool) {
+        match self {
+            Self::Never => (u64::MAX, false),
+            Self::Absolute(at) => (at.timestamp_micros().max(0) as u64, false),
+            Self::Relative(d) => (d.as_micros().min(u64::MAX as u128) as u64, true),
+        }
+    }
+
+    fn from_wire(micros: u64, relative: bool) -> Self {
+        if relative {
+            return Self::Relative(Duration::from_micros(micros));
+        }
+        if micros == u64::MAX {
+            return Self::Never;
+        }
+        i64::try_from(micros)
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .map(Self::Absolute)
+            .unwrap_or(Self::Never)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Pkey(PublicKey),
+    Edkey(PublicKey),
+    Gns2Dns {
+        name: String,
+        server: String,
+    },
+    /// `GNUNET_GNSRECORD_BoxRecord`: a record scoped to a service and protocol,
+    /// carrying the embedded record in its wire encoding.
+    Box {
+        protocol: u16,
+        service: u16,
+        record_type: u32,
+        data: Vec<u8>,
+    },
+    Text(String),
+    Identity {
+        peer: PeerIdentity,
+        username: String,
+    },
+    Social(PeerIdentity),
+}
+
+impl RecordData {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            Self::A(_) => RecordType::A,
+            Self::Aaaa(_) => RecordType::AAAA,
+            Self::Cname(_) => RecordType::CNAME,
+            Self::Ns(_) => RecordType::NS,
+            Self::Pkey(_) => RecordType::PKEY,
+            Self::Edkey(_) => RecordType::EDKEY,
+            Self::Gns2Dns { .. } => RecordType::GNS2DNS,
+            Self::Box { .. } => RecordType::BOX,
+            Self::Text(_) => RecordType::TEXT,
+            Self::Identity { .. } => RecordType::IDENTITY,
+            Self::Social(_) => RecordType::SOCIAL,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GnsRecordError> {
+        let mut out = Vec::new();
+        match self {
+            Self::A(addr) => out.extend_from_slice(&addr.octets()),
+            Self::Aaaa(addr) => out.extend_from_slice(&addr.octets()),
+            Self::Cname(name) | Self::Ns(name) => write_dns_name(&mut out, name)?,
+            Self::Pkey(key) | Self::Edkey(key) => out.extend_from_slice(&zone_key_bytes(key)?),
+            Self::Gns2Dns { name, server } => {
+                write_dns_name(&mut out, name)?;
+                write_dns_name(&mut out, server)?;
+            }
+            Self::Box {
+                protocol,
+                service,
+                record_type,
+                data,
+            } => {
+                out.extend_from_slice(&protocol.to_be_bytes());
+                out.extend_from_slice(&service.to_be_bytes());
+                out.extend_from_slice(&record_type.to_be_bytes());
+                out.extend_from_slice(data);
+            }
+            Self::Text(text) => out.extend_from_slice(text.as_bytes()),
+            Self::Identity { peer, username } => {
+                out.extend_from_slice(peer.as_bytes());
+                out.extend_from_slice(username.as_bytes());
+            }
+            Self::Social(peer) => out.extend_from_slice(peer.as_bytes()),
+        }
+        Ok(out)
+    }
+
+    pub fn from_bytes(record_type: RecordType, data: &[u8]) -> Result<Self, GnsRecordError> {
+        let record = match record_type {
+            RecordType::A => {
+                let octets: [u8; 4] = data
+                    .try_into()
+                    .map_err(|_| GnsRecordError::InvalidData("A"))?;
+                Self::A(Ipv4Addr::from(octets))
+            }
+            RecordType::AAAA => {
+                let octets: [u8; 16] = data
+                    .try_into()
+                    .map_err(|_| GnsRecordError::InvalidData("AAAA"))?;
+                Self::Aaaa(Ipv6Addr::from(octets))
+            }
+            RecordType::CNAME | RecordType::NS => {
+                let (name, rest) = read_dns_name(data)?;
+                if !rest.is_empty() {
+                    return Err(GnsRecordError::InvalidData(record_type.as_str()));
+                }
+                if record_type == RecordType::CNAME {
+                    Self::Cname(name)
+                } else {
+                    Self::Ns(name)
+                }
+            }
+            RecordType::PKEY => Self::Pkey(zone_key_from_bytes(data)?),
+            RecordType::EDKEY => Self::Edkey(zone_key_from_bytes(data)?),
+            RecordType::GNS2DNS => {
+                let (name, rest) = read_dns_name(data)?;
+                let (server, rest) = read_dns_name(rest)?;
+                if !rest.is_empty() {
+                    return Err(GnsRecordError::InvalidData("GNS2DNS"));
+                }
+                Self::Gns2Dns { name, server }
+            }
+            RecordType::BOX => {
+                if data.len() < 8 {
+                    return Err(GnsRecordError::Truncated);
+                }
+                Self::Box {
+                    protocol: u16::from_be_bytes([data[0], data[1]]),
+                    service: u16::from_be_bytes([data[2], data[3]]),
+                    record_type: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+                    data: data[8..].to_vec(),
+                }
+            }
+            RecordType::TEXT => Self::Text(utf8(data, "TEXT")?),
+            RecordType::IDENTITY => {
+                if data.len() < PEER_IDENTITY_SIZE {
+                    return Err(GnsRecordError::Truncated);
+                }
+                let (peer, username) = data.split_at(PEER_IDENTITY_SIZE);
+                Self::Identity {
+                    peer: PeerIdentity::from_bytes(peer.try_into().expect("split at peer size")),
+                    username: utf8(username, "IDENTITY")?,
+                }
+            }
+            RecordType::SOCIAL => {
+                let peer = data
+                    .try_into()
+                    .map_err(|_| GnsRecordError::InvalidData("SOCIAL"))?;
+                Self::Social(PeerIdentity::from_bytes(peer))
+            }
+        };
+        Ok(record)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GnsRecord {
+    pub data: RecordData,
+    pub expiration: Expiration,
+    pub flags: RecordFlags,
+}
+
+impl GnsRecord {
+    pub fn new(data: RecordData) -> Self {
+        Self {
+            data,
+            expiration: Expiration::Never,
+            flags: RecordFlags::empty(),
+        }
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        self.data.record_type()
+    }
+
+    pub fn with_expiration(mut self, expiration: Expiration) -> Self {
+        self.expiration = expiration;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: RecordFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expiration.is_expired(Utc::now())
+    }
+
+    /// Appends the record as a `NetworkRecord` header (expiration, data size,
+    /// flags, type; all big-endian) followed by its data.
+    pub fn write_to(&self, out: &mut Vec<u8>) -> Result<(), GnsRecordError> {
+        let data = self.data.to_bytes()?;
+        let data_size =
+            u16::try_from(data.len()).map_err(|_| GnsRecordError::TooLarge(data.len()))?;
+        let (expiration, relative) = self.expiration.to_wire();
+        let mut flags = self.flags.bits();
+        if relative {
+            flags |= RELATIVE_EXPIRATION_FLAG;
+        }
+
+        out.extend_from_slice(&expiration.to_be_bytes());
+        out.extend_from_slice(&data_size.to_be_bytes());
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&self.record_type().number().to_be_bytes());
+        out.extend_from_slice(&data);
+        Ok(())
+    }
+
+    /// Parses one record and returns it with the remaining input.
+    pub fn read_from(input: &[u8]) -> Result<(Self, &[u8]), GnsRecordError> {
+        if input.len() < NETWORK_RECORD_HEADER {
+            return Err(GnsRecordError::Truncated);
+        }
+        let (header, rest) = input.split_at(NETWORK_RECORD_HEADER);
+        let expiration = u64::from_be_bytes(header[0..8].try_into().unwrap());
+        let data_size = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let flags = u16::from_be_bytes([header[10], header[11]]);
+        let number = u32::from_be_bytes(header[12..16].try_into().unwrap());
+
+        if rest.len() < data_size {
+            return Err(GnsRecordError::Truncated);
+        }
+        let (data, rest) = rest.split_at(data_size);
+        let record_type =
+            RecordType::from_number(number).ok_or(GnsRecordError::UnknownType(number))?;
+
+        let record = Self {
+            data: RecordData::from_bytes(record_type, data)?,
+            expiration: Expiration::from_wire(expiration, flags & RELATIVE_EXPIRATION_FLAG != 0),
+            flags: RecordFlags::from_bits_truncate(flags),
+        };
+        Ok((record, rest))
+    }
+}
+
+/// Serializes a record set like `GNUNET_GNSRECORD_records_serialize`.
+pub fn serialize_records(records: &[GnsRecord]) -> Result<Vec<u8>, GnsRecordError> {
+    let mut out = Vec::new();
+    for record in records {
+        record.write_to(&mut out)?;
+    }
+    Ok(out)
+}
+
+pub fn deserialize_records(mut input: &[u8]) -> Result<Vec<GnsRecord>, GnsRecordError> {
+    let mut records = Vec::new();
+    while !input.is_empty() {
+        let (record, rest) = GnsRecord::read_from(input)?;
+        records.push(record);
+        input = rest;
+    }
+    Ok(records)
+}
+
+fn utf8(data: &[u8], what: &'static str) -> Result<String, GnsRecordError> {
+    String::from_utf8(data.to_vec()).map_err(|_| GnsRecordError::InvalidData(what))
+}
+
+/// Writes `name` as uncompressed DNS labels, as `GNUNET_DNSPARSER_builder_add_name` does.
+fn write_dns_name(out: &mut Vec<u8>, name: &str) -> Result<(), GnsRecordError> {
+    let trimmed = name.trim_end_matches('.');
+    if trimmed.len() > MAX_DNS_NAME {
+        return Err(GnsRecordError::InvalidName(name.to_string()));
+    }
+    for label in trimmed.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > MAX_DNS_LABEL {
+            return Err(GnsRecordError::InvalidName(name.to_string()));
+        }
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    Ok(())
+}
+
+fn read_dns_name(data: &[u8]) -> Result<(String, &[u8]), GnsRecordError> {
+    let mut labels = Vec::new();
+    let mut pos = 0;
+    loop {
+        let len = *data.get(pos).ok_or(GnsRecordError::Truncated)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len > MAX_DNS_LABEL {
+            // Compression pointers are not valid inside record data.
+            return Err(GnsRecordError::InvalidData("DNS name"));
+        }
+        let label = data.get(pos..pos + len).ok_or(GnsRecordError::Truncated)?;
+        labels.push(utf8(label, "DNS name")?);
+        pos += len;
+    }
+    Ok((labels.join("."), &data[pos..]))
+}
+
+/// Decodes a zone key string to the 32-byte key. Keys listed by the identity
+/// daemon carry a 4-byte key type prefix, which the record type already implies.
+fn zone_key_bytes(key: &PublicKey) -> Result<[u8; 32], GnsRecordError> {
+    let invalid = || GnsRecordError::InvalidZoneKey(key.as_str().to_string());
+    let encoded = CString::new(key.as_str()).map_err(|_| invalid())?;
+    let decode = |out: &mut [u8]| unsafe {
+        gnunet_sys::GNUNET_STRINGS_string_to_data(
+            encoded.as_ptr(),
+            key.as_str().len(),
+            out.as_mut_ptr() as *mut libc::c_void,
+            out.len(),
+        ) == gnunet_sys::GNUNET_GenericReturnValue_GNUNET_OK
+    };
+
+    let mut bytes = [0u8; 32];
+    if decode(&mut bytes) {
+        return Ok(bytes);
+    }
+    let mut typed = [0u8; 36];
+    if decode(&mut typed) {
+        bytes.copy_from_slice(&typed[4..]);
+        return Ok(bytes);
+    }
+    Err(invalid())
+}
+
+fn zone_key_from_bytes(data: &[u8]) -> Result<PublicKey, GnsRecordError> {
+    if data.len() != 32 {
+        return Err(GnsRecordError::InvalidData("zone key"));
+    }
+    Ok(PublicKey::new(data_to_string(data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(byte: u8) -> PeerIdentity {
+        PeerIdentity::from_bytes([byte; PEER_IDENTITY_SIZE])
+    }
+
+    fn round_trip(record: GnsRecord) {
+        let bytes = serialize_records(std::slice::from_ref(&record)).unwrap();
+        assert_eq!(deserialize_records(&bytes).unwrap(), vec![record]);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = [
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            RecordData::Aaaa(Ipv6Addr::LOCALHOST),
+            RecordData::Cname("www.example.com".to_string()),
+            RecordData::Ns("ns1.example.com".to_string()),
+            RecordData::Gns2Dns {
+                name: "example.com".to_string(),
+                server: "ns1.example.com".to_string(),
+            },
+            RecordData::Box {
+                protocol: 6,
+                service: 443,
+                record_type: RecordType::TEXT.number(),
+                data: b"boxed".to_vec(),
+            },
+            RecordData::Text("hello, zone".to_string()),
+            RecordData::Identity {
+                peer: peer(7),
+                username: "alice".to_string(),
+            },
+            RecordData::Social(peer(9)),
+        ];
+        for data in records {
+            round_trip(GnsRecord::new(data));
+        }
+    }
+
+    #[test]
+    fn zone_keys_round_trip() {
+        let key = zone_key_from_bytes(&[42; 32]).unwrap();
+        round_trip(GnsRecord::new(RecordData::Pkey(key.clone())));
+        round_trip(GnsRecord::new(RecordData::Edkey(key)));
+    }
+
+    #[test]
+    fn expiration_and_flags_round_trip() {
+        let at = DateTime::from_timestamp_micros(1_700_000_000_000_000).unwrap();
+        round_trip(
+            GnsRecord::new(RecordData::Social(peer(1)))
+                .with_expiration(Expiration::Absolute(at))
+                .with_flags(RecordFlags::PRIVATE | RecordFlags::SHADOW),
+        );
+        round_trip(
+            GnsRecord::new(RecordData::Text("ttl".to_string()))
+                .with_expiration(Expiration::Relative(Duration::from_secs(3600))),
+        );
+    }
+
+    #[test]
+    fn record_sets_round_trip() {
+        let records = vec![
+            GnsRecord::new(RecordData::Social(peer(3))),
+            GnsRecord::new(RecordData::Text("second".to_string())),
+        ];
+        let bytes = serialize_records(&records).unwrap();
+        assert_eq!(deserialize_records(&bytes).unwrap(), records);
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let bytes =
+            serialize_records(&[GnsRecord::new(RecordData::Text("cut".to_string()))]).unwrap();
+        for len in [1, NETWORK_RECORD_HEADER - 1, bytes.len() - 1] {
+            assert!(matches!(
+                deserialize_records(&bytes[..len]),
+                Err(GnsRecordError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn unknown_type_is_rejected() {
+        let mut bytes =
+            serialize_records(&[GnsRecord::new(RecordData::Text(String::new()))]).unwrap();
+        bytes[12..16].copy_from_slice(&99u32.to_be_bytes());
+        assert!(matches!(
+            deserialize_records(&bytes),
+            Err(GnsRecordError::UnknownType(99))
+        ));
+    }
+
+    #[test]
+    fn malformed_data_is_rejected() {
+        assert!(RecordData::from_bytes(RecordType::A, &[1, 2, 3]).is_err());
+        assert!(RecordData::from_bytes(RecordType::SOCIAL, &[0; 4]).is_err());
+        assert!(RecordData::from_bytes(RecordType::TEXT, &[0xff, 0xfe]).is_err());
+        assert!(RecordData::from_bytes(RecordType::CNAME, &[3, b'w', b'w']).is_err());
+        assert!(RecordData::from_bytes(RecordType::PKEY, &[0; 31]).is_err());
+    }
+
+    #[test]
+    fn oversized_names_are_rejected() {
+        let label = "a".repeat(MAX_DNS_LABEL + 1);
+        assert!(RecordData::Cname(label).to_bytes().is_err());
+    }
+}
@@ -1,5 +1,37 @@
-use crate::gnunet::{PrivateKey, PublicKey};
+use crate::gnunet::{Config, Keyring, PrivateKey, PublicKey, is_valid_ego_name};
+use futures::channel::mpsc::{Receiver, Sender, channel};
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+/// Directory used for the keyring fallback when none is configured.
+pub const DEFAULT_KEYRING_DIR: &str = "keyring";
+
+/// How long to wait for the identity daemon to list its egos or answer a request.
+const DAEMON_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the scheduler thread picks up requests from the service.
+const COMMAND_POLL_INTERVAL_US: u64 = 50_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    #[error("identity daemon unavailable: {0}")]
+    Unavailable(String),
+    #[error("identity daemon error: {0}")]
+    Daemon(String),
+    #[error("ego `{0}` already exists")]
+    EgoExists(String),
+    #[error("ego `{0}` not found")]
+    EgoNotFound(String),
+    #[error("invalid ego name `{0}`")]
+    InvalidName(String),
+    #[error("invalid private key for ego `{0}`")]
+    InvalidKey(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ego {
@@ -10,20 +42,103 @@ pub struct Ego {
 
 impl Ego {
     pub fn new(name: impl Into<String>) -> Self {
-        let name = name.into();
-        let private_key = PrivateKey::new(format!("sk:{}", uuid::Uuid::new_v4()));
-        let public_key = private_key.public_key();
+        let private_key = PrivateKey::generate_eddsa();
+        let public_key = private_key
+            .public_key()
+            .expect("freshly generated EdDSA key is valid");
         Self {
+            name: name.into(),
+            private_key,
+            public_key,
+        }
+    }
+
+    pub fn from_private_key(
+        name: impl Into<String>,
+        private_key: PrivateKey,
+    ) -> Result<Self, IdentityError> {
+        let name = name.into();
+        let public_key = private_key
+            .public_key()
+            .ok_or_else(|| IdentityError::InvalidKey(name.clone()))?;
+        Ok(Self {
             name,
             private_key,
             public_key,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EgoEvent {
+    Added(Ego),
+    Renamed { old_name: String, ego: Ego },
+    Deleted(Ego),
+}
+
+/// Ego list and watchers, shared with the daemon's scheduler thread.
+#[derive(Default)]
+struct EgoState {
+    egos: RwLock<Vec<Ego>>,
+    watchers: Mutex<Vec<Sender<EgoEvent>>>,
+}
+
+impl EgoState {
+    fn apply(&self, event: EgoEvent) {
+        self.update(&event);
+        self.notify(event);
+    }
+
+    /// Adds a new ego, refusing a name that is already taken.
+    fn add(&self, ego: Ego) -> Result<(), IdentityError> {
+        {
+            let mut egos = self.egos.write();
+            if egos.iter().any(|e| e.name == ego.name) {
+                return Err(IdentityError::EgoExists(ego.name));
+            }
+            egos.push(ego.clone());
         }
+        self.notify(EgoEvent::Added(ego));
+        Ok(())
     }
+
+    /// Updates the ego list without telling watchers.
+    fn update(&self, event: &EgoEvent) {
+        let mut egos = self.egos.write();
+        match event {
+            EgoEvent::Added(ego) => {
+                egos.retain(|e| e.name != ego.name);
+                egos.push(ego.clone());
+            }
+            EgoEvent::Renamed { old_name, ego } => {
+                egos.retain(|e| e.name != *old_name && e.name != ego.name);
+                egos.push(ego.clone());
+            }
+            EgoEvent::Deleted(ego) => egos.retain(|e| e.name != ego.name),
+        }
+    }
+
+    fn notify(&self, event: EgoEvent) {
+        self.watchers
+            .lock()
+            .retain_mut(|tx| !tx.is_closed() && tx.try_send(event.clone()).is_ok());
+    }
+}
+
+enum Backend {
+    Memory,
+    Keyring(Keyring),
+    Daemon(mpsc::Sender<DaemonCommand>),
 }
 
+/// Egos held by the identity daemon or a local fallback.
+///
+/// All methods take `&self`, so the service can be shared without an outer
+/// lock that would be held while the daemon answers.
 pub struct IdentityService {
-    egos: Vec<Ego>,
-    default_ego: Option<String>,
+    backend: Backend,
+    state: Arc<EgoState>,
+    default_ego: RwLock<Option<String>>,
 }
 
 impl Default for IdentityService {
@@ -33,34 +148,502 @@ impl Default for IdentityService {
 }
 
 impl IdentityService {
+    /// An ephemeral service whose egos live only in memory.
     pub fn new() -> Self {
         Self {
-            egos: Vec::new(),
-            default_ego: None,
+            backend: Backend::Memory,
+            state: Arc::new(EgoState::default()),
+            default_ego: RwLock::new(None),
         }
     }
 
-    pub fn create_ego(&mut self, name: &str) -> Ego {
-        let ego = Ego::new(name);
-        self.egos.push(ego.clone());
-        ego
+    /// Connects to the GNUnet identity daemon and waits for its ego listing.
+    pub fn connect(config: &Config) -> Result<Self, IdentityError> {
+        let state = Arc::new(EgoState::default());
+        let commands = daemon::spawn(config, state.clone())?;
+        Ok(Self {
+            backend: Backend::Daemon(commands),
+            state,
+            default_ego: RwLock::new(None),
+        })
     }
 
-    pub fn get_ego(&self, name: &str) -> Option<&Ego> {
-        self.egos.iter().find(|e| e.name == name)
+    /// Opens the on-disk keyring in `dir`, loading the egos stored there.
+    pub fn with_keyring(dir: impl Into<std::path::PathBuf>) -> Result<Self, IdentityError> {
+        let keyring = Keyring::open(dir)?;
+        let state = Arc::new(EgoState::default());
+        *state.egos.write() = keyring.load()?;
+        Ok(Self {
+            backend: Backend::Keyring(keyring),
+            state,
+            default_ego: RwLock::new(None),
+        })
     }
 
-    pub fn set_default(&mut self, name: &str) {
-        self.default_ego = Some(name.to_string());
+    pub fn is_daemon(&self) -> bool {
+        matches!(self.backend, Backend::Daemon(_))
     }
 
-    pub fn get_default(&self) -> Option<&Ego> {
-        self.default_ego
-            .as_ref()
-            .and_then(|name| self.get_ego(name))
+    pub fn create_ego(&self, name: &str) -> Result<Ego, IdentityError> {
+        if !is_valid_ego_name(name) {
+            return Err(IdentityError::InvalidName(name.to_string()));
+        }
+        if self.get_ego(name).is_some() {
+            return Err(IdentityError::EgoExists(name.to_string()));
+        }
+
+        let ego = match &self.backend {
+            Backend::Memory => Ego::new(name),
+            Backend::Keyring(keyring) => {
+                let ego = Ego::new(name);
+                keyring.store(&ego)?;
+                ego
+            }
+            Backend::Daemon(commands) => {
+                let ego = daemon::request(commands, |reply| DaemonCommand::Create {
+                    name: name.to_string(),
+                    reply,
+                })?;
+                // Watchers hear of the ego when the daemon announces it; cache
+                // it now so it can be looked up straight away.
+                self.state.update(&EgoEvent::Added(ego.clone()));
+                return Ok(ego);
+            }
+        };
+
+        self.state.add(ego.clone())?;
+        Ok(ego)
+    }
+
+    pub fn rename_ego(&self, old_name: &str, new_name: &str) -> Result<Ego, IdentityError> {
+        if !is_valid_ego_name(new_name) {
+            return Err(IdentityError::InvalidName(new_name.to_string()));
+        }
+        let ego = self
+            .get_ego(old_name)
+            .ok_or_else(|| IdentityError::EgoNotFound(old_name.to_string()))?;
+        if self.get_ego(new_name).is_some() {
+            return Err(IdentityError::EgoExists(new_name.to_string()));
+        }
+
+        match &self.backend {
+            Backend::Memory => {}
+            Backend::Keyring(keyring) => keyring.rename(old_name, new_name)?,
+            Backend::Daemon(commands) => {
+                daemon::request(commands, |reply| DaemonCommand::Rename {
+                    old_name: old_name.to_string(),
+                    new_name: new_name.to_string(),
+                    reply,
+                })?;
+            }
+        }
+
+        let renamed = Ego {
+            name: new_name.to_string(),
+            ..ego
+        };
+        let event = EgoEvent::Renamed {
+            old_name: old_name.to_string(),
+            ego: renamed.clone(),
+        };
+        if self.is_daemon() {
+            // As for a new ego, watchers hear of it from the daemon.
+            self.state.update(&event);
+        } else {
+            self.state.apply(event);
+        }
+        let mut default_ego = self.default_ego.write();
+        if default_ego.as_deref() == Some(old_name) {
+            *default_ego = Some(new_name.to_string());
+        }
+        Ok(renamed)
     }
 
-    pub fn list_egos(&self) -> &[Ego] {
-        &self.egos
+    pub fn delete_ego(&self, name: &str) -> Result<Ego, IdentityError> {
+        let ego = self
+            .get_ego(name)
+            .ok_or_else(|| IdentityError::EgoNotFound(name.to_string()))?;
+
+        match &self.backend {
+            Backend::Memory => {}
+            Backend::Keyring(keyring) => keyring.remove(name)?,
+            Backend::Daemon(commands) => {
+                daemon::request(commands, |reply| DaemonCommand::Delete {
+                    name: name.to_string(),
+                    reply,
+                })?;
+            }
+        }
+
+        if !self.is_daemon() {
+            self.state.apply(EgoEvent::Deleted(ego.clone()));
+        }
+        let mut default_ego = self.default_ego.write();
+        if default_ego.as_deref() == Some(name) {
+            *default_ego = None;
+        }
+        Ok(ego)
+    }
+
+    pub fn get_ego(&self, name: &str) -> Option<Ego> {
+        self.state
+            .egos
+            .read()
+            .iter()
+            .find(|e| e.name == name)
+            .cloned()
+    }
+
+    pub fn set_default(&self, name: &str) {
+        *self.default_ego.write() = Some(name.to_string());
+    }
+
+    pub fn get_default(&self) -> Option<Ego> {
+        let name = self.default_ego.read().clone()?;
+        self.get_ego(&name)
+    }
+
+    pub fn list_egos(&self) -> Vec<Ego> {
+        self.state.egos.read().clone()
+    }
+
+    /// Streams ego additions, renames and deletions, including those made by
+    /// other clients of the identity daemon.
+    pub fn watch(&self) -> Receiver<EgoEvent> {
+        let (tx, rx) = channel(64);
+        self.state.watchers.lock().push(tx);
+        rx
+    }
+}
+
+enum DaemonCommand {
+    Create {
+        name: String,
+        reply: mpsc::Sender<Result<Ego, IdentityError>>,
+    },
+    Rename {
+        old_name: String,
+        new_name: String,
+        reply: mpsc::Sender<Result<(), IdentityError>>,
+    },
+    Delete {
+        name: String,
+        reply: mpsc::Sender<Result<(), IdentityError>>,
+    },
+}
+
+/// Glue between the service and `GNUNET_IDENTITY_*`, which must run on a
+/// thread driven by the GNUnet scheduler.
+mod daemon {
+    use super::*;
+    use gnunet_sys as sys;
+    use std::ptr;
+
+    struct Context {
+        config_path: Option<CString>,
+        cfg: *mut sys::GNUNET_CONFIGURATION_Handle,
+        handle: *mut sys::GNUNET_IDENTITY_Handle,
+        state: Arc<EgoState>,
+        commands: mpsc::Receiver<DaemonCommand>,
+        ready: Option<mpsc::Sender<Result<(), IdentityError>>>,
+    }
+
+    pub(super) fn spawn(
+        config: &Config,
+        state: Arc<EgoState>,
+    ) -> Result<mpsc::Sender<DaemonCommand>, IdentityError> {
+        let config_path = match &config.config_path {
+            Some(path) => Some(
+                CString::new(path.to_string_lossy().into_owned())
+                    .map_err(|e| IdentityError::Unavailable(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let (command_tx, command_rx) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let context = Box::new(Context {
+            config_path,
+            cfg: ptr::null_mut(),
+            handle: ptr::null_mut(),
+            state,
+            commands: command_rx,
+            ready: Some(ready_tx),
+        });
+        let context = SendPtr(Box::into_raw(context));
+
+        std::thread::Builder::new()
+            .name("gnunet-identity".to_string())
+            .spawn(move || {
+                let context = context;
+                unsafe {
+                    sys::GNUNET_SCHEDULER_run(Some(start), context.0 as *mut libc::c_void);
+                    drop(Box::from_raw(context.0));
+                }
+            })?;
+
+        match blocking(|| ready_rx.recv_timeout(DAEMON_TIMEOUT)) {
+            Ok(Ok(())) => Ok(command_tx),
+            Ok(Err(e)) => Err(e),
+            // Dropping `command_tx` makes the scheduler thread disconnect and exit.
+            Err(_) => Err(IdentityError::Unavailable(
+                "timed out waiting for ego list".to_string(),
+            )),
+        }
+    }
+
+    pub(super) fn request<T>(
+        commands: &mpsc::Sender<DaemonCommand>,
+        command: impl FnOnce(mpsc::Sender<Result<T, IdentityError>>) -> DaemonCommand,
+    ) -> Result<T, IdentityError> {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        commands
+            .send(command(reply_tx))
+            .map_err(|_| IdentityError::Unavailable("scheduler thread exited".to_string()))?;
+        blocking(|| reply_rx.recv_timeout(DAEMON_TIMEOUT))
+            .map_err(|_| IdentityError::Unavailable("request timed out".to_string()))?
+    }
+
+    /// Runs a wait for the scheduler thread, letting a multi-threaded tokio
+    /// runtime move its other tasks off the current worker meanwhile.
+    fn blocking<T>(wait: impl FnOnce() -> T) -> T {
+        use tokio::runtime::{Handle, RuntimeFlavor};
+        match Handle::try_current() {
+            Ok(rt) if rt.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(wait)
+            }
+            _ => wait(),
+        }
+    }
+
+    struct SendPtr(*mut Context);
+
+    unsafe impl Send for SendPtr {}
+
+    unsafe extern "C" fn start(cls: *mut libc::c_void) {
+        let ctx = unsafe { &mut *(cls as *mut Context) };
+        unsafe {
+            ctx.cfg = sys::GNUNET_CONFIGURATION_create();
+            let filename = ctx.config_path.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+            if sys::GNUNET_CONFIGURATION_load(ctx.cfg, filename)
+                != sys::GNUNET_GenericReturnValue_GNUNET_OK
+            {
+                if let Some(ready) = ctx.ready.take() {
+                    let _ = ready.send(Err(IdentityError::Unavailable(
+                        "failed to load configuration".to_string(),
+                    )));
+                }
+                sys::GNUNET_CONFIGURATION_destroy(ctx.cfg);
+                ctx.cfg = ptr::null_mut();
+                return;
+            }
+            ctx.handle = sys::GNUNET_IDENTITY_connect(ctx.cfg, Some(on_ego), cls);
+            schedule_poll(cls);
+        }
+    }
+
+    unsafe fn schedule_poll(cls: *mut libc::c_void) {
+        let delay = sys::GNUNET_TIME_Relative {
+            rel_value_us: COMMAND_POLL_INTERVAL_US,
+        };
+        unsafe {
+            sys::GNUNET_SCHEDULER_add_delayed(delay, Some(poll_commands), cls);
+        }
+    }
+
+    unsafe extern "C" fn poll_commands(cls: *mut libc::c_void) {
+        let ctx = unsafe { &mut *(cls as *mut Context) };
+        loop {
+            match ctx.commands.try_recv() {
+                Ok(command) => unsafe { dispatch(ctx, command) },
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    unsafe {
+                        sys::GNUNET_IDENTITY_disconnect(ctx.handle);
+                        sys::GNUNET_CONFIGURATION_destroy(ctx.cfg);
+                    }
+                    ctx.handle = ptr::null_mut();
+                    ctx.cfg = ptr::null_mut();
+                    return;
+                }
+            }
+        }
+        unsafe { schedule_poll(cls) };
+    }
+
+    unsafe fn dispatch(ctx: &mut Context, command: DaemonCommand) {
+        match command {
+            DaemonCommand::Create { name, reply } => {
+                let Ok(c_name) = CString::new(name.clone()) else {
+                    let _ = reply.send(Err(IdentityError::InvalidName(name)));
+                    return;
+                };
+                let pending = Box::into_raw(Box::new((name, reply)));
+                unsafe {
+                    sys::GNUNET_IDENTITY_create(
+                        ctx.handle,
+                        c_name.as_ptr(),
+                        ptr::null(),
+                        sys::GNUNET_CRYPTO_KeyType_GNUNET_PUBLIC_KEY_TYPE_EDDSA,
+                        Some(on_created),
+                        pending as *mut libc::c_void,
+                    );
+                }
+            }
+            DaemonCommand::Rename {
+                old_name,
+                new_name,
+                reply,
+            } => {
+                let (Ok(old), Ok(new)) = (CString::new(old_name), CString::new(new_name.clone()))
+                else {
+                    let _ = reply.send(Err(IdentityError::InvalidName(new_name)));
+                    return;
+                };
+                let pending = Box::into_raw(Box::new(reply));
+                unsafe {
+                    sys::GNUNET_IDENTITY_rename(
+                        ctx.handle,
+                        old.as_ptr(),
+                        new.as_ptr(),
+                        Some(on_done),
+                        pending as *mut libc::c_void,
+                    );
+                }
+            }
+            DaemonCommand::Delete { name, reply } => {
+                let Ok(c_name) = CString::new(name.clone()) else {
+                    let _ = reply.send(Err(IdentityError::InvalidName(name)));
+                    return;
+                };
+                let pending = Box::into_raw(Box::new(reply));
+                unsafe {
+                    sys::GNUNET_IDENTITY_delete(
+                        ctx.handle,
+                        c_name.as_ptr(),
+                        Some(on_done),
+                        pending as *mut libc::c_void,
+                    );
+                }
+            }
+        }
+    }
+
+    type CreateReply = (String, mpsc::Sender<Result<Ego, IdentityError>>);
+
+    unsafe extern "C" fn on_created(
+        cls: *mut libc::c_void,
+        pk: *const sys::GNUNET_CRYPTO_PrivateKey,
+        ec: sys::GNUNET_ErrorCode,
+    ) {
+        let (name, reply) = *unsafe { Box::from_raw(cls as *mut CreateReply) };
+        let result = if ec != sys::GNUNET_ErrorCode_GNUNET_EC_NONE || pk.is_null() {
+            Err(error_from_code(ec))
+        } else {
+            Ok(unsafe { ego_from_keys(name, pk) })
+        };
+        let _ = reply.send(result);
+    }
+
+    unsafe extern "C" fn on_done(cls: *mut libc::c_void, ec: sys::GNUNET_ErrorCode) {
+        let reply = *unsafe { Box::from_raw(cls as *mut mpsc::Sender<Result<(), IdentityError>>) };
+        let result = if ec == sys::GNUNET_ErrorCode_GNUNET_EC_NONE {
+            Ok(())
+        } else {
+            Err(error_from_code(ec))
+        };
+        let _ = reply.send(result);
+    }
+
+    /// `GNUNET_IDENTITY_Callback`: called once per ego on connect, with a null
+    /// ego when the initial listing is complete, and again on every change.
+    /// `*ego_ctx` holds the ego's last known name so renames and deletions can
+    /// be reported against it.
+    unsafe extern "C" fn on_ego(
+        cls: *mut libc::c_void,
+        ego: *mut sys::GNUNET_IDENTITY_Ego,
+        ego_ctx: *mut *mut libc::c_void,
+        name: *const libc::c_char,
+    ) {
+        let ctx = unsafe { &mut *(cls as *mut Context) };
+        if ego.is_null() {
+            if let Some(ready) = ctx.ready.take() {
+                let _ = ready.send(Ok(()));
+            }
+            return;
+        }
+
+        let known = unsafe { *ego_ctx as *mut String };
+        let current =
+            unsafe { ego_from_keys(String::new(), sys::GNUNET_IDENTITY_ego_get_private_key(ego)) };
+
+        if name.is_null() {
+            if !known.is_null() {
+                let old_name = *unsafe { Box::from_raw(known) };
+                unsafe { *ego_ctx = ptr::null_mut() };
+                ctx.state.apply(EgoEvent::Deleted(Ego {
+                    name: old_name,
+                    ..current
+                }));
+            }
+            return;
+        }
+
+        let name = unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned();
+        let ego = Ego {
+            name: name.clone(),
+            ..current
+        };
+        if known.is_null() {
+            unsafe { *ego_ctx = Box::into_raw(Box::new(name)) as *mut libc::c_void };
+            ctx.state.apply(EgoEvent::Added(ego));
+        } else {
+            let old_name = std::mem::replace(unsafe { &mut *known }, name.clone());
+            if old_name != name {
+                ctx.state.apply(EgoEvent::Renamed { old_name, ego });
+            }
+        }
+    }
+
+    unsafe fn ego_from_keys(name: String, pk: *const sys::GNUNET_CRYPTO_PrivateKey) -> Ego {
+        unsafe {
+            let mut public: sys::GNUNET_CRYPTO_PublicKey = std::mem::zeroed();
+            sys::GNUNET_CRYPTO_key_get_public(pk, &mut public);
+            Ego {
+                name,
+                private_key: PrivateKey::new(take_string(
+                    sys::GNUNET_CRYPTO_private_key_to_string(pk),
+                )),
+                public_key: PublicKey::new(take_string(sys::GNUNET_CRYPTO_public_key_to_string(
+                    &public,
+                ))),
+            }
+        }
+    }
+
+    fn error_from_code(ec: sys::GNUNET_ErrorCode) -> IdentityError {
+        let hint = unsafe { sys::GNUNET_ErrorCode_get_hint(ec) };
+        if hint.is_null() {
+            return IdentityError::Daemon(format!("error code {}", ec));
+        }
+        IdentityError::Daemon(
+            unsafe { CStr::from_ptr(hint) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    unsafe fn take_string(cstr: *mut libc::c_char) -> String {
+        unsafe {
+            let s = CStr::from_ptr(cstr).to_string_lossy().into_owned();
+            sys::GNUNET_xfree_(
+                cstr as *mut libc::c_void,
+                c"identity.rs".as_ptr(),
+                line!() as libc::c_int,
+            );
+            s
+        }
     }
 }
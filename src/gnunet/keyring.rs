@@ -0,0 +1,91 @@
+use crate::gnunet::{Ego, IdentityError, PrivateKey};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// On-disk ego store used when the identity daemon is unavailable.
+///
+/// Like the daemon's `egos` directory, each ego is a file named after the ego
+/// holding its private key, here in the GNUnet string encoding.
+pub struct Keyring {
+    dir: PathBuf,
+}
+
+impl Keyring {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, IdentityError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Loads every ego in the keyring. Unreadable or malformed files are
+    /// logged and skipped so one bad file doesn't keep the others out.
+    pub fn load(&self) -> Result<Vec<Ego>, IdentityError> {
+        let mut egos = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !is_valid_ego_name(&name) {
+                continue;
+            }
+            let ego = fs::read_to_string(entry.path())
+                .map_err(IdentityError::from)
+                .and_then(|encoded| Ego::from_private_key(name, PrivateKey::new(encoded.trim())));
+            match ego {
+                Ok(ego) => egos.push(ego),
+                Err(e) => tracing::warn!("Skipping keyring file {:?}: {}", entry.path(), e),
+            }
+        }
+        egos.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(egos)
+    }
+
+    pub fn store(&self, ego: &Ego) -> Result<(), IdentityError> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(self.path(&ego.name)?)?;
+        file.write_all(ego.private_key.as_str().as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn rename(&self, old_name: &str, new_name: &str) -> Result<(), IdentityError> {
+        let to = self.path(new_name)?;
+        if to.exists() {
+            return Err(IdentityError::EgoExists(new_name.to_string()));
+        }
+        fs::rename(self.path(old_name)?, to)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), IdentityError> {
+        fs::remove_file(self.path(name)?)?;
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, IdentityError> {
+        if !is_valid_ego_name(name) {
+            return Err(IdentityError::InvalidName(name.to_string()));
+        }
+        Ok(self.dir.join(name))
+    }
+}
+
+/// Ego names double as file names, so path separators and dot-files are refused.
+pub fn is_valid_ego_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\', '\0'])
+}
@@ -2,10 +2,16 @@ pub mod cadet;
 pub mod config;
 pub mod crypto;
 pub mod gns;
+pub mod gnsrecord;
 pub mod identity;
+pub mod keyring;
+pub mod namestore;
 
 pub use cadet::*;
 pub use config::*;
 pub use crypto::*;
 pub use gns::*;
+pub use gnsrecord::*;
 pub use identity::*;
+pub use keyring::*;
+pub use namestore::*;
@@ -0,0 +1,74 @@
+use crate::gnunet::{GnsRecord, PublicKey};
+use std::collections::HashMap;
+
+/// Label under which records for the zone itself are stored.
+pub const APEX_LABEL: &str = "@";
+
+pub struct NamestoreService {
+    zones: HashMap<String, HashMap<String, Vec<GnsRecord>>>,
+}
+
+impl Default for NamestoreService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NamestoreService {
+    pub fn new() -> Self {
+        Self {
+            zones: HashMap::new(),
+        }
+    }
+
+    /// Replaces the record set under `label`, like `GNUNET_NAMESTORE_records_store`.
+    /// An empty record set removes the label.
+    pub fn store_records(&mut self, zone: &PublicKey, label: &str, records: Vec<GnsRecord>) {
+        if records.is_empty() {
+            self.remove_records(zone, label);
+            return;
+        }
+        self.zones
+            .entry(zone.as_str().to_string())
+            .or_default()
+            .insert(normalize_label(label), records);
+    }
+
+    pub fn lookup_records(&self, zone: &PublicKey, label: &str) -> Option<Vec<GnsRecord>> {
+        self.zones
+            .get(zone.as_str())?
+            .get(&normalize_label(label))
+            .cloned()
+    }
+
+    pub fn remove_records(&mut self, zone: &PublicKey, label: &str) -> bool {
+        let Some(labels) = self.zones.get_mut(zone.as_str()) else {
+            return false;
+        };
+        let removed = labels.remove(&normalize_label(label)).is_some();
+        if labels.is_empty() {
+            self.zones.remove(zone.as_str());
+        }
+        removed
+    }
+
+    pub fn zone_labels(&self, zone: &PublicKey) -> Vec<String> {
+        self.zones
+            .get(zone.as_str())
+            .map(|labels| labels.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Labels are single DNS-style name components; `@` is reserved for the apex.
+pub fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && label != APEX_LABEL
+        && !label.contains('.')
+        && !label.chars().any(char::is_whitespace)
+}
+
+fn normalize_label(label: &str) -> String {
+    label.to_lowercase()
+}
@@ -1,4 +1,5 @@
 pub mod gnunet;
+pub mod media;
 pub mod mqtt;
 pub mod protocol;
 pub mod social;
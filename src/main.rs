@@ -1,32 +1,31 @@
 use futures::{SinkExt, StreamExt};
+use gnunet_social::gnunet::{Config, DEFAULT_KEYRING_DIR, IdentityService};
 use gnunet_social::mqtt::MqttServer;
 use gnunet_social::protocol::ServerMessage;
+use gnunet_social::social::SocialStore;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
 use tokio_tungstenite::{accept_hdr_async, tungstenite::protocol::Message};
 use tracing::{error, info, warn};
 
-type Tx = broadcast::Sender<String>;
+const MEDIA_GC_INTERVAL: Duration = Duration::from_secs(600);
+const TRANSFER_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+const DELIVERY_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct WebSocketServer {
     addr: SocketAddr,
     mqtt_server: Arc<MqttServer>,
-    broadcast_tx: Tx,
 }
 
 impl WebSocketServer {
-    pub fn new(addr: SocketAddr) -> Self {
-        let mqtt_server = Arc::new(MqttServer::new());
-        let (broadcast_tx, _) = broadcast::channel(1024);
-
-        Self {
-            addr,
-            mqtt_server,
-            broadcast_tx,
-        }
+    pub fn new(addr: SocketAddr, mqtt_server: MqttServer) -> Self {
+        let mqtt_server = Arc::new(mqtt_server);
+
+        Self { addr, mqtt_server }
     }
 
     pub fn mqtt_server(&self) -> Arc<MqttServer> {
@@ -37,13 +36,11 @@ impl WebSocketServer {
         let listener = TcpListener::bind(&self.addr).await?;
         info!("WebSocket server listening on {}", self.addr);
 
-        let broadcast_tx = self.broadcast_tx.clone();
         let mqtt_server = self.mqtt_server.clone();
 
         while let Ok((stream, addr)) = listener.accept().await {
-            let broadcast_tx = broadcast_tx.clone();
-            let mqtt_server = mqtt_server.clone();
-            let mut broadcast_rx = broadcast_tx.subscribe();
+            let mqtt_server = mqtt_server.connect();
+            let mut events = mqtt_server.subscribe_events();
 
             tokio::spawn(async move {
                 info!("New connection from {}", addr);
@@ -64,7 +61,7 @@ impl WebSocketServer {
 
                 let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-                loop {
+                'connection: loop {
                     tokio::select! {
                         msg = ws_receiver.next() => {
                             match msg {
@@ -75,20 +72,37 @@ impl WebSocketServer {
                                             error!("Failed to send message: {}", e);
                                             break;
                                         }
-
-                                        if let ServerMessage::Event(event) = response {
-                                            let event_json = serde_json::to_string(&event).unwrap();
-                                            let _ = broadcast_tx.send(event_json);
+                                        for frame in mqtt_server.media_frames(&response) {
+                                            if let Err(e) = ws_sender.send(Message::Binary(frame)).await {
+                                                error!("Failed to send media: {}", e);
+                                                break 'connection;
+                                            }
                                         }
                                     }
                                 }
                                 Some(Ok(Message::Binary(data))) => {
-                                    if let Some(response) = mqtt_server.process_message(&data) {
+                                    // The last chunk of an upload gets the picture decoded,
+                                    // which must not hold up the other connections.
+                                    let server = mqtt_server.clone();
+                                    let response = match tokio::task::spawn_blocking(move || server.process_frame(&data)).await {
+                                        Ok(response) => response,
+                                        Err(e) => {
+                                            error!("Failed to handle frame: {}", e);
+                                            break;
+                                        }
+                                    };
+                                    if let Some(response) = response {
                                         let json = serde_json::to_string(&response).unwrap();
                                         if let Err(e) = ws_sender.send(Message::Text(json.into())).await {
                                             error!("Failed to send message: {}", e);
                                             break;
                                         }
+                                        for frame in mqtt_server.media_frames(&response) {
+                                            if let Err(e) = ws_sender.send(Message::Binary(frame)).await {
+                                                error!("Failed to send media: {}", e);
+                                                break 'connection;
+                                            }
+                                        }
                                     }
                                 }
                                 Some(Ok(Message::Ping(data))) => {
@@ -110,16 +124,24 @@ impl WebSocketServer {
                             }
                         }
 
-                        event = broadcast_rx.recv() => {
+                        event = events.recv() => {
                             match event {
-                                Ok(json) => {
+                                Ok(dispatch) => {
+                                    if !mqtt_server.accepts(&dispatch) {
+                                        continue;
+                                    }
+                                    let json = serde_json::to_string(&ServerMessage::Event(dispatch.event.clone())).unwrap();
                                     if let Err(e) = ws_sender.send(Message::Text(json.into())).await {
                                         error!("Failed to broadcast: {}", e);
                                         break;
                                     }
+                                    mqtt_server.delivered(&dispatch);
                                 }
                                 Err(broadcast::error::RecvError::Closed) => break,
-                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                                    warn!("Client {} missed {} events", addr, missed);
+                                    continue;
+                                }
                             }
                         }
                     }
@@ -138,7 +160,87 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let addr: SocketAddr = "0.0.0.0:8080".parse()?;
-    let server = WebSocketServer::new(addr);
+    let config = Config::default();
+
+    let identity = match IdentityService::connect(&config) {
+        Ok(identity) => {
+            info!("Connected to GNUnet identity service");
+            identity
+        }
+        Err(e) => {
+            let dir = config
+                .keyring_path
+                .clone()
+                .unwrap_or_else(|| DEFAULT_KEYRING_DIR.into());
+            warn!("{}; using keyring at {}", e, dir.display());
+            IdentityService::with_keyring(dir)?
+        }
+    };
+
+    let server = WebSocketServer::new(
+        addr,
+        MqttServer::with_identity(SocialStore::new(), identity),
+    );
+
+    let mqtt_server = server.mqtt_server();
+    let mut ego_events = mqtt_server.watch_egos();
+    tokio::spawn(async move {
+        while let Some(event) = ego_events.next().await {
+            mqtt_server.handle_ego_event(event);
+        }
+    });
+
+    let mqtt_server = server.mqtt_server();
+    let mut social = mqtt_server.listen_social();
+    tokio::spawn(async move {
+        while let Some(msg) = social.next().await {
+            mqtt_server.handle_cadet_message(msg);
+        }
+    });
+
+    let mqtt_server = server.mqtt_server();
+    let mut fileshare = mqtt_server.listen_fileshare();
+    tokio::spawn(async move {
+        while let Some(msg) = fileshare.next().await {
+            // Completed fetches are decoded like uploads.
+            let mqtt_server = mqtt_server.clone();
+            if let Err(e) =
+                tokio::task::spawn_blocking(move || mqtt_server.handle_fileshare(msg)).await
+            {
+                error!("Failed to handle fileshare message: {}", e);
+            }
+        }
+    });
+
+    let mqtt_server = server.mqtt_server();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DELIVERY_FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            mqtt_server.flush_deliveries();
+        }
+    });
+
+    let mqtt_server = server.mqtt_server();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TRANSFER_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            mqtt_server.sweep_transfers();
+        }
+    });
+
+    let media = server.mqtt_server().get_store().media.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MEDIA_GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            let removed = media.collect_garbage(chrono::Duration::hours(1));
+            if removed > 0 {
+                info!("Removed {} unreferenced media blobs", removed);
+            }
+        }
+    });
 
     info!("GNUnet Social Media Server starting...");
     info!("WebSocket endpoint: ws://{}", addr);
@@ -0,0 +1,471 @@
+use crate::gnunet::HashCode;
+use bytes::Bytes;
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// Derived images generated for every stored picture, with the longest
+/// side they are scaled down to. Pictures already smaller get no variant.
+pub const THUMBNAIL_SIZES: &[(VariantKind, u32)] =
+    &[(VariantKind::Thumbnail, 160), (VariantKind::Preview, 640)];
+
+/// Largest picture, per side, the store decodes.
+const MAX_IMAGE_DIMENSION: u32 = 16384;
+/// Memory the image decoders may allocate.
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantKind {
+    Thumbnail,
+    Preview,
+}
+
+/// A derived blob attached to the original it was generated from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaVariant {
+    pub kind: VariantKind,
+    pub hash: HashCode,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A generated thumbnail, before it is stored.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub kind: VariantKind,
+    pub hash: HashCode,
+    pub mime_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub data: Bytes,
+}
+
+/// What could be learned about a blob from its content.
+#[derive(Debug, Clone, Default)]
+pub struct MediaMetadata {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_ms: Option<u64>,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+/// Extracts dimensions and duration and renders thumbnails. Content that
+/// fails to parse simply yields no metadata.
+pub fn analyze(mime_type: &str, data: &[u8]) -> MediaMetadata {
+    let mut metadata = match ImageFormat::from_mime_type(mime_type) {
+        Some(format) => analyze_image(format, data).unwrap_or_default(),
+        None => MediaMetadata::default(),
+    };
+    match mime_type {
+        "image/gif" => metadata.duration_ms = gif_duration(data).filter(|ms| *ms > 0),
+        "audio/wav" => metadata.duration_ms = wav_duration(data),
+        "video/mp4" | "video/quicktime" | "audio/mp4" => {
+            if let Some(movie) = mp4_metadata(data) {
+                metadata.duration_ms = movie.duration_ms;
+                metadata.width = movie.width;
+                metadata.height = movie.height;
+            }
+        }
+        _ => {}
+    }
+    metadata
+}
+
+fn decode_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    limits
+}
+
+fn analyze_image(format: ImageFormat, data: &[u8]) -> Option<MediaMetadata> {
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(decode_limits());
+    let mut decoder = reader.into_decoder().ok()?;
+    let orientation = decoder.orientation().ok()?;
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    image.apply_orientation(orientation);
+
+    let (width, height) = (image.width(), image.height());
+    let thumbnails = THUMBNAIL_SIZES
+        .iter()
+        .filter(|(_, size)| width.max(height) > *size)
+        .filter_map(|&(kind, size)| render_thumbnail(&image, kind, size))
+        .collect();
+
+    Some(MediaMetadata {
+        width: Some(width),
+        height: Some(height),
+        duration_ms: None,
+        thumbnails,
+    })
+}
+
+/// Scales `image` to fit in `size` pixels; JPEG unless transparency needs
+/// PNG. Encoding from decoded pixels drops all metadata of the original.
+fn render_thumbnail(image: &DynamicImage, kind: VariantKind, size: u32) -> Option<Thumbnail> {
+    let scaled = image.thumbnail(size, size);
+    let mut buf = Vec::new();
+    let mime_type = if scaled.color().has_alpha() {
+        scaled
+            .to_rgba8()
+            .write_with_encoder(PngEncoder::new(&mut buf))
+            .ok()?;
+        "image/png"
+    } else {
+        scaled
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(
+                &mut buf,
+                THUMBNAIL_JPEG_QUALITY,
+            ))
+            .ok()?;
+        "image/jpeg"
+    };
+
+    Some(Thumbnail {
+        kind,
+        hash: HashCode::generate(&buf),
+        mime_type,
+        width: scaled.width(),
+        height: scaled.height(),
+        data: Bytes::from(buf),
+    })
+}
+
+fn gif_duration(data: &[u8]) -> Option<u64> {
+    let mut decoder = GifDecoder::new(Cursor::new(data)).ok()?;
+    decoder.set_limits(decode_limits()).ok()?;
+    let mut total = 0u64;
+    for frame in decoder.into_frames() {
+        let (numer, denom) = frame.ok()?.delay().numer_denom_ms();
+        total += u64::from(numer) / u64::from(denom.max(1));
+    }
+    Some(total)
+}
+
+fn wav_duration(data: &[u8]) -> Option<u64> {
+    let mut byte_rate = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        let body = pos + 8;
+        match id {
+            b"fmt " => {
+                let rate = data.get(body + 8..body + 12)?;
+                byte_rate = Some(u32::from_le_bytes(rate.try_into().ok()?));
+            }
+            b"data" => {
+                // Streams written on the fly may leave the length open.
+                let len = len.min(data.len() - body) as u64;
+                return byte_rate
+                    .filter(|rate| *rate > 0)
+                    .map(|rate| len * 1000 / u64::from(rate));
+            }
+            _ => {}
+        }
+        pos = body.checked_add(len)?.checked_add(len & 1)?;
+    }
+    None
+}
+
+#[derive(Debug, Default)]
+struct MovieMetadata {
+    duration_ms: Option<u64>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// Iterates the ISO base media boxes in `data` as `(type, body)` pairs.
+fn mp4_boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos.checked_add(8)?)?;
+        let kind = &header[4..8];
+        let (start, end) = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+            0 => (pos + 8, data.len()),
+            1 => {
+                let large = data.get(pos + 8..pos + 16)?;
+                let size = u64::from_be_bytes(large.try_into().ok()?) as usize;
+                (pos + 16, pos.checked_add(size)?)
+            }
+            size => (pos + 8, pos.checked_add(size as usize)?),
+        };
+        let body = data.get(start..end)?;
+        pos = end;
+        Some((kind, body))
+    })
+}
+
+fn mp4_metadata(data: &[u8]) -> Option<MovieMetadata> {
+    let (_, moov) = mp4_boxes(data).find(|(kind, _)| *kind == b"moov")?;
+    let mut movie = MovieMetadata::default();
+
+    if let Some((_, mvhd)) = mp4_boxes(moov).find(|(kind, _)| *kind == b"mvhd") {
+        let (timescale, duration) = match mvhd.first()? {
+            0 => (
+                u32::from_be_bytes(mvhd.get(12..16)?.try_into().ok()?),
+                u64::from(u32::from_be_bytes(mvhd.get(16..20)?.try_into().ok()?)),
+            ),
+            _ => (
+                u32::from_be_bytes(mvhd.get(20..24)?.try_into().ok()?),
+                u64::from_be_bytes(mvhd.get(24..32)?.try_into().ok()?),
+            ),
+        };
+        if timescale > 0 {
+            movie.duration_ms = Some(duration.saturating_mul(1000) / u64::from(timescale));
+        }
+    }
+
+    // Track headers end with the display size as 16.16 fixed point; audio
+    // tracks leave it at zero.
+    let video = mp4_boxes(moov)
+        .filter(|(kind, _)| *kind == b"trak")
+        .filter_map(|(_, trak)| mp4_boxes(trak).find(|(kind, _)| *kind == b"tkhd"))
+        .filter_map(|(_, tkhd)| {
+            let size = tkhd.get(tkhd.len().checked_sub(8)?..)?;
+            let width = u32::from_be_bytes(size[..4].try_into().ok()?) >> 16;
+            let height = u32::from_be_bytes(size[4..].try_into().ok()?) >> 16;
+            Some((width, height))
+        })
+        .find(|(width, height)| *width > 0 && *height > 0);
+    if let Some((width, height)) = video {
+        movie.width = Some(width);
+        movie.height = Some(height);
+    }
+
+    Some(movie)
+}
+
+/// What [`strip_location`] made of a picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stripped {
+    /// There was no location data to remove.
+    Unchanged,
+    /// The picture without its location data.
+    Stripped(Vec<u8>),
+    /// The metadata could not be walked, so it may still hold a location.
+    Malformed,
+}
+
+/// Removes location data embedded in pictures: the GPS directory of EXIF
+/// blocks is zeroed in place and XMP packets, which may repeat it, are
+/// dropped. Pixels are left untouched.
+pub fn strip_location(mime_type: &str, data: &[u8]) -> Stripped {
+    let stripped = match mime_type {
+        "image/jpeg" => strip_jpeg_location(data),
+        "image/png" => strip_png_location(data),
+        "image/webp" => strip_webp_location(data),
+        _ => return Stripped::Unchanged,
+    };
+    stripped.unwrap_or(Stripped::Malformed)
+}
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// The helpers return `None` when the container cannot be parsed.
+fn strip_jpeg_location(data: &[u8]) -> Option<Stripped> {
+    const APP1: u8 = 0xE1;
+    const SOS: u8 = 0xDA;
+    const EOI: u8 = 0xD9;
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(data.get(..2)?);
+    let mut changed = false;
+    let mut pos = 2;
+    // Metadata segments precede the scan; stop at the first image data.
+    loop {
+        if pos + 4 > data.len() || data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        if marker == SOS || marker == EOI {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if len < 2 || pos + 2 + len > data.len() {
+            return None;
+        }
+        let segment = &data[pos..pos + 2 + len];
+        let payload = &segment[4..];
+        if marker == APP1 && payload.starts_with(XMP_HEADER) {
+            changed = true;
+        } else if marker == APP1 && payload.starts_with(EXIF_HEADER) {
+            let start = out.len() + 4 + EXIF_HEADER.len();
+            out.extend_from_slice(segment);
+            changed |= scrub_exif_gps(&mut out[start..]);
+        } else {
+            out.extend_from_slice(segment);
+        }
+        pos += 2 + len;
+    }
+
+    if !changed {
+        return Some(Stripped::Unchanged);
+    }
+    out.extend_from_slice(&data[pos..]);
+    Some(Stripped::Stripped(out))
+}
+
+fn strip_png_location(data: &[u8]) -> Option<Stripped> {
+    const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(data.get(..8)?);
+    let mut changed = false;
+    let mut pos = 8;
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let end = pos.checked_add(12 + len).filter(|end| *end <= data.len())?;
+        let kind = &data[pos + 4..pos + 8];
+        let body = &data[pos + 8..pos + 8 + len];
+        // Dropping whole chunks leaves the checksums of the others valid.
+        if kind == b"eXIf" || (kind == b"iTXt" && body.starts_with(XMP_KEYWORD)) {
+            changed = true;
+        } else {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+        if kind == b"IEND" {
+            out.extend_from_slice(&data[pos..]);
+            break;
+        }
+    }
+
+    Some(match changed {
+        true => Stripped::Stripped(out),
+        false => Stripped::Unchanged,
+    })
+}
+
+fn strip_webp_location(data: &[u8]) -> Option<Stripped> {
+    const VP8X_EXIF: u8 = 0x08;
+    const VP8X_XMP: u8 = 0x04;
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(data.get(..12)?);
+    let mut changed = false;
+    let mut pos = 12;
+    while pos < data.len() {
+        let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let body_end = pos.checked_add(8 + len).filter(|end| *end <= data.len())?;
+        // The pad byte of an odd-sized last chunk is often left out.
+        let end = (body_end + (len & 1)).min(data.len());
+        match &data[pos..pos + 4] {
+            b"EXIF" | b"XMP " => changed = true,
+            b"VP8X" => {
+                let flags = out.len() + 8;
+                out.extend_from_slice(&data[pos..end]);
+                if let Some(flags) = out.get_mut(flags) {
+                    *flags &= !(VP8X_EXIF | VP8X_XMP);
+                }
+            }
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+
+    if !changed {
+        return Some(Stripped::Unchanged);
+    }
+    let riff_len = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Some(Stripped::Stripped(out))
+}
+
+/// Bounds-checked view of a TIFF structure in either byte order.
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn zero(&mut self, at: usize, len: usize) {
+        let end = at.saturating_add(len).min(self.data.len());
+        if at < end {
+            self.data[at..end].fill(0);
+        }
+    }
+}
+
+/// Size in bytes of one value of a TIFF field type.
+fn tiff_type_size(kind: u16) -> usize {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+/// Zeroes every GPS field of the EXIF block `tiff` and leaves an empty
+/// GPS directory behind, so offsets elsewhere in the block stay valid.
+fn scrub_exif_gps(tiff: &mut [u8]) -> bool {
+    const GPS_IFD_TAG: u16 = 0x8825;
+
+    let big_endian = match tiff.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return false,
+    };
+    let mut tiff = Tiff {
+        data: tiff,
+        big_endian,
+    };
+
+    let Some(ifd0) = tiff.u32(4).map(|o| o as usize) else {
+        return false;
+    };
+    let entries = tiff.u16(ifd0).unwrap_or(0) as usize;
+    let gps = (0..entries)
+        .map(|i| ifd0 + 2 + i * 12)
+        .find(|entry| tiff.u16(*entry) == Some(GPS_IFD_TAG))
+        .and_then(|entry| tiff.u32(entry + 8))
+        .map(|o| o as usize);
+    let Some(gps) = gps else {
+        return false;
+    };
+
+    let entries = tiff.u16(gps).unwrap_or(0) as usize;
+    for i in 0..entries {
+        let entry = gps + 2 + i * 12;
+        let (Some(kind), Some(count)) = (tiff.u16(entry + 2), tiff.u32(entry + 4)) else {
+            break;
+        };
+        let len = tiff_type_size(kind).saturating_mul(count as usize);
+        if len > 4
+            && let Some(offset) = tiff.u32(entry + 8)
+        {
+            tiff.zero(offset as usize, len);
+        }
+        tiff.zero(entry, 12);
+    }
+    tiff.zero(gps, 2);
+    entries > 0
+}
@@ -0,0 +1,490 @@
+use crate::gnunet::{HashCode, HashContext};
+use crate::social::PeerId;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub mod metadata;
+pub mod transfer;
+
+pub use metadata::*;
+pub use transfer::*;
+
+/// Largest blob accepted by the store.
+pub const MAX_MEDIA_SIZE: u64 = 16 * 1024 * 1024;
+/// Payload size of upload and download frames.
+pub const MEDIA_CHUNK_SIZE: usize = 64 * 1024;
+/// Uploads a single peer may have in flight.
+pub const MAX_PENDING_UPLOADS: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MediaError {
+    #[error("media exceeds {MAX_MEDIA_SIZE} bytes")]
+    TooLarge,
+    #[error("unsupported media type")]
+    UnsupportedType,
+    #[error("too many uploads in progress")]
+    TooManyUploads,
+    #[error("unknown upload {0}")]
+    UnknownUpload(Uuid),
+    #[error("expected chunk at offset {expected}, got {got}")]
+    UnexpectedOffset { expected: u64, got: u64 },
+    #[error("content does not match the announced hash")]
+    HashMismatch,
+    #[error("media metadata could not be parsed")]
+    Malformed,
+    #[error("media not found")]
+    NotFound,
+}
+
+/// What clients learn about a stored blob.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub hash: HashCode,
+    pub mime_type: String,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Thumbnails derived from this blob; each is a blob of its own,
+    /// referenced by the original for as long as it is stored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<MediaVariant>,
+}
+
+#[derive(Debug, Clone)]
+struct MediaBlob {
+    info: MediaInfo,
+    data: Bytes,
+    /// Posts, messages and avatars pointing at this blob.
+    refs: u32,
+    /// Peers who uploaded this content, and may read it before attaching it.
+    uploaders: Vec<PeerId>,
+}
+
+/// Progress of an upload, acknowledged after every chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadStatus {
+    pub upload_id: Uuid,
+    pub size: u64,
+    pub received: u64,
+    pub chunk_size: usize,
+}
+
+struct PendingUpload {
+    owner: PeerId,
+    size: u64,
+    expected_hash: Option<HashCode>,
+    keep_location: bool,
+    hasher: HashContext,
+    data: Vec<u8>,
+    started_at: DateTime<Utc>,
+}
+
+impl PendingUpload {
+    fn status(&self, upload_id: Uuid) -> UploadStatus {
+        UploadStatus {
+            upload_id,
+            size: self.size,
+            received: self.data.len() as u64,
+            chunk_size: MEDIA_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Outcome of feeding a chunk into an upload.
+#[derive(Debug, Clone)]
+pub enum UploadProgress {
+    Partial(UploadStatus),
+    Complete(MediaInfo),
+}
+
+/// Content-addressed blob store: every blob is keyed by the `HashCode` of its
+/// bytes, so identical uploads share storage.
+#[derive(Clone, Default)]
+pub struct MediaStore {
+    blobs: Arc<RwLock<HashMap<HashCode, MediaBlob>>>,
+    uploads: Arc<Mutex<HashMap<Uuid, PendingUpload>>>,
+    /// References to blobs still being fetched from other peers.
+    pending_refs: Arc<Mutex<HashMap<HashCode, u32>>>,
+}
+
+impl std::fmt::Debug for MediaStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MediaStore")
+            .field("blobs", &self.blobs.read().len())
+            .field("uploads", &self.uploads.lock().len())
+            .finish()
+    }
+}
+
+impl MediaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens an upload of `size` bytes. When `expected_hash` is given the
+    /// upload fails unless the content hashes to it. Location data is
+    /// stripped from pictures unless `keep_location` is set, in which case
+    /// the stored blob hashes differently from what was sent.
+    pub fn start_upload(
+        &self,
+        owner: PeerId,
+        size: u64,
+        expected_hash: Option<HashCode>,
+        keep_location: bool,
+    ) -> Result<UploadStatus, MediaError> {
+        if size == 0 {
+            return Err(MediaError::UnsupportedType);
+        }
+        if size > MAX_MEDIA_SIZE {
+            return Err(MediaError::TooLarge);
+        }
+
+        let mut uploads = self.uploads.lock();
+        if uploads.values().filter(|u| u.owner == owner).count() >= MAX_PENDING_UPLOADS {
+            return Err(MediaError::TooManyUploads);
+        }
+
+        let upload_id = Uuid::new_v4();
+        let upload = PendingUpload {
+            owner,
+            size,
+            expected_hash,
+            keep_location,
+            hasher: HashContext::new(),
+            data: Vec::new(),
+            started_at: Utc::now(),
+        };
+        let status = upload.status(upload_id);
+        uploads.insert(upload_id, upload);
+        Ok(status)
+    }
+
+    /// Appends a chunk; chunks must arrive in order. Any error aborts the
+    /// upload.
+    pub fn write_chunk(
+        &self,
+        owner: &PeerId,
+        upload_id: Uuid,
+        offset: u64,
+        chunk: &[u8],
+    ) -> Result<UploadProgress, MediaError> {
+        let mut uploads = self.uploads.lock();
+        let upload = uploads
+            .get_mut(&upload_id)
+            .filter(|u| u.owner == *owner)
+            .ok_or(MediaError::UnknownUpload(upload_id))?;
+
+        let received = upload.data.len() as u64;
+        if offset != received {
+            uploads.remove(&upload_id);
+            return Err(MediaError::UnexpectedOffset {
+                expected: received,
+                got: offset,
+            });
+        }
+        if chunk.len() > MEDIA_CHUNK_SIZE || received + chunk.len() as u64 > upload.size {
+            uploads.remove(&upload_id);
+            return Err(MediaError::TooLarge);
+        }
+
+        upload.hasher.update(chunk);
+        upload.data.extend_from_slice(chunk);
+        if (upload.data.len() as u64) < upload.size {
+            return Ok(UploadProgress::Partial(upload.status(upload_id)));
+        }
+
+        let upload = uploads.remove(&upload_id).expect("upload present");
+        drop(uploads);
+        self.finish_upload(upload).map(UploadProgress::Complete)
+    }
+
+    fn finish_upload(&self, upload: PendingUpload) -> Result<MediaInfo, MediaError> {
+        let hash = upload.hasher.finish();
+        if upload.expected_hash.as_ref().is_some_and(|h| *h != hash) {
+            return Err(MediaError::HashMismatch);
+        }
+        let mime_type = sniff_mime_type(&upload.data).ok_or(MediaError::UnsupportedType)?;
+        if !upload.keep_location {
+            match strip_location(mime_type, &upload.data) {
+                Stripped::Unchanged => {}
+                Stripped::Stripped(stripped) => {
+                    return Ok(self.insert(
+                        HashCode::generate(&stripped),
+                        mime_type,
+                        stripped.into(),
+                        Some(&upload.owner),
+                    ));
+                }
+                // It may still carry a location, so it is not stored as is.
+                Stripped::Malformed => return Err(MediaError::Malformed),
+            }
+        }
+        Ok(self.insert(
+            hash,
+            mime_type,
+            Bytes::from(upload.data),
+            Some(&upload.owner),
+        ))
+    }
+
+    /// Stores `data` in one piece, e.g. media fetched from another peer.
+    /// The content is kept as is so it still matches the hash it was
+    /// requested by.
+    pub fn put(&self, data: Bytes) -> Result<MediaInfo, MediaError> {
+        if data.len() as u64 > MAX_MEDIA_SIZE {
+            return Err(MediaError::TooLarge);
+        }
+        let mime_type = sniff_mime_type(&data).ok_or(MediaError::UnsupportedType)?;
+        Ok(self.insert(HashCode::generate(&data), mime_type, data, None))
+    }
+
+    fn insert(
+        &self,
+        hash: HashCode,
+        mime_type: &str,
+        data: Bytes,
+        uploader: Option<&PeerId>,
+    ) -> MediaInfo {
+        // Decoding is slow, so it happens before taking the lock; a blob
+        // stored meanwhile wins and the work is discarded.
+        let metadata = (!self.contains(&hash)).then(|| analyze(mime_type, &data));
+
+        let mut blobs = self.blobs.write();
+        let pending = self.pending_refs.lock().remove(&hash).unwrap_or(0);
+        if let Some(blob) = blobs.get_mut(&hash) {
+            blob.refs += pending;
+            if let Some(uploader) = uploader
+                && !blob.uploaders.contains(uploader)
+            {
+                blob.uploaders.push(uploader.clone());
+            }
+            return blob.info.clone();
+        }
+
+        let metadata = metadata.unwrap_or_else(|| analyze(mime_type, &data));
+        let now = Utc::now();
+        let variants = metadata
+            .thumbnails
+            .into_iter()
+            .map(|thumb| {
+                let variant = MediaVariant {
+                    kind: thumb.kind,
+                    hash: thumb.hash.clone(),
+                    mime_type: thumb.mime_type.to_string(),
+                    width: thumb.width,
+                    height: thumb.height,
+                };
+                let blob = blobs
+                    .entry(thumb.hash.clone())
+                    .or_insert_with(|| MediaBlob {
+                        info: MediaInfo {
+                            hash: thumb.hash,
+                            mime_type: thumb.mime_type.to_string(),
+                            size: thumb.data.len() as u64,
+                            created_at: now,
+                            width: Some(thumb.width),
+                            height: Some(thumb.height),
+                            duration_ms: None,
+                            variants: Vec::new(),
+                        },
+                        data: thumb.data,
+                        refs: 0,
+                        uploaders: Vec::new(),
+                    });
+                blob.refs += 1;
+                variant
+            })
+            .collect();
+
+        let info = MediaInfo {
+            hash: hash.clone(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            created_at: now,
+            width: metadata.width,
+            height: metadata.height,
+            duration_ms: metadata.duration_ms,
+            variants,
+        };
+        blobs.insert(
+            hash,
+            MediaBlob {
+                info: info.clone(),
+                data,
+                refs: pending,
+                uploaders: uploader.into_iter().cloned().collect(),
+            },
+        );
+        info
+    }
+
+    pub fn abort_upload(&self, upload_id: Uuid) {
+        self.uploads.lock().remove(&upload_id);
+    }
+
+    pub fn contains(&self, hash: &HashCode) -> bool {
+        self.blobs.read().contains_key(hash)
+    }
+
+    pub fn info(&self, hash: &HashCode) -> Option<MediaInfo> {
+        self.blobs.read().get(hash).map(|b| b.info.clone())
+    }
+
+    pub fn get(&self, hash: &HashCode) -> Option<Bytes> {
+        self.blobs.read().get(hash).map(|b| b.data.clone())
+    }
+
+    pub fn uploaded_by(&self, hash: &HashCode, peer: &PeerId) -> bool {
+        self.blobs
+            .read()
+            .get(hash)
+            .is_some_and(|b| b.uploaders.contains(peer))
+    }
+
+    /// `hash` followed by the blobs it is a variant of.
+    pub fn sources(&self, hash: &HashCode) -> Vec<HashCode> {
+        let blobs = self.blobs.read();
+        std::iter::once(hash.clone())
+            .chain(
+                blobs
+                    .iter()
+                    .filter(|(_, b)| b.info.variants.iter().any(|v| v.hash == *hash))
+                    .map(|(h, _)| h.clone()),
+            )
+            .collect()
+    }
+
+    /// Returns up to `length` bytes starting at `offset`.
+    pub fn read(&self, hash: &HashCode, offset: u64, length: u64) -> Result<Bytes, MediaError> {
+        let data = self.get(hash).ok_or(MediaError::NotFound)?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(length as usize).min(data.len());
+        Ok(data.slice(start..end))
+    }
+
+    /// Takes a reference on each blob; fails without side effects if any
+    /// of them is unknown.
+    pub fn retain(&self, hashes: &[HashCode]) -> Result<(), MediaError> {
+        let mut blobs = self.blobs.write();
+        if !hashes.iter().all(|h| blobs.contains_key(h)) {
+            return Err(MediaError::NotFound);
+        }
+        for hash in hashes {
+            if let Some(blob) = blobs.get_mut(hash) {
+                blob.refs += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a reference on each blob, including ones not stored yet; those
+    /// are returned so the caller can fetch them, and gain the reference once
+    /// they arrive.
+    pub fn retain_remote(&self, hashes: &[HashCode]) -> Vec<HashCode> {
+        let mut blobs = self.blobs.write();
+        let mut pending = self.pending_refs.lock();
+        let mut missing = Vec::new();
+        for hash in hashes {
+            match blobs.get_mut(hash) {
+                Some(blob) => blob.refs += 1,
+                None => {
+                    *pending.entry(hash.clone()).or_default() += 1;
+                    missing.push(hash.clone());
+                }
+            }
+        }
+        missing
+    }
+
+    /// Drops the references [`retain_remote`](Self::retain_remote) took on
+    /// `hash` when it cannot be fetched.
+    pub fn release_pending(&self, hash: &HashCode) {
+        self.pending_refs.lock().remove(hash);
+    }
+
+    /// Drops references taken with [`retain`](Self::retain). Blobs left
+    /// without references are removed by the next garbage collection.
+    pub fn release(&self, hashes: &[HashCode]) {
+        let mut blobs = self.blobs.write();
+        for hash in hashes {
+            if let Some(blob) = blobs.get_mut(hash) {
+                blob.refs = blob.refs.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Removes unreferenced blobs older than `grace`, which gives clients
+    /// time to attach fresh uploads, and uploads idle for as long. Variants
+    /// of removed blobs lose their reference and go in a later run. Returns
+    /// the number of blobs removed.
+    pub fn collect_garbage(&self, grace: Duration) -> usize {
+        let cutoff = Utc::now() - grace;
+        self.uploads.lock().retain(|_, u| u.started_at > cutoff);
+
+        let mut blobs = self.blobs.write();
+        let expired: Vec<HashCode> = blobs
+            .iter()
+            .filter(|(_, b)| b.refs == 0 && b.info.created_at <= cutoff)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        for hash in &expired {
+            let Some(blob) = blobs.remove(hash) else {
+                continue;
+            };
+            for variant in blob.info.variants {
+                if let Some(derived) = blobs.get_mut(&variant.hash) {
+                    derived.refs = derived.refs.saturating_sub(1);
+                }
+            }
+        }
+        expired.len()
+    }
+}
+
+/// Identifies the supported formats from their magic numbers; the type a
+/// client claims is never trusted.
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"\x89PNG\r\n\x1A\n", "image/png"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"\x1A\x45\xDF\xA3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"%PDF-", "application/pdf"),
+    ];
+
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(mime);
+    }
+    if data.len() >= 12 && data.starts_with(b"RIFF") {
+        match &data[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            _ => {}
+        }
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some(match &data[8..12] {
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        });
+    }
+    if data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0 {
+        return Some("audio/mpeg");
+    }
+    None
+}
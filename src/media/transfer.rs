@@ -0,0 +1,526 @@
+use crate::gnunet::{
+    CadetMessage, CadetService, ChannelId, FILESHARE_PORT, HASH_SIZE, HashCode, PeerIdentity,
+};
+use crate::media::{MAX_MEDIA_SIZE, MEDIA_CHUNK_SIZE, MediaStore};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::channel::mpsc::Receiver;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Blobs fetched from other peers at the same time.
+pub const MAX_CONCURRENT_TRANSFERS: usize = 4;
+/// Chunk requests kept in flight per transfer.
+const CHUNK_WINDOW: usize = 8;
+/// How often a chunk that failed its hash check is requested again.
+const MAX_CHUNK_RETRIES: u32 = 3;
+/// How long an active transfer may go without a reply before it is
+/// requeued by [`MediaTransfers::sweep`].
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a transfer is started without a verified chunk arriving
+/// before it is given up.
+const MAX_TRANSFER_ATTEMPTS: u32 = 5;
+
+const GET_MANIFEST: u8 = 1;
+const MANIFEST: u8 = 2;
+const GET_CHUNK: u8 = 3;
+const CHUNK: u8 = 4;
+const NOT_FOUND: u8 = 5;
+
+/// Messages exchanged on `FILESHARE_PORT`. The manifest lists the hash of
+/// every `MEDIA_CHUNK_SIZE` chunk so each one is checked on arrival.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileshareMessage {
+    GetManifest {
+        hash: HashCode,
+    },
+    Manifest {
+        hash: HashCode,
+        size: u64,
+        chunk_hashes: Vec<HashCode>,
+    },
+    GetChunk {
+        hash: HashCode,
+        index: u32,
+    },
+    Chunk {
+        hash: HashCode,
+        index: u32,
+        data: Bytes,
+    },
+    NotFound {
+        hash: HashCode,
+    },
+}
+
+impl FileshareMessage {
+    pub fn hash(&self) -> &HashCode {
+        match self {
+            Self::GetManifest { hash }
+            | Self::Manifest { hash, .. }
+            | Self::GetChunk { hash, .. }
+            | Self::Chunk { hash, .. }
+            | Self::NotFound { hash } => hash,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        let tag = match self {
+            Self::GetManifest { .. } => GET_MANIFEST,
+            Self::Manifest { .. } => MANIFEST,
+            Self::GetChunk { .. } => GET_CHUNK,
+            Self::Chunk { .. } => CHUNK,
+            Self::NotFound { .. } => NOT_FOUND,
+        };
+        buf.put_u8(tag);
+        buf.put_slice(self.hash().as_bytes());
+        match self {
+            Self::Manifest {
+                size, chunk_hashes, ..
+            } => {
+                buf.put_u64(*size);
+                buf.put_u32(chunk_hashes.len() as u32);
+                for chunk in chunk_hashes {
+                    buf.put_slice(chunk.as_bytes());
+                }
+            }
+            Self::GetChunk { index, .. } => buf.put_u32(*index),
+            Self::Chunk { index, data, .. } => {
+                buf.put_u32(*index);
+                buf.put_slice(data);
+            }
+            Self::GetManifest { .. } | Self::NotFound { .. } => {}
+        }
+        buf.to_vec()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut buf = Bytes::copy_from_slice(data);
+        if buf.remaining() < 1 + HASH_SIZE {
+            return None;
+        }
+        let tag = buf.get_u8();
+        let hash = read_hash(&mut buf);
+        let message = match tag {
+            GET_MANIFEST => Self::GetManifest { hash },
+            MANIFEST => {
+                if buf.remaining() < 12 {
+                    return None;
+                }
+                let size = buf.get_u64();
+                let count = buf.get_u32() as usize;
+                if buf.remaining() != count * HASH_SIZE {
+                    return None;
+                }
+                let chunk_hashes = (0..count).map(|_| read_hash(&mut buf)).collect();
+                Self::Manifest {
+                    hash,
+                    size,
+                    chunk_hashes,
+                }
+            }
+            GET_CHUNK if buf.remaining() == 4 => Self::GetChunk {
+                hash,
+                index: buf.get_u32(),
+            },
+            CHUNK if buf.remaining() >= 4 => Self::Chunk {
+                hash,
+                index: buf.get_u32(),
+                data: buf,
+            },
+            NOT_FOUND => Self::NotFound { hash },
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+fn read_hash(buf: &mut Bytes) -> HashCode {
+    let mut hash = [0u8; HASH_SIZE];
+    buf.copy_to_slice(&mut hash);
+    HashCode::from_bytes(hash)
+}
+
+#[derive(Debug, Clone)]
+struct Manifest {
+    size: u64,
+    chunk_hashes: Vec<HashCode>,
+}
+
+/// A blob being fetched. Verified chunks survive a lost channel so the
+/// transfer resumes where it stopped.
+#[derive(Debug)]
+struct Transfer {
+    peer: PeerIdentity,
+    channel: Option<ChannelId>,
+    manifest: Option<Manifest>,
+    chunks: Vec<Option<Bytes>>,
+    in_flight: BTreeSet<u32>,
+    retries: HashMap<u32, u32>,
+    attempts: u32,
+    last_progress: Instant,
+}
+
+impl Transfer {
+    fn new(peer: PeerIdentity) -> Self {
+        Self {
+            peer,
+            channel: None,
+            manifest: None,
+            chunks: Vec::new(),
+            in_flight: BTreeSet::new(),
+            retries: HashMap::new(),
+            attempts: 0,
+            last_progress: Instant::now(),
+        }
+    }
+
+    fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(i, c)| c.is_none() && !self.in_flight.contains(&(*i as u32)))
+            .map(|(i, _)| i as u32)
+    }
+
+    fn is_complete(&self) -> bool {
+        !self.chunks.is_empty() && self.chunks.iter().all(Option::is_some)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TransferState {
+    active: HashMap<HashCode, Transfer>,
+    /// Waiting for a free slot, including transfers whose channel dropped.
+    queued: VecDeque<(HashCode, Transfer)>,
+}
+
+impl TransferState {
+    fn knows(&self, hash: &HashCode) -> bool {
+        self.active.contains_key(hash) || self.queued.iter().any(|(h, _)| h == hash)
+    }
+
+    /// The active transfer of `hash`, if it runs on `channel`.
+    fn on_channel(&mut self, hash: &HashCode, channel: ChannelId) -> Option<&mut Transfer> {
+        self.active
+            .get_mut(hash)
+            .filter(|t| t.channel == Some(channel))
+    }
+
+    /// The blob fetched over `channel`, if one of our transfers opened it.
+    fn fetching_over(&self, channel: ChannelId) -> Option<HashCode> {
+        self.active
+            .iter()
+            .find(|(_, t)| t.channel == Some(channel))
+            .map(|(h, _)| h.clone())
+    }
+}
+
+/// Serves local blobs to other peers and fetches missing ones from them over
+/// `FILESHARE_PORT`.
+pub struct MediaTransfers {
+    store: MediaStore,
+    cadet: Arc<RwLock<CadetService>>,
+    state: Mutex<TransferState>,
+}
+
+impl MediaTransfers {
+    pub fn new(store: MediaStore, cadet: Arc<RwLock<CadetService>>) -> Self {
+        Self {
+            store,
+            cadet,
+            state: Mutex::new(TransferState::default()),
+        }
+    }
+
+    pub fn listen(&self) -> Receiver<CadetMessage> {
+        self.cadet.write().open_port(FILESHARE_PORT)
+    }
+
+    /// Fetches `hash` from `peer` unless it is already stored or underway.
+    pub fn fetch(&self, hash: HashCode, peer: PeerIdentity) {
+        if self.store.contains(&hash) {
+            return;
+        }
+        let mut state = self.state.lock();
+        if state.knows(&hash) {
+            return;
+        }
+        state.queued.push_back((hash, Transfer::new(peer)));
+        self.start_queued(&mut state);
+    }
+
+    /// Serves requests arriving on channels other peers opened, and takes
+    /// replies only on the channel of the transfer they belong to.
+    pub fn handle_message(&self, msg: CadetMessage) {
+        let Some(message) = FileshareMessage::decode(&msg.data) else {
+            tracing::warn!("Malformed fileshare message on channel {}", msg.channel);
+            return;
+        };
+        let ours = self.state.lock().fetching_over(msg.channel);
+        match message {
+            FileshareMessage::GetManifest { .. } | FileshareMessage::GetChunk { .. }
+                if ours.is_some() =>
+            {
+                // A request on a channel we opened came back to us: the
+                // channel loops back to this node rather than reaching the
+                // peer.
+                let hash = ours.expect("transfer present");
+                tracing::warn!("No route to the peer holding media {}", hash);
+                self.abort(&hash);
+            }
+            FileshareMessage::GetManifest { hash } => self.serve_manifest(msg.channel, hash),
+            FileshareMessage::GetChunk { hash, index } => {
+                self.serve_chunk(msg.channel, hash, index)
+            }
+            _ if ours.as_ref() != Some(message.hash()) => {
+                tracing::debug!("Ignoring fileshare reply on channel {}", msg.channel);
+            }
+            FileshareMessage::Manifest {
+                hash,
+                size,
+                chunk_hashes,
+            } => self.receive_manifest(msg.channel, hash, Manifest { size, chunk_hashes }),
+            FileshareMessage::Chunk { hash, index, data } => {
+                self.receive_chunk(msg.channel, hash, index, data)
+            }
+            FileshareMessage::NotFound { hash } => {
+                tracing::warn!("Peer does not have media {}", hash);
+                self.abort(&hash);
+            }
+        }
+    }
+
+    /// Requeues active transfers that got no reply for [`TRANSFER_TIMEOUT`],
+    /// and retries queued ones.
+    pub fn sweep(&self) {
+        let mut state = self.state.lock();
+        let stalled: Vec<ChannelId> = state
+            .active
+            .values()
+            .filter(|t| t.last_progress.elapsed() >= TRANSFER_TIMEOUT)
+            .filter_map(|t| t.channel)
+            .collect();
+        for channel in stalled {
+            tracing::debug!("Media transfer on channel {} stalled", channel);
+            self.channel_closed(&mut state, channel);
+        }
+        self.start_queued(&mut state);
+    }
+
+    /// Puts the transfer using `channel` back in the queue, keeping the
+    /// chunks received so far.
+    fn channel_closed(&self, state: &mut TransferState, channel: ChannelId) {
+        if let Some(hash) = state.fetching_over(channel)
+            && let Some(mut transfer) = state.active.remove(&hash)
+        {
+            self.close(&transfer);
+            transfer.channel = None;
+            transfer.in_flight.clear();
+            state.queued.push_back((hash, transfer));
+        }
+    }
+
+    fn serve_manifest(&self, channel: ChannelId, hash: HashCode) {
+        let reply = match self.store.get(&hash) {
+            Some(data) => FileshareMessage::Manifest {
+                hash,
+                size: data.len() as u64,
+                chunk_hashes: data
+                    .chunks(MEDIA_CHUNK_SIZE)
+                    .map(HashCode::generate)
+                    .collect(),
+            },
+            None => FileshareMessage::NotFound { hash },
+        };
+        self.send(channel, &reply);
+    }
+
+    fn serve_chunk(&self, channel: ChannelId, hash: HashCode, index: u32) {
+        let offset = index as u64 * MEDIA_CHUNK_SIZE as u64;
+        let reply = match self.store.read(&hash, offset, MEDIA_CHUNK_SIZE as u64) {
+            Ok(data) if !data.is_empty() => FileshareMessage::Chunk { hash, index, data },
+            _ => FileshareMessage::NotFound { hash },
+        };
+        self.send(channel, &reply);
+    }
+
+    fn receive_manifest(&self, channel: ChannelId, hash: HashCode, manifest: Manifest) {
+        let expected_chunks = manifest.size.div_ceil(MEDIA_CHUNK_SIZE as u64) as usize;
+        if manifest.size == 0
+            || manifest.size > MAX_MEDIA_SIZE
+            || manifest.chunk_hashes.len() != expected_chunks
+        {
+            tracing::warn!("Rejecting invalid manifest for {}", hash);
+            self.abort(&hash);
+            return;
+        }
+
+        let mut state = self.state.lock();
+        let Some(transfer) = state.on_channel(&hash, channel) else {
+            return;
+        };
+        // A resumed transfer keeps its chunks only if the manifest is unchanged.
+        let unchanged = transfer
+            .manifest
+            .as_ref()
+            .is_some_and(|m| m.chunk_hashes == manifest.chunk_hashes);
+        if !unchanged {
+            transfer.chunks = vec![None; expected_chunks];
+            transfer.retries.clear();
+        }
+        transfer.manifest = Some(manifest);
+        transfer.in_flight.clear();
+        transfer.last_progress = Instant::now();
+        if !self.request_chunks(&hash, transfer) {
+            self.channel_closed(&mut state, channel);
+            self.start_queued(&mut state);
+        }
+    }
+
+    fn receive_chunk(&self, channel: ChannelId, hash: HashCode, index: u32, data: Bytes) {
+        let mut state = self.state.lock();
+        let Some(transfer) = state.on_channel(&hash, channel) else {
+            return;
+        };
+        let Some(manifest) = &transfer.manifest else {
+            return;
+        };
+        let Some(expected) = manifest.chunk_hashes.get(index as usize) else {
+            return;
+        };
+        transfer.in_flight.remove(&index);
+        transfer.last_progress = Instant::now();
+
+        if HashCode::generate(&data) == *expected {
+            transfer.chunks[index as usize] = Some(data);
+            transfer.attempts = 0;
+        } else {
+            let retries = transfer.retries.entry(index).or_default();
+            *retries += 1;
+            if *retries > MAX_CHUNK_RETRIES {
+                tracing::warn!("Chunk {} of {} failed verification, giving up", index, hash);
+                drop(state);
+                self.abort(&hash);
+                return;
+            }
+        }
+
+        if !transfer.is_complete() {
+            if !self.request_chunks(&hash, transfer) {
+                self.channel_closed(&mut state, channel);
+                self.start_queued(&mut state);
+            }
+            return;
+        }
+
+        let transfer = state.active.remove(&hash).expect("transfer present");
+        self.close(&transfer);
+        self.start_queued(&mut state);
+        drop(state);
+        self.finish(hash, transfer);
+    }
+
+    /// Checks the assembled blob against the hash it was requested by
+    /// before it reaches the store.
+    fn finish(&self, hash: HashCode, transfer: Transfer) {
+        let mut data = BytesMut::new();
+        for chunk in transfer.chunks.into_iter().flatten() {
+            data.put_slice(&chunk);
+        }
+        let data = data.freeze();
+        if HashCode::generate(&data) != hash {
+            tracing::warn!("Media from {} does not match {}", transfer.peer, hash);
+            self.store.release_pending(&hash);
+            return;
+        }
+        match self.store.put(data) {
+            Ok(info) => tracing::info!("Fetched media {} ({} bytes)", hash, info.size),
+            Err(e) => {
+                tracing::warn!("Discarding fetched media {}: {}", hash, e);
+                self.store.release_pending(&hash);
+            }
+        }
+    }
+
+    /// Gives up on `hash`, dropping the references waiting for it.
+    fn abort(&self, hash: &HashCode) {
+        let mut state = self.state.lock();
+        if let Some(transfer) = state.active.remove(hash) {
+            self.close(&transfer);
+        }
+        state.queued.retain(|(h, _)| h != hash);
+        self.store.release_pending(hash);
+        self.start_queued(&mut state);
+    }
+
+    /// Opens channels for queued transfers while slots are free. A transfer
+    /// whose request cannot be sent waits in the queue for the next call,
+    /// until it has used up its attempts.
+    fn start_queued(&self, state: &mut TransferState) {
+        let mut waiting = VecDeque::new();
+        while state.active.len() < MAX_CONCURRENT_TRANSFERS {
+            let Some((hash, mut transfer)) = state.queued.pop_front() else {
+                break;
+            };
+            if transfer.attempts >= MAX_TRANSFER_ATTEMPTS {
+                tracing::warn!("Giving up on media {} from {}", hash, transfer.peer);
+                self.store.release_pending(&hash);
+                continue;
+            }
+            transfer.attempts += 1;
+            let channel = self
+                .cadet
+                .write()
+                .create_channel(transfer.peer.clone(), FILESHARE_PORT);
+            if !self.send(
+                channel.id,
+                &FileshareMessage::GetManifest { hash: hash.clone() },
+            ) {
+                self.cadet.write().destroy_channel(channel.id);
+                waiting.push_back((hash, transfer));
+                continue;
+            }
+            transfer.channel = Some(channel.id);
+            transfer.last_progress = Instant::now();
+            state.active.insert(hash, transfer);
+        }
+        state.queued.extend(waiting);
+    }
+
+    /// Requests the next missing chunks; false if the channel is gone.
+    fn request_chunks(&self, hash: &HashCode, transfer: &mut Transfer) -> bool {
+        let Some(channel) = transfer.channel else {
+            return false;
+        };
+        let room = CHUNK_WINDOW.saturating_sub(transfer.in_flight.len());
+        let next: Vec<u32> = transfer.missing().take(room).collect();
+        for index in next {
+            transfer.in_flight.insert(index);
+            let request = FileshareMessage::GetChunk {
+                hash: hash.clone(),
+                index,
+            };
+            if !self.send(channel, &request) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn close(&self, transfer: &Transfer) {
+        if let Some(channel) = transfer.channel {
+            self.cadet.write().destroy_channel(channel);
+        }
+    }
+
+    fn send(&self, channel: ChannelId, message: &FileshareMessage) -> bool {
+        let sent = self.cadet.write().send(channel, message.encode());
+        if !sent {
+            tracing::warn!("Failed to send fileshare message on channel {}", channel);
+        }
+        sent
+    }
+}
@@ -1,27 +1,547 @@
-use crate::gnunet::PeerIdentity;
+use crate::gnunet::{
+    APEX_LABEL, CadetMessage, CadetService, CryptoError, Ego, EgoEvent, GnsError, GnsService,
+    HashCode, IdentityError, IdentityService, PeerIdentity, PublicKey,
+    SIGNATURE_PURPOSE_INVITE_LINK, SOCIAL_PORT, SocialCadetMessage, TombstoneTarget,
+    is_valid_label,
+};
+use crate::media::{MEDIA_CHUNK_SIZE, MediaError, MediaInfo, MediaTransfers, UploadProgress};
 use crate::protocol::*;
 use crate::social::*;
+use futures::channel::mpsc::Receiver;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::sync::broadcast;
+use uuid::Uuid;
 
+/// Name of the ego whose zone holds the username delegations.
+pub const SERVER_EGO: &str = "gnunet-social";
+
+/// Reply levels `GetThread` returns unless asked otherwise.
+const DEFAULT_THREAD_DEPTH: u32 = 3;
+/// Deepest a thread is walked, up or down.
+const MAX_THREAD_DEPTH: u32 = 16;
+/// Replies `GetThread` returns per post unless asked otherwise.
+const DEFAULT_THREAD_LIMIT: u32 = 50;
+/// Most replies `GetThread` returns per post.
+const MAX_THREAD_LIMIT: u32 = 200;
+
+/// Egos authenticated on this connection: the account's own peer and the
+/// personas created under it, one of which acts unless a request picks another.
+#[derive(Debug, Default)]
+struct Session {
+    egos: Vec<PeerIdentity>,
+    active: Option<PeerIdentity>,
+}
+
+/// Room messages handed to each member's session since the last
+/// [`MessageHandler::flush_deliveries`], by room and member.
+type PendingDeliveries = HashMap<(Uuid, PeerIdentity), Vec<Uuid>>;
+
+#[derive(Clone)]
 pub struct MessageHandler {
     store: SocialStore,
-    current_peer: Arc<parking_lot::RwLock<Option<PeerIdentity>>>,
+    session: Arc<parking_lot::RwLock<Session>>,
+    identity: Arc<IdentityService>,
+    gns: Arc<parking_lot::RwLock<GnsService>>,
+    events: broadcast::Sender<Dispatch>,
+    cadet: Arc<parking_lot::RwLock<CadetService>>,
+    transfers: Arc<MediaTransfers>,
+    deliveries: Arc<parking_lot::Mutex<PendingDeliveries>>,
 }
 
 impl MessageHandler {
     pub fn new(store: SocialStore) -> Self {
+        Self::with_identity(store, IdentityService::new())
+    }
+
+    pub fn with_identity(store: SocialStore, identity: IdentityService) -> Self {
+        let mut gns = GnsService::new();
+        let server_ego = match identity.get_ego(SERVER_EGO) {
+            Some(ego) => Ok(ego),
+            None => identity.create_ego(SERVER_EGO),
+        };
+        match server_ego {
+            Ok(ego) => {
+                identity.set_default(SERVER_EGO);
+                gns.set_local_zone(ego.public_key);
+            }
+            Err(e) => tracing::error!("Failed to set up server ego: {}", e),
+        }
+
+        let cadet = Arc::new(parking_lot::RwLock::new(CadetService::new()));
+        let transfers = Arc::new(MediaTransfers::new(store.media.clone(), cadet.clone()));
+
         Self {
             store,
-            current_peer: Arc::new(parking_lot::RwLock::new(None)),
+            session: Arc::new(parking_lot::RwLock::new(Session::default())),
+            identity: Arc::new(identity),
+            gns: Arc::new(parking_lot::RwLock::new(gns)),
+            events: broadcast::channel(1024).0,
+            cadet,
+            transfers,
+            deliveries: Arc::new(parking_lot::Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// A handler for a new client connection: it shares everything with
+    /// this one except the session, so each connection authenticates on its
+    /// own.
+    pub fn for_connection(&self) -> Self {
+        Self {
+            session: Arc::new(parking_lot::RwLock::new(Session::default())),
+            ..self.clone()
+        }
+    }
+
+    /// Channel carrying the events raised while handling requests.
+    pub fn event_sender(&self) -> broadcast::Sender<Dispatch> {
+        self.events.clone()
+    }
+
+    /// Whether this connection's session is among `audience`.
+    pub fn accepts(&self, audience: &Audience) -> bool {
+        let session = self.session.read();
+        match audience {
+            Audience::Everyone => true,
+            Audience::Peers(peers) => session.egos.iter().any(|ego| peers.contains(ego)),
+            Audience::PostViewers(post) => session
+                .egos
+                .iter()
+                .any(|ego| self.store.can_view(post, Some(ego))),
+        }
+    }
+
+    pub fn listen_social(&self) -> Receiver<CadetMessage> {
+        self.cadet.write().open_port(SOCIAL_PORT)
+    }
+
+    pub fn listen_fileshare(&self) -> Receiver<CadetMessage> {
+        self.transfers.listen()
+    }
+
+    pub fn handle_fileshare(&self, msg: CadetMessage) {
+        self.transfers.handle_message(msg);
+    }
+
+    pub fn sweep_transfers(&self) {
+        self.transfers.sweep();
+    }
+
+    /// Handles content federated from the peer at the other end of the
+    /// channel. Blocks apply to the users they were set by: direct messages,
+    /// reactions and mentions that reach a blocker are dropped, and posts are
+    /// hidden from blockers as they are viewed.
+    pub fn handle_cadet_message(&self, msg: CadetMessage) {
+        let Some(from) = self
+            .cadet
+            .read()
+            .channel(msg.channel)
+            .map(|c| c.peer.clone())
+        else {
+            return;
+        };
+        let message = match serde_json::from_slice::<SocialCadetMessage>(&msg.data) {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::warn!("Malformed message from {}: {}", from, e);
+                return;
+            }
+        };
+
+        match message {
+            SocialCadetMessage::Post {
+                post_id,
+                author,
+                content,
+                media_hashes,
+            } => {
+                if author != from.to_string() {
+                    tracing::warn!("Dropping post {} relayed by {}", post_id, from);
+                    return;
+                }
+                self.receive_post(from, post_id, content, media_hashes);
+            }
+            SocialCadetMessage::Tombstone { target } => self.receive_tombstone(from, target),
+            SocialCadetMessage::Reaction {
+                target,
+                emoji,
+                reacted,
+            } => {
+                if !is_valid_reaction(&emoji) || self.check_reaction_target(&target, &from).is_err()
+                {
+                    tracing::warn!("Dropping reaction on {:?} from {}", target, from);
+                    return;
+                }
+                self.apply_reaction(target, &emoji, &from, reacted);
+            }
+            SocialCadetMessage::PrivateMessage { message } => {
+                self.receive_private_message(from, message)
+            }
+            SocialCadetMessage::Receipt { target, state, at } => {
+                self.apply_receipt(&target, &from, state, at.min(chrono::Utc::now()));
+            }
+            other => tracing::debug!("Ignoring federated message {:?}", other),
+        }
+    }
+
+    fn receive_post(
+        &self,
+        from: PeerIdentity,
+        post_id: Uuid,
+        content: String,
+        media_hashes: Vec<HashCode>,
+    ) {
+        if self.store.get_post(post_id).is_some() {
+            return;
+        }
+        for hash in self.store.media.retain_remote(&media_hashes) {
+            self.transfers.fetch(hash, from.clone());
+        }
+
+        let entities = self.extract_entities(&content, &from);
+        let mut post = Post::new(from, content);
+        post.id = post_id;
+        post.entities = entities;
+        post.media_hashes = media_hashes;
+        self.store.add_post(post.clone());
+        self.notify_mentions(&post, &[]);
+        self.emit(
+            Audience::PostViewers(Box::new(post.clone())),
+            EventMessage::NewPost { post },
+        );
+    }
+
+    /// Takes in a direct message `from` sent to a user here, and tells
+    /// `from` it was delivered.
+    fn receive_private_message(&self, from: PeerIdentity, mut message: PrivateMessage) {
+        if message.sender_id != from
+            || self.store.get_user(&message.recipient_id).is_none()
+            || self.store.blocked_between(&from, &message.recipient_id)
+            || message
+                .content
+                .verify(&message.sender_id, &message.recipient_id)
+                .is_err()
+        {
+            tracing::warn!("Dropping private message {} from {}", message.id, from);
+            return;
+        }
+        if self.store.get_private_message(message.id).is_some() {
+            return;
+        }
+        for hash in self.store.media.retain_remote(&message.media_hashes) {
+            self.transfers.fetch(hash, from.clone());
+        }
+
+        let now = chrono::Utc::now();
+        message.state = DeliveryState::Sent;
+        message.delivered_at = None;
+        message.read_at = None;
+        message.edited_at = None;
+        message.history.clear();
+        message.deleted_at = None;
+        message.advance(DeliveryState::Delivered, now);
+        self.store.add_private_message(message.clone());
+        self.emit(
+            Audience::Peers(vec![
+                message.sender_id.clone(),
+                message.recipient_id.clone(),
+            ]),
+            EventMessage::NewPrivateMessage {
+                message: message.clone(),
+            },
+        );
+        self.send_to(
+            &from,
+            &SocialCadetMessage::Receipt {
+                target: ReceiptTarget::PrivateMessage {
+                    message_ids: vec![message.id],
+                },
+                state: DeliveryState::Delivered,
+                at: now,
+            },
+        );
+    }
+
+    /// Records that `peer` got the target messages as far as `state` and
+    /// tells clients which ones moved on.
+    fn apply_receipt(
+        &self,
+        target: &ReceiptTarget,
+        peer: &PeerIdentity,
+        state: DeliveryState,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> Vec<Uuid> {
+        let (advanced, audience) = match target {
+            ReceiptTarget::PrivateMessage { message_ids } => {
+                let advanced = self
+                    .store
+                    .advance_private_messages(peer, message_ids, state, at);
+                let senders = advanced
+                    .iter()
+                    .filter_map(|id| self.store.get_private_message(*id))
+                    .map(|m| m.sender_id)
+                    .collect();
+                (advanced, Audience::Peers(senders).and(peer))
+            }
+            ReceiptTarget::Room {
+                room_id,
+                message_ids,
+            } => match self.store.get_room(*room_id) {
+                Some(room) if room.is_member(peer) => {
+                    let advanced =
+                        self.store
+                            .advance_room_messages(*room_id, peer, message_ids, state, at);
+                    let senders = advanced
+                        .iter()
+                        .filter_map(|id| self.store.get_message(*id))
+                        .map(|m| m.sender_id)
+                        .collect();
+                    (advanced, Audience::Peers(senders).and(peer))
+                }
+                _ => return Vec::new(),
+            },
+        };
+        if !advanced.is_empty() {
+            self.emit(
+                audience,
+                EventMessage::MessageReceipt {
+                    target: target.with_ids(advanced.clone()),
+                    peer_id: peer.to_string(),
+                    state,
+                    at,
+                },
+            );
+        }
+        advanced
+    }
+
+    /// Applies a deletion sent by `from`, which must be allowed to delete
+    /// the target here as well.
+    fn receive_tombstone(&self, from: PeerIdentity, target: TombstoneTarget) {
+        let allowed = match &target {
+            TombstoneTarget::Post { post_id } => self
+                .store
+                .get_post(*post_id)
+                .is_some_and(|p| p.author_id == from),
+            TombstoneTarget::Chat {
+                room_id,
+                message_id,
+            } => self
+                .store
+                .get_message(*message_id)
+                .filter(|m| m.room_id == *room_id)
+                .and_then(|m| self.store.get_room(m.room_id).map(|r| (m, r)))
+                .is_some_and(|(m, room)| {
+                    m.sender_id == from || room.has_role(&from, RoomRole::Moderator)
+                }),
+            TombstoneTarget::PrivateMessage { message_id } => self
+                .store
+                .get_private_message(*message_id)
+                .is_some_and(|m| m.sender_id == from),
+        };
+        if !allowed {
+            tracing::warn!("Dropping tombstone {:?} from {}", target, from);
+            return;
+        }
+
+        match target {
+            TombstoneTarget::Post { post_id } => {
+                self.tombstone_post(post_id);
+            }
+            TombstoneTarget::Chat { message_id, .. } => {
+                self.tombstone_message(message_id, &from);
+            }
+            TombstoneTarget::PrivateMessage { message_id } => {
+                self.tombstone_private_message(message_id);
+            }
+        }
+    }
+
+    /// Sends `message` to every peer connected on `SOCIAL_PORT`.
+    fn federate(&self, message: &SocialCadetMessage) {
+        match serde_json::to_vec(message) {
+            Ok(data) => self.cadet.write().broadcast(SOCIAL_PORT, data),
+            Err(e) => tracing::warn!("Failed to encode federated message: {}", e),
+        }
+    }
+
+    /// Sends `message` to `peer` alone over `SOCIAL_PORT`.
+    fn send_to(&self, peer: &PeerIdentity, message: &SocialCadetMessage) {
+        let data = match serde_json::to_vec(message) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("Failed to encode federated message: {}", e);
+                return;
+            }
+        };
+        let mut cadet = self.cadet.write();
+        let channel = cadet.channel_to(peer, SOCIAL_PORT);
+        if !cadet.send(channel.id, data) {
+            tracing::warn!("Failed to send federated message to {}", peer);
+        }
+    }
+
+    /// Records that `event` was handed to this connection's session: a room
+    /// message reaching a member is delivered to them once the deliveries
+    /// are flushed.
+    pub fn delivered(&self, event: &EventMessage) {
+        let EventMessage::NewRoomMessage { room_id, message } = event else {
+            return;
+        };
+        let Some(room) = self.store.get_room(*room_id) else {
+            return;
+        };
+        let egos = self.session.read().egos.clone();
+        for ego in egos
+            .iter()
+            .filter(|ego| room.is_member(ego) && **ego != message.sender_id)
+        {
+            self.queue_delivery(*room_id, ego, vec![message.id]);
+        }
+    }
+
+    fn queue_delivery(&self, room_id: Uuid, peer: &PeerIdentity, ids: Vec<Uuid>) {
+        if ids.is_empty() {
+            return;
+        }
+        self.deliveries
+            .lock()
+            .entry((room_id, peer.clone()))
+            .or_default()
+            .extend(ids);
+    }
+
+    /// Applies the queued deliveries, one receipt per room and member.
+    pub fn flush_deliveries(&self) {
+        let deliveries = std::mem::take(&mut *self.deliveries.lock());
+        let now = chrono::Utc::now();
+        for ((room_id, peer), message_ids) in deliveries {
+            let target = ReceiptTarget::Room {
+                room_id,
+                message_ids,
+            };
+            self.apply_receipt(&target, &peer, DeliveryState::Delivered, now);
+        }
+    }
+
+    fn emit(&self, audience: Audience, event: EventMessage) {
+        let _ = self.events.send(Dispatch { audience, event });
+    }
+
+    /// The members of the room `room_id`, or no one if it is gone.
+    fn room_audience(&self, room_id: Uuid) -> Audience {
+        match self.store.get_room(room_id) {
+            Some(room) => Audience::members(&room),
+            None => Audience::Peers(Vec::new()),
+        }
+    }
+
+    /// Stores `notification` for its recipient and pushes it along with the
+    /// new unread count, unless the store turns it away.
+    fn notify(&self, notification: Notification) {
+        let recipient = notification.recipient_id.clone();
+        if !self.store.add_notification(notification.clone()) {
+            return;
         }
+        self.emit(
+            Audience::Peers(vec![recipient.clone()]),
+            EventMessage::NewNotification { notification },
+        );
+        self.emit_unread_notifications(&recipient);
+    }
+
+    fn emit_unread_notifications(&self, peer: &PeerIdentity) {
+        self.emit(
+            Audience::Peers(vec![peer.clone()]),
+            EventMessage::UnreadNotifications {
+                peer_id: peer.to_string(),
+                unread: self.store.unread_notifications(peer),
+            },
+        );
     }
 
+    /// Authenticates `peer` as the account, along with its personas.
     pub fn set_current_peer(&self, peer: PeerIdentity) {
-        *self.current_peer.write() = Some(peer);
+        let mut egos = vec![peer.clone()];
+        egos.extend(self.store.get_personas(&peer));
+        *self.session.write() = Session {
+            egos,
+            active: Some(peer),
+        };
     }
 
     pub fn current_peer(&self) -> Option<PeerIdentity> {
-        self.current_peer.read().clone()
+        self.session.read().active.clone()
+    }
+
+    /// The peer a request acts as: the session ego named by `ego` (an ego
+    /// name or persona peer id), or the active ego when none is given.
+    fn acting_peer(&self, ego: Option<&str>) -> Result<PeerIdentity, ErrorResponse> {
+        let Some(name) = ego else {
+            return self
+                .current_peer()
+                .ok_or_else(|| ErrorResponse::new(401, "Not authenticated"));
+        };
+        let egos = self.session.read().egos.clone();
+        egos.into_iter()
+            .find(|peer| {
+                peer.to_string() == name
+                    || self
+                        .store
+                        .get_user(peer)
+                        .and_then(|user| self.ego_name(&user))
+                        .is_some_and(|ego_name| ego_name == name)
+            })
+            .ok_or_else(|| ErrorResponse::new(403, "Ego not in session"))
+    }
+
+    /// Name of the ego whose zone holds `user`'s profile.
+    fn ego_name(&self, user: &User) -> Option<String> {
+        self.identity
+            .list_egos()
+            .into_iter()
+            .find(|ego| ego.public_key.as_str() == user.gns_zone)
+            .map(|ego| ego.name)
+    }
+
+    fn ego_info(&self, peer: &PeerIdentity, active: Option<&PeerIdentity>) -> EgoInfo {
+        let user = self.store.get_user(peer);
+        EgoInfo {
+            name: user.as_ref().and_then(|u| self.ego_name(u)),
+            peer_id: peer.to_string(),
+            zone: user.as_ref().map(|u| u.gns_zone.clone()),
+            active: active == Some(peer),
+            user,
+        }
+    }
+
+    pub fn watch_egos(&self) -> Receiver<EgoEvent> {
+        self.identity.watch()
+    }
+
+    /// Keeps published profiles in step with egos changed outside the server,
+    /// e.g. with `gnunet-identity`.
+    pub fn handle_ego_event(&self, event: EgoEvent) {
+        match event {
+            EgoEvent::Added(ego) => tracing::info!("Ego added: {}", ego.name),
+            EgoEvent::Renamed { old_name, ego } => {
+                tracing::info!("Ego renamed: {} -> {}", old_name, ego.name)
+            }
+            EgoEvent::Deleted(ego) => {
+                tracing::info!("Ego deleted: {}", ego.name);
+                let zone = ego.public_key.as_str();
+                let owner = self
+                    .store
+                    .users
+                    .read()
+                    .values()
+                    .find(|u| u.gns_zone == zone)
+                    .cloned();
+                if let Some(user) = owner {
+                    self.gns.write().unpublish_profile(&user);
+                }
+            }
+        }
     }
 
     pub fn handle(&self, msg: ClientMessage) -> ServerMessage {
@@ -32,25 +552,83 @@ impl MessageHandler {
             ClientMessage::CreatePost(req) => self.handle_create_post(req),
             ClientMessage::GetFeed(req) => self.handle_get_feed(req),
             ClientMessage::GetPost(req) => self.handle_get_post(req),
+            ClientMessage::GetHashtagFeed(req) => self.handle_get_hashtag_feed(req),
+            ClientMessage::GetThread(req) => self.handle_get_thread(req),
+            ClientMessage::UndoRepost(req) => self.handle_undo_repost(req),
+            ClientMessage::EditPost(req) => self.handle_edit_post(req),
+            ClientMessage::DeletePost(req) => self.handle_delete_post(req),
             ClientMessage::LikePost(req) => self.handle_like_post(req),
+            ClientMessage::React(req) => self.handle_react(req),
+            ClientMessage::Unreact(req) => self.handle_unreact(req),
             ClientMessage::CreateRoom(req) => self.handle_create_room(req),
             ClientMessage::GetRooms(req) => self.handle_get_rooms(req),
             ClientMessage::JoinRoom(req) => self.handle_join_room(req),
             ClientMessage::LeaveRoom(req) => self.handle_leave_room(req),
+            ClientMessage::InviteToRoom(req) => self.handle_invite_to_room(req),
+            ClientMessage::AcceptInvite(req) => self.handle_accept_invite(req),
+            ClientMessage::DeclineInvite(req) => self.handle_decline_invite(req),
+            ClientMessage::GetRoomInvites(req) => self.handle_get_room_invites(req),
+            ClientMessage::RequestJoinRoom(req) => self.handle_request_join_room(req),
+            ClientMessage::ApproveJoinRequest(req) => self.handle_approve_join_request(req),
+            ClientMessage::DenyJoinRequest(req) => self.handle_deny_join_request(req),
+            ClientMessage::GetJoinRequests(req) => self.handle_get_join_requests(req),
+            ClientMessage::CreateInviteLink(req) => self.handle_create_invite_link(req),
+            ClientMessage::JoinWithInviteLink(req) => self.handle_join_with_invite_link(req),
+            ClientMessage::ListPublicRooms(req) => self.handle_list_public_rooms(req),
+            ClientMessage::KickMember(req) => self.handle_kick_member(req),
+            ClientMessage::BanMember(req) => self.handle_ban_member(req),
+            ClientMessage::UnbanMember(req) => self.handle_unban_member(req),
+            ClientMessage::PromoteMember(req) => self.handle_promote_member(req),
+            ClientMessage::UpdateRoom(req) => self.handle_update_room(req),
+            ClientMessage::DeleteRoom(req) => self.handle_delete_room(req),
+            ClientMessage::TransferOwnership(req) => self.handle_transfer_ownership(req),
             ClientMessage::SendRoomMessage(req) => self.handle_send_room_message(req),
             ClientMessage::GetRoomMessages(req) => self.handle_get_room_messages(req),
+            ClientMessage::EditRoomMessage(req) => self.handle_edit_room_message(req),
+            ClientMessage::DeleteRoomMessage(req) => self.handle_delete_room_message(req),
             ClientMessage::RequestFriend(req) => self.handle_request_friend(req),
             ClientMessage::AcceptFriend(req) => self.handle_accept_friend(req),
+            ClientMessage::RejectFriend(req) => self.handle_reject_friend(req),
+            ClientMessage::CancelFriendRequest(req) => self.handle_cancel_friend_request(req),
+            ClientMessage::Unfriend(req) => self.handle_unfriend(req),
             ClientMessage::GetFriends(req) => self.handle_get_friends(req),
+            ClientMessage::GetPendingRequests(req) => self.handle_get_pending_requests(req),
+            ClientMessage::BlockUser(req) => self.handle_block_user(req),
+            ClientMessage::UnblockUser(req) => self.handle_unblock_user(req),
+            ClientMessage::MuteUser(req) => self.handle_mute_user(req),
+            ClientMessage::UnmuteUser(req) => self.handle_unmute_user(req),
+            ClientMessage::GetBlockedUsers(req) => self.handle_get_blocked_users(req),
             ClientMessage::SendPrivateMessage(req) => self.handle_send_private_message(req),
             ClientMessage::GetPrivateMessages(req) => self.handle_get_private_messages(req),
+            ClientMessage::EditPrivateMessage(req) => self.handle_edit_private_message(req),
+            ClientMessage::DeletePrivateMessage(req) => self.handle_delete_private_message(req),
+            ClientMessage::GetConversations(req) => self.handle_get_conversations(req),
+            ClientMessage::MarkConversationRead(req) => self.handle_mark_conversation_read(req),
+            ClientMessage::MessageRead(req) => self.handle_message_read(req),
+            ClientMessage::SetReadReceipts(req) => self.handle_set_read_receipts(req),
             ClientMessage::GetUser(req) => self.handle_get_user(req),
             ClientMessage::SearchUsers(req) => self.handle_search_users(req),
+            ClientMessage::SearchPosts(req) => self.handle_search_posts(req),
+            ClientMessage::SearchRooms(req) => self.handle_search_rooms(req),
+            ClientMessage::ResolveName(req) => self.handle_resolve_name(req),
+            ClientMessage::SetPetname(req) => self.handle_set_petname(req),
+            ClientMessage::RemovePetname(req) => self.handle_remove_petname(req),
+            ClientMessage::ListContacts(req) => self.handle_list_contacts(req),
+            ClientMessage::CreateEgo(req) => self.handle_create_ego(req),
+            ClientMessage::UseEgo(req) => self.handle_use_ego(req),
+            ClientMessage::ListEgos(req) => self.handle_list_egos(req),
+            ClientMessage::RotateGroupKey(req) => self.handle_rotate_group_key(req),
+            ClientMessage::GetGroupKeys(req) => self.handle_get_group_keys(req),
+            ClientMessage::UploadMedia(req) => self.handle_upload_media(req),
+            ClientMessage::GetMedia(req) => self.handle_get_media(req),
+            ClientMessage::GetNotifications(req) => self.handle_get_notifications(req),
+            ClientMessage::MarkNotificationsRead(req) => self.handle_mark_notifications_read(req),
+            ClientMessage::SetNotificationMuted(req) => self.handle_set_notification_muted(req),
         }
     }
 
     fn handle_auth(&self, req: AuthRequest) -> ServerMessage {
-        let peer = PeerIdentity::new(req.peer_id);
+        let peer = req.peer_id;
         self.set_current_peer(peer.clone());
         ServerMessage::Auth(AuthResponse {
             success: true,
@@ -64,14 +642,117 @@ impl MessageHandler {
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let mut user = User::new(req.username, peer.to_string());
+        let existing = self.store.get_user(&peer);
+        let old_ego = existing
+            .as_ref()
+            .filter(|user| user.username != req.username)
+            .and_then(|user| self.ego_name(user));
+        let zone = match &existing {
+            Some(user) if user.username == req.username => {
+                Ok(PublicKey::new(user.gns_zone.clone()))
+            }
+            // Renaming keeps the ego, and with it the zone and its petnames.
+            Some(user) if old_ego.is_some() => self
+                .rename_ego(user, &req.username)
+                .map(|ego| ego.public_key),
+            _ => self.claim_ego(&req.username).map(|ego| ego.public_key),
+        };
+        let zone = match zone {
+            Ok(zone) => zone,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let mut user = match &existing {
+            Some(user) => user.clone(),
+            None => User::new(req.username.clone(), String::new()),
+        };
+        user.id = peer;
+        user.username = req.username;
+        user.gns_zone = zone.as_str().to_string();
         user.display_name = req.display_name.unwrap_or_default();
         user.bio = req.bio;
+        user.updated_at = chrono::Utc::now();
+
+        let mut gns = self.gns.write();
+        let previous = existing.filter(|previous| previous.username != user.username);
+        if let Some(previous) = &previous {
+            gns.unpublish_profile(previous);
+        }
+        if let Err(e) = gns.publish_profile(&user) {
+            // Put back the profile and ego name the user had.
+            if let Some(previous) = &previous
+                && let Err(e) = gns.publish_profile(previous)
+            {
+                tracing::warn!("Failed to restore the profile of {}: {}", previous.id, e);
+            }
+            drop(gns);
+            if let Some(old_ego) = &old_ego
+                && let Err(e) = self.identity.rename_ego(&user.username, old_ego)
+            {
+                tracing::warn!("Failed to rename ego {} back: {}", user.username, e);
+            }
+            return Self::gns_error(e);
+        }
+        drop(gns);
 
         self.store.add_user(user.clone());
         ServerMessage::User(UserResponse { user: Some(user) })
     }
 
+    /// Returns the ego for a new username, creating it if needed, after
+    /// checking that the name is free in the local zone. An existing ego is
+    /// only handed to a session that already holds its zone.
+    fn claim_ego(&self, username: &str) -> Result<Ego, ErrorResponse> {
+        self.check_username(username)?;
+
+        if let Some(ego) = self.identity.get_ego(username) {
+            if !self.owns_ego(&ego) {
+                return Err(ErrorResponse::new(409, "Username already taken"));
+            }
+            return Ok(ego);
+        }
+        self.identity.create_ego(username).map_err(|e| match e {
+            IdentityError::EgoExists(_) => ErrorResponse::new(409, "Username already taken"),
+            e => ErrorResponse::new(500, e.to_string()),
+        })
+    }
+
+    /// Whether a user authenticated on this connection has `ego` as zone.
+    fn owns_ego(&self, ego: &Ego) -> bool {
+        let egos = self.session.read().egos.clone();
+        egos.iter().any(|peer| {
+            self.store
+                .get_user(peer)
+                .is_some_and(|user| user.gns_zone == ego.public_key.as_str())
+        })
+    }
+
+    /// Renames the ego holding `user`'s zone to `username`, so no ego is
+    /// left behind under the old name.
+    fn rename_ego(&self, user: &User, username: &str) -> Result<Ego, ErrorResponse> {
+        self.check_username(username)?;
+        let old_name = self
+            .ego_name(user)
+            .ok_or_else(|| ErrorResponse::new(404, "Ego not found"))?;
+
+        if self.identity.get_ego(username).is_some() {
+            return Err(ErrorResponse::new(409, "Username already taken"));
+        }
+        self.identity
+            .rename_ego(&old_name, username)
+            .map_err(|e| ErrorResponse::new(500, e.to_string()))
+    }
+
+    fn check_username(&self, username: &str) -> Result<(), ErrorResponse> {
+        if !is_valid_label(username) || looks_like_peer_id(username) {
+            return Err(ErrorResponse::new(400, "Invalid username"));
+        }
+        if username == SERVER_EGO || self.gns.read().delegated_zone(username).is_some() {
+            return Err(ErrorResponse::new(409, "Username already taken"));
+        }
+        Ok(())
+    }
+
     fn handle_update_user(&self, req: UpdateUserRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
@@ -79,7 +760,15 @@ impl MessageHandler {
         };
 
         let mut users = self.store.users.write();
-        if let Some(user) = users.get_mut(peer.as_str()) {
+        if let Some(user) = users.get_mut(&peer) {
+            if let Some(avatar) = req.avatar_hash {
+                if let Err(e) = self.store.media.retain(std::slice::from_ref(&avatar)) {
+                    return ServerMessage::Error(Self::media_error(e));
+                }
+                if let Some(previous) = user.avatar_hash.replace(avatar) {
+                    self.store.media.release(&[previous]);
+                }
+            }
             if let Some(name) = req.display_name {
                 user.display_name = name;
             }
@@ -89,6 +778,10 @@ impl MessageHandler {
             user.updated_at = chrono::Utc::now();
             let updated = user.clone();
             drop(users);
+            self.store.index_user(&updated);
+            if let Err(e) = self.gns.write().publish_profile(&updated) {
+                return Self::gns_error(e);
+            }
             ServerMessage::User(UserResponse {
                 user: Some(updated),
             })
@@ -98,23 +791,303 @@ impl MessageHandler {
     }
 
     fn handle_create_post(&self, req: CreatePostRequest) -> ServerMessage {
-        let peer = match self.current_peer() {
-            Some(p) => p,
-            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        // Hidden posts are reported missing so their existence doesn't leak.
+        let parent = match req.reply_to {
+            Some(id) => match self.store.get_post(id) {
+                Some(p) if !p.is_deleted() && self.store.can_view(&p, Some(&peer)) => Some(p),
+                _ => return ServerMessage::Error(ErrorResponse::new(404, "Post not found")),
+            },
+            None => None,
         };
 
+        let entities = self.extract_entities(&req.content, &peer);
         let mut post = Post::new(peer, req.content);
+        post.entities = entities;
         post.media_hashes = req.media_hashes;
         post.reply_to = req.reply_to;
-        post.repost_of = req.repost_of;
         post.visibility = req.visibility;
 
-        self.store.add_post(post.clone());
-        ServerMessage::Post(PostResponse { post: Some(post) })
+        let original = match req.repost_of {
+            Some(id) => match self.repost_target(id, &post) {
+                Ok(original) => Some(original),
+                Err(e) => return ServerMessage::Error(e),
+            },
+            None => None,
+        };
+        post.repost_of = original.as_ref().map(|o| o.id);
+
+        if let Err(e) = self.store.media.retain(&post.media_hashes) {
+            return ServerMessage::Error(Self::media_error(e));
+        }
+
+        // Checked again under the lock, for a repost made meanwhile.
+        if !self.store.add_post(post.clone()) {
+            self.store.media.release(&post.media_hashes);
+            return ServerMessage::Error(ErrorResponse::new(409, "Already reposted"));
+        }
+        if let Some(original) = original
+            && original.author_id != post.author_id
+        {
+            if self.store.can_view(&post, Some(&original.author_id)) {
+                self.emit(
+                    Audience::Peers(vec![original.author_id.clone()]),
+                    EventMessage::NewRepost {
+                        original_id: original.id,
+                        original_author: original.author_id.to_string(),
+                        post: post.clone(),
+                    },
+                );
+            }
+            let mut notification = Notification::new(
+                original.author_id,
+                NotificationKind::Repost,
+                post.author_id.clone(),
+            );
+            notification.post_id = Some(post.id);
+            self.notify(notification);
+        }
+        if let Some(parent) = parent
+            && parent.author_id != post.author_id
+        {
+            if self.store.can_view(&post, Some(&parent.author_id)) {
+                self.emit(
+                    Audience::Peers(vec![parent.author_id.clone()]),
+                    EventMessage::NewReply {
+                        parent_id: parent.id,
+                        parent_author: parent.author_id.to_string(),
+                        post: post.clone(),
+                    },
+                );
+            }
+            let mut notification = Notification::new(
+                parent.author_id,
+                NotificationKind::Reply,
+                post.author_id.clone(),
+            );
+            notification.post_id = Some(post.id);
+            self.notify(notification);
+        }
+        self.notify_mentions(&post, &[]);
+        let viewer = post.author_id.clone();
+        self.post_response(Some(post), Some(&viewer))
+    }
+
+    /// Parses `content` and resolves its mentions the way `author` names
+    /// peers: by petname, username or peer id.
+    fn extract_entities(&self, content: &str, author: &PeerIdentity) -> Vec<Entity> {
+        let mut entities = parse_entities(content);
+        for entity in &mut entities {
+            if let EntityKind::Mention { name, peer_id } = &mut entity.kind {
+                *peer_id = self.resolve_peer(author, name).ok();
+            }
+        }
+        entities
+    }
+
+    /// Tells the peers mentioned in `post` who may see it, except the author,
+    /// those in `notified` and those who muted the author.
+    fn notify_mentions(&self, post: &Post, notified: &[PeerIdentity]) {
+        for peer in post.mentioned_peers() {
+            if peer == post.author_id
+                || notified.contains(&peer)
+                || !self.store.can_view(post, Some(&peer))
+                || self.store.is_hidden_from(&post.author_id, Some(&peer))
+            {
+                continue;
+            }
+            self.emit(
+                Audience::Peers(vec![peer.clone()]),
+                EventMessage::Mentioned {
+                    peer_id: peer.to_string(),
+                    post: post.clone(),
+                },
+            );
+            let mut notification =
+                Notification::new(peer, NotificationKind::Mention, post.author_id.clone());
+            notification.post_id = Some(post.id);
+            self.notify(notification);
+        }
+    }
+
+    fn handle_edit_post(&self, req: EditPostRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let post = match self.store.get_post(req.post_id) {
+            Some(p) if !p.is_deleted() => p,
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Post not found")),
+        };
+        if post.author_id != peer {
+            return ServerMessage::Error(ErrorResponse::new(
+                403,
+                "Only the author can edit a post",
+            ));
+        }
+        if post.is_repost() {
+            return ServerMessage::Error(ErrorResponse::new(400, "Reposts cannot be edited"));
+        }
+        let media_hashes = req
+            .media_hashes
+            .clone()
+            .unwrap_or_else(|| post.media_hashes.clone());
+        // An empty quote would turn into a plain repost.
+        if req.content.is_empty() && media_hashes.is_empty() {
+            return ServerMessage::Error(ErrorResponse::new(400, "Post would be empty"));
+        }
+        if req.media_hashes.is_some()
+            && let Err(e) = self.store.media.retain(&media_hashes)
+        {
+            return ServerMessage::Error(Self::media_error(e));
+        }
+
+        let entities = self.extract_entities(&req.content, &peer);
+        let mut posts = self.store.posts.write();
+        let Some(stored) = posts.get_mut(&req.post_id).filter(|p| !p.is_deleted()) else {
+            drop(posts);
+            if req.media_hashes.is_some() {
+                self.store.media.release(&media_hashes);
+            }
+            return ServerMessage::Error(ErrorResponse::new(404, "Post not found"));
+        };
+        let previous = stored.clone();
+        stored.edit(req.content);
+        stored.entities = entities;
+        let replaced = std::mem::replace(&mut stored.media_hashes, media_hashes);
+        let updated = stored.clone();
+        drop(posts);
+        if req.media_hashes.is_some() {
+            self.store.media.release(&replaced);
+        }
+        self.store.unindex_hashtags(&previous);
+        self.store.index_hashtags(&updated);
+        self.store.index_post(&updated);
+
+        self.emit(
+            Audience::PostViewers(Box::new(updated.clone())),
+            EventMessage::PostEdited {
+                post: updated.clone(),
+            },
+        );
+        self.notify_mentions(&updated, &previous.mentioned_peers());
+        self.post_response(Some(updated), Some(&peer))
+    }
+
+    fn handle_delete_post(&self, req: DeletePostRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        match self.store.get_post(req.post_id) {
+            Some(p) if !p.is_deleted() => {
+                if p.author_id != peer {
+                    return ServerMessage::Error(ErrorResponse::new(
+                        403,
+                        "Only the author can delete a post",
+                    ));
+                }
+            }
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Post not found")),
+        }
+
+        if self.tombstone_post(req.post_id) {
+            self.federate(&SocialCadetMessage::Tombstone {
+                target: TombstoneTarget::Post {
+                    post_id: req.post_id,
+                },
+            });
+        }
+        self.post_response(self.store.get_post(req.post_id), Some(&peer))
+    }
+
+    /// Deletes a post whose removal was authorized, for local and federated
+    /// requests alike.
+    fn tombstone_post(&self, post_id: Uuid) -> bool {
+        let Some(previous) = self.store.delete_post(post_id) else {
+            return false;
+        };
+        self.store.media.release(&previous.media_hashes);
+        self.emit(
+            Audience::PostViewers(Box::new(previous.clone())),
+            EventMessage::PostDeleted {
+                post_id,
+                author_id: previous.author_id.to_string(),
+            },
+        );
+        true
+    }
+
+    /// Finds the post `post` reposts or quotes. Plain reposts are resolved
+    /// to what they repost, and only public posts may be shared, once each
+    /// without comment.
+    fn repost_target(&self, id: Uuid, post: &Post) -> Result<Post, ErrorResponse> {
+        let mut original = self
+            .store
+            .get_post(id)
+            .filter(|p| !p.is_deleted() && self.store.can_view(p, Some(&post.author_id)))
+            .ok_or_else(|| ErrorResponse::new(404, "Post not found"))?;
+        if original.is_repost()
+            && let Some(inner) = original.repost_of.and_then(|id| self.store.get_post(id))
+        {
+            original = inner;
+        }
+        if original.visibility != PostVisibility::Public {
+            return Err(ErrorResponse::new(403, "Only public posts can be reposted"));
+        }
+
+        let mut shared = post.clone();
+        shared.repost_of = Some(original.id);
+        if shared.is_repost()
+            && self
+                .store
+                .find_repost(&post.author_id, original.id)
+                .is_some()
+        {
+            return Err(ErrorResponse::new(409, "Already reposted"));
+        }
+        Ok(original)
+    }
+
+    fn handle_undo_repost(&self, req: UndoRepostRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let repost = self
+            .store
+            .find_repost(&peer, req.post_id)
+            .and_then(|id| self.store.remove_post(id));
+        match repost {
+            Some(_) => self.post_response(self.store.get_post(req.post_id), Some(&peer)),
+            None => ServerMessage::Error(ErrorResponse::new(404, "Repost not found")),
+        }
+    }
+
+    fn post_response(
+        &self,
+        mut post: Option<Post>,
+        viewer: Option<&PeerIdentity>,
+    ) -> ServerMessage {
+        let mut originals = self.repost_originals(&post, viewer);
+        let media = self.post_media(post.iter().chain(&originals));
+        self.mark_reacted_posts(post.iter_mut().chain(&mut originals), viewer);
+        ServerMessage::Post(PostResponse {
+            post,
+            originals,
+            media,
+        })
     }
 
     fn handle_get_feed(&self, req: GetFeedRequest) -> ServerMessage {
-        let peer = PeerIdentity::new(req.peer_id);
+        let peer = req.peer_id;
         let limit = req.limit.unwrap_or(50) as usize;
 
         let mut posts: Vec<Post> = self
@@ -122,6 +1095,8 @@ impl MessageHandler {
             .posts
             .read()
             .values()
+            .filter(|p| !p.is_deleted())
+            .filter(|p| !self.store.is_hidden_from(&p.author_id, Some(&peer)))
             .filter(|p| {
                 if p.visibility == PostVisibility::Public {
                     return true;
@@ -137,57 +1112,404 @@ impl MessageHandler {
         posts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
         posts.truncate(limit);
 
-        ServerMessage::Feed(FeedResponse { posts })
+        let mut originals = self.repost_originals(&posts, Some(&peer));
+        let media = self.post_media(posts.iter().chain(&originals));
+        self.mark_reacted_posts(posts.iter_mut().chain(&mut originals), Some(&peer));
+        ServerMessage::Feed(FeedResponse {
+            posts,
+            originals,
+            media,
+        })
+    }
+
+    fn handle_get_hashtag_feed(&self, req: GetHashtagFeedRequest) -> ServerMessage {
+        let Some(tag) = normalize_hashtag(&req.tag) else {
+            return ServerMessage::Error(ErrorResponse::new(400, "Invalid hashtag"));
+        };
+        let viewer = self.current_peer();
+        let limit = req.limit.unwrap_or(50) as usize;
+
+        let mut posts: Vec<Post> = self
+            .store
+            .get_posts_by_hashtag(&tag)
+            .into_iter()
+            .filter(|p| !p.is_deleted() && self.store.can_view(p, viewer.as_ref()))
+            .filter(|p| !self.store.is_hidden_from(&p.author_id, viewer.as_ref()))
+            .filter(|p| req.before.is_none_or(|before| p.created_at < before))
+            .take(limit)
+            .collect();
+
+        let mut originals = self.repost_originals(&posts, viewer.as_ref());
+        let media = self.post_media(posts.iter().chain(&originals));
+        self.mark_reacted_posts(posts.iter_mut().chain(&mut originals), viewer.as_ref());
+        ServerMessage::Feed(FeedResponse {
+            posts,
+            originals,
+            media,
+        })
     }
 
     fn handle_get_post(&self, req: GetPostRequest) -> ServerMessage {
         let post = self.store.get_post(req.post_id);
-        ServerMessage::Post(PostResponse { post })
+        self.post_response(post, self.current_peer().as_ref())
     }
 
-    fn handle_like_post(&self, req: LikePostRequest) -> ServerMessage {
-        let peer = match self.current_peer() {
+    fn handle_get_thread(&self, req: GetThreadRequest) -> ServerMessage {
+        let viewer = self.current_peer();
+        let visible = |p: &Post| self.store.can_view(p, viewer.as_ref());
+        let post = match self.store.get_post(req.post_id).filter(visible) {
             Some(p) => p,
-            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Post not found")),
+        };
+        let depth = req
+            .depth
+            .unwrap_or(DEFAULT_THREAD_DEPTH)
+            .min(MAX_THREAD_DEPTH);
+        let limit = req
+            .limit
+            .unwrap_or(DEFAULT_THREAD_LIMIT)
+            .min(MAX_THREAD_LIMIT) as usize;
+        // Replies sharing a timestamp are told apart by id.
+        let after = |p: &Post| match (req.after, req.after_id) {
+            (None, _) => true,
+            (Some(at), None) => p.created_at > at,
+            (Some(at), Some(id)) => (p.created_at, p.id) > (at, id),
         };
 
-        let peer_str = peer.to_string();
-        let mut posts = self.store.posts.write();
-        if let Some(post) = posts.get_mut(&req.post_id) {
-            if post.likes.contains(&peer_str) {
-                post.likes.retain(|id| id != &peer_str);
-            } else {
-                post.likes.push(peer_str);
-            }
-            let updated = post.clone();
-            drop(posts);
-            ServerMessage::Post(PostResponse {
-                post: Some(updated),
-            })
-        } else {
-            ServerMessage::Error(ErrorResponse::new(404, "Post not found"))
+        let mut ancestors = Vec::new();
+        let mut parent = post.reply_to;
+        while let Some(id) = parent
+            && ancestors.len() < MAX_THREAD_DEPTH as usize
+        {
+            let Some(p) = self.store.get_post(id).filter(visible) else {
+                break;
+            };
+            parent = p.reply_to;
+            ancestors.push(p);
         }
-    }
+        ancestors.reverse();
 
-    fn handle_create_room(&self, req: CreateRoomRequest) -> ServerMessage {
-        let peer = match self.current_peer() {
-            Some(p) => p,
-            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
-        };
-
-        let mut room = ChatRoom::new(req.name, peer, req.is_group);
-        room.description = req.description;
-        room.is_public = req.is_public;
+        let mut replies = Vec::new();
+        let mut has_more = false;
+        if depth > 0 {
+            let mut direct: Vec<Post> = self
+                .store
+                .get_replies(post.id)
+                .into_iter()
+                .filter(|p| visible(p) && after(p))
+                .collect();
+            has_more = direct.len() > limit;
+            direct.truncate(limit);
+            for reply in direct {
+                self.collect_replies(reply, depth - 1, limit, viewer.as_ref(), &mut replies);
+            }
+        }
 
-        self.store.add_room(room.clone());
-        ServerMessage::Room(RoomResponse {
-            room: Some(room),
-            rooms: None,
+        let thread = || ancestors.iter().chain([&post]).chain(&replies);
+        let mut originals = self.repost_originals(thread(), viewer.as_ref());
+        let media = self.post_media(thread().chain(&originals));
+        let mut post = post;
+        self.mark_reacted_posts(
+            ancestors
+                .iter_mut()
+                .chain([&mut post])
+                .chain(&mut replies)
+                .chain(&mut originals),
+            viewer.as_ref(),
+        );
+        ServerMessage::Thread(ThreadResponse {
+            post,
+            ancestors,
+            replies,
+            has_more,
+            originals,
+            media,
         })
     }
 
-    fn handle_get_rooms(&self, _req: GetRoomsRequest) -> ServerMessage {
-        let peer = match self.current_peer() {
+    /// Appends `post` and, `depth` levels deep, the first `limit` visible
+    /// replies below each post.
+    fn collect_replies(
+        &self,
+        post: Post,
+        depth: u32,
+        limit: usize,
+        viewer: Option<&PeerIdentity>,
+        out: &mut Vec<Post>,
+    ) {
+        let id = post.id;
+        out.push(post);
+        if depth == 0 {
+            return;
+        }
+        let replies = self
+            .store
+            .get_replies(id)
+            .into_iter()
+            .filter(|p| self.store.can_view(p, viewer))
+            .take(limit);
+        for reply in replies {
+            self.collect_replies(reply, depth - 1, limit, viewer, out);
+        }
+    }
+
+    /// The posts `posts` repost or quote, once each, as far as `viewer` may
+    /// see them.
+    fn repost_originals<'a>(
+        &self,
+        posts: impl IntoIterator<Item = &'a Post>,
+        viewer: Option<&PeerIdentity>,
+    ) -> Vec<Post> {
+        let mut seen = HashSet::new();
+        posts
+            .into_iter()
+            .filter_map(|p| p.repost_of)
+            .filter(|id| seen.insert(*id))
+            .filter_map(|id| self.store.get_post(id))
+            .filter(|p| self.store.can_view(p, viewer))
+            .collect()
+    }
+
+    fn mark_reacted_posts<'a>(
+        &self,
+        posts: impl IntoIterator<Item = &'a mut Post>,
+        viewer: Option<&PeerIdentity>,
+    ) {
+        let Some(viewer) = viewer else {
+            return;
+        };
+        for post in posts {
+            self.store
+                .mark_reacted(post.id, &mut post.reactions, viewer);
+        }
+    }
+
+    fn mark_reacted_messages<'a>(
+        &self,
+        messages: impl IntoIterator<Item = &'a mut ChatMessage>,
+        viewer: Option<&PeerIdentity>,
+    ) {
+        let Some(viewer) = viewer else {
+            return;
+        };
+        for msg in messages {
+            self.store.mark_reacted(msg.id, &mut msg.reactions, viewer);
+        }
+    }
+
+    /// Info on the media attached to `posts`, once each; blobs still being
+    /// fetched from other peers are left out.
+    fn post_media<'a>(&self, posts: impl IntoIterator<Item = &'a Post>) -> Vec<MediaInfo> {
+        let mut seen = HashSet::new();
+        posts
+            .into_iter()
+            .flat_map(|p| &p.media_hashes)
+            .filter(|hash| seen.insert(*hash))
+            .filter_map(|hash| self.store.media.info(hash))
+            .collect()
+    }
+
+    /// Toggles the requester's [`LIKE_REACTION`] on a post.
+    fn handle_like_post(&self, req: LikePostRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+
+        let target = ReactionTarget::Post {
+            post_id: req.post_id,
+        };
+        if let Err(e) = self.check_reaction_target(&target, &peer) {
+            return ServerMessage::Error(e);
+        }
+        let liked = self
+            .store
+            .reactions
+            .read()
+            .get(&req.post_id)
+            .and_then(|r| r.get(LIKE_REACTION))
+            .is_some_and(|reactors| reactors.contains(&peer));
+        if self
+            .apply_reaction(target, LIKE_REACTION, &peer, !liked)
+            .is_some()
+        {
+            self.federate(&SocialCadetMessage::Reaction {
+                target,
+                emoji: LIKE_REACTION.to_string(),
+                reacted: !liked,
+            });
+        }
+        self.post_response(self.store.get_post(req.post_id), Some(&peer))
+    }
+
+    fn handle_react(&self, req: ReactRequest) -> ServerMessage {
+        self.react(req.target, req.emoji, req.ego.as_deref(), true)
+    }
+
+    fn handle_unreact(&self, req: UnreactRequest) -> ServerMessage {
+        self.react(req.target, req.emoji, req.ego.as_deref(), false)
+    }
+
+    fn react(
+        &self,
+        target: ReactionTarget,
+        emoji: String,
+        ego: Option<&str>,
+        reacted: bool,
+    ) -> ServerMessage {
+        let peer = match self.acting_peer(ego) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        if !is_valid_reaction(&emoji) {
+            return ServerMessage::Error(ErrorResponse::new(400, "Invalid reaction"));
+        }
+        if let Err(e) = self.check_reaction_target(&target, &peer) {
+            return ServerMessage::Error(e);
+        }
+        // The store enforces the cap too; this only gives a clearer error.
+        if reacted && self.store.reactions_by(target.id(), &peer) >= MAX_REACTIONS_PER_PEER {
+            return ServerMessage::Error(ErrorResponse::new(429, "Too many reactions"));
+        }
+
+        let Some(mut reactions) = self.apply_reaction(target, &emoji, &peer, reacted) else {
+            return ServerMessage::Error(match reacted {
+                true => ErrorResponse::new(409, "Already reacted"),
+                false => ErrorResponse::new(404, "Reaction not found"),
+            });
+        };
+        self.federate(&SocialCadetMessage::Reaction {
+            target,
+            emoji,
+            reacted,
+        });
+        self.store.mark_reacted(target.id(), &mut reactions, &peer);
+        ServerMessage::Reactions(ReactionsResponse { target, reactions })
+    }
+
+    /// Posts take reactions from whoever may see them, messages from room
+    /// members.
+    fn check_reaction_target(
+        &self,
+        target: &ReactionTarget,
+        peer: &PeerIdentity,
+    ) -> Result<(), ErrorResponse> {
+        match target {
+            ReactionTarget::Post { post_id } => self
+                .store
+                .get_post(*post_id)
+                .filter(|p| !p.is_deleted() && self.store.can_view(p, Some(peer)))
+                .map(|_| ())
+                .ok_or_else(|| ErrorResponse::new(404, "Post not found")),
+            ReactionTarget::Message { message_id } => {
+                let room = self
+                    .store
+                    .get_message(*message_id)
+                    .filter(|m| !m.is_deleted())
+                    .and_then(|m| self.store.get_room(m.room_id))
+                    .ok_or_else(|| ErrorResponse::new(404, "Message not found"))?;
+                if !room.is_member(peer) {
+                    return Err(ErrorResponse::new(403, "Not a member of this room"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Records a reaction checked by the caller and announces it; `None` if
+    /// nothing changed.
+    fn apply_reaction(
+        &self,
+        target: ReactionTarget,
+        emoji: &str,
+        peer: &PeerIdentity,
+        reacted: bool,
+    ) -> Option<Vec<ReactionCount>> {
+        let reactions = self.store.set_reaction(target, emoji, peer, reacted)?;
+        if reacted {
+            self.notify_reaction(target, emoji, peer);
+        }
+        let event = match reacted {
+            true => EventMessage::ReactionAdded {
+                target,
+                emoji: emoji.to_string(),
+                peer_id: peer.to_string(),
+                reactions: reactions.clone(),
+            },
+            false => EventMessage::ReactionRemoved {
+                target,
+                emoji: emoji.to_string(),
+                peer_id: peer.to_string(),
+                reactions: reactions.clone(),
+            },
+        };
+        self.emit(self.reaction_audience(target), event);
+        Some(reactions)
+    }
+
+    /// Those who may see what `target` refers to.
+    fn reaction_audience(&self, target: ReactionTarget) -> Audience {
+        match target {
+            ReactionTarget::Post { post_id } => match self.store.get_post(post_id) {
+                Some(post) => Audience::PostViewers(Box::new(post)),
+                None => Audience::Peers(Vec::new()),
+            },
+            ReactionTarget::Message { message_id } => match self.store.get_message(message_id) {
+                Some(message) => self.room_audience(message.room_id),
+                None => Audience::Peers(Vec::new()),
+            },
+        }
+    }
+
+    /// Tells the author of what `peer` reacted to.
+    fn notify_reaction(&self, target: ReactionTarget, emoji: &str, peer: &PeerIdentity) {
+        let (author, room_id) = match target {
+            ReactionTarget::Post { post_id } => match self.store.get_post(post_id) {
+                Some(post) => (post.author_id, None),
+                None => return,
+            },
+            ReactionTarget::Message { message_id } => match self.store.get_message(message_id) {
+                Some(message) => (message.sender_id, Some(message.room_id)),
+                None => return,
+            },
+        };
+        let mut notification = Notification::new(author, NotificationKind::Reaction, peer.clone());
+        match target {
+            ReactionTarget::Post { post_id } => notification.post_id = Some(post_id),
+            ReactionTarget::Message { message_id } => notification.message_id = Some(message_id),
+        }
+        notification.room_id = room_id;
+        notification.emoji = Some(emoji.to_string());
+        self.notify(notification);
+    }
+
+    fn handle_create_room(&self, req: CreateRoomRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let mut room = if req.encrypted {
+            ChatRoom::new_encrypted(req.name, peer, req.is_group)
+        } else {
+            ChatRoom::new(req.name, peer, req.is_group)
+        };
+        room.description = req.description;
+        room.is_public = req.is_public;
+
+        self.store.add_room(room.clone());
+        if room.encrypted {
+            self.request_rekey(&room);
+        }
+        ServerMessage::Room(RoomResponse {
+            room: Some(room),
+            rooms: None,
+        })
+    }
+
+    fn handle_get_rooms(&self, _req: GetRoomsRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
@@ -207,198 +1529,2125 @@ impl MessageHandler {
         })
     }
 
-    fn handle_join_room(&self, req: JoinRoomRequest) -> ServerMessage {
+    /// Joins a public room; private rooms take an invitation, an approved
+    /// join request or an invite link instead.
+    fn handle_join_room(&self, req: JoinRoomRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        match self.admit_member(req.room_id, &peer, |room| room.is_public) {
+            Ok(room) => Self::room_response(room),
+            Err(e) => ServerMessage::Error(e),
+        }
+    }
+
+    /// Adds `peer` to a room if `allowed` lets them in, or they are already
+    /// a member. Bans always keep peers out.
+    fn admit_member(
+        &self,
+        room_id: Uuid,
+        peer: &PeerIdentity,
+        allowed: impl FnOnce(&ChatRoom) -> bool,
+    ) -> Result<ChatRoom, ErrorResponse> {
+        let mut rooms = self.store.rooms.write();
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| ErrorResponse::new(404, "Room not found"))?;
+        if room.is_banned(peer) {
+            return Err(ErrorResponse::new(403, "Banned from this room"));
+        }
+        if room.is_member(peer) {
+            return Ok(room.clone());
+        }
+        if !allowed(room) {
+            return Err(ErrorResponse::new(403, "Room is private"));
+        }
+        room.members.push(peer.clone());
+        room.require_rekey();
+        let updated = room.clone();
+        drop(rooms);
+
+        self.store.clear_room_admission(room_id, peer);
+        if updated.encrypted {
+            self.request_rekey(&updated);
+        }
+        self.emit(
+            Audience::members(&updated),
+            EventMessage::RoomMemberJoined {
+                room_id,
+                peer_id: peer.to_string(),
+            },
+        );
+        Ok(updated)
+    }
+
+    fn handle_invite_to_room(&self, req: InviteToRoomRequest) -> ServerMessage {
+        let (peer, invitee) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let room = match self.store.get_room(req.room_id) {
+            Some(r) => r,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Room not found")),
+        };
+        if !room.has_role(&peer, RoomRole::Moderator) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Requires the moderator role"));
+        }
+        if room.is_member(&invitee) {
+            return ServerMessage::Error(ErrorResponse::new(409, "Already a member"));
+        }
+        if room.is_banned(&invitee) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Banned from this room"));
+        }
+        if self.store.blocked_between(&peer, &invitee) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Blocked"));
+        }
+
+        let invite =
+            self.store
+                .add_room_invite(RoomInvite::new(room.id, peer.clone(), invitee.clone()));
+        self.emit(
+            Audience::members(&room).and(&invite.invitee_id),
+            EventMessage::RoomInvited {
+                invite: invite.clone(),
+            },
+        );
+        let mut notification = Notification::new(invitee, NotificationKind::RoomInvite, peer);
+        notification.room_id = Some(room.id);
+        self.notify(notification);
+        Self::invite_response(invite)
+    }
+
+    fn handle_accept_invite(&self, req: AcceptInviteRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let invite = match self.store.get_room_invite(req.invite_id) {
+            Some(i) if i.invitee_id == peer => i,
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Invite not found")),
+        };
+        // Joining clears the invitation.
+        match self.admit_member(invite.room_id, &peer, |_| true) {
+            Ok(room) => Self::room_response(room),
+            Err(e) => ServerMessage::Error(e),
+        }
+    }
+
+    fn handle_decline_invite(&self, req: DeclineInviteRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        match self.store.get_room_invite(req.invite_id) {
+            Some(i) if i.invitee_id == peer => {}
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Invite not found")),
+        }
+        let Some(invite) = self.store.remove_room_invite(req.invite_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Invite not found"));
+        };
+        self.emit(
+            self.room_audience(invite.room_id).and(&invite.invitee_id),
+            EventMessage::RoomInviteDeclined {
+                invite: invite.clone(),
+            },
+        );
+        Self::invite_response(invite)
+    }
+
+    fn handle_get_room_invites(&self, req: GetRoomInvitesRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        ServerMessage::RoomInvite(RoomInviteResponse {
+            invite: None,
+            invites: Some(self.store.get_room_invites(&peer)),
+        })
+    }
+
+    fn handle_request_join_room(&self, req: RequestJoinRoomRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let room = match self.store.get_room(req.room_id) {
+            Some(r) => r,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Room not found")),
+        };
+        if room.is_banned(&peer) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Banned from this room"));
+        }
+        if room.is_member(&peer) {
+            return ServerMessage::Error(ErrorResponse::new(409, "Already a member"));
+        }
+
+        let request = JoinRequest {
+            room_id: room.id,
+            peer_id: peer.clone(),
+            created_at: chrono::Utc::now(),
+        };
+        if !self.store.add_join_request(request.clone()) {
+            return ServerMessage::Error(ErrorResponse::new(409, "Join request already sent"));
+        }
+        self.emit(
+            Audience::members(&room).and(&peer),
+            EventMessage::RoomJoinRequested {
+                request: request.clone(),
+            },
+        );
+        for moderator in room
+            .members
+            .iter()
+            .filter(|m| room.has_role(m, RoomRole::Moderator))
+        {
+            let mut notification = Notification::new(
+                moderator.clone(),
+                NotificationKind::JoinRequest,
+                peer.clone(),
+            );
+            notification.room_id = Some(room.id);
+            self.notify(notification);
+        }
+        ServerMessage::JoinRequest(JoinRequestResponse {
+            request: Some(request),
+            requests: None,
+        })
+    }
+
+    fn handle_approve_join_request(&self, req: ApproveJoinRequestRequest) -> ServerMessage {
+        let (peer, requester) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if let Err(e) = self.check_join_request(req.room_id, &peer, &requester) {
+            return ServerMessage::Error(e);
+        }
+
+        // Joining clears the request.
+        match self.admit_member(req.room_id, &requester, |_| true) {
+            Ok(room) => {
+                self.emit(
+                    Audience::members(&room),
+                    EventMessage::RoomJoinRequestAnswered {
+                        room_id: req.room_id,
+                        peer_id: requester.to_string(),
+                        approved: true,
+                        answered_by: peer.to_string(),
+                    },
+                );
+                Self::room_response(room)
+            }
+            Err(e) => ServerMessage::Error(e),
+        }
+    }
+
+    fn handle_deny_join_request(&self, req: DenyJoinRequestRequest) -> ServerMessage {
+        let (peer, requester) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if let Err(e) = self.check_join_request(req.room_id, &peer, &requester) {
+            return ServerMessage::Error(e);
+        }
+
+        let request = self.store.take_join_request(req.room_id, &requester);
+        self.emit(
+            self.room_audience(req.room_id).and(&requester),
+            EventMessage::RoomJoinRequestAnswered {
+                room_id: req.room_id,
+                peer_id: requester.to_string(),
+                approved: false,
+                answered_by: peer.to_string(),
+            },
+        );
+        ServerMessage::JoinRequest(JoinRequestResponse {
+            request,
+            requests: None,
+        })
+    }
+
+    fn handle_get_join_requests(&self, req: GetJoinRequestsRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if let Err(e) = self.check_room_role(req.room_id, &peer, RoomRole::Moderator) {
+            return ServerMessage::Error(e);
+        }
+
+        ServerMessage::JoinRequest(JoinRequestResponse {
+            request: None,
+            requests: Some(self.store.get_join_requests(req.room_id)),
+        })
+    }
+
+    /// Checks that `peer` may answer a join request `requester` has
+    /// pending.
+    fn check_join_request(
+        &self,
+        room_id: Uuid,
+        peer: &PeerIdentity,
+        requester: &PeerIdentity,
+    ) -> Result<(), ErrorResponse> {
+        self.check_room_role(room_id, peer, RoomRole::Moderator)?;
+        let pending = self
+            .store
+            .get_join_requests(room_id)
+            .iter()
+            .any(|r| r.peer_id == *requester);
+        match pending {
+            true => Ok(()),
+            false => Err(ErrorResponse::new(404, "Join request not found")),
+        }
+    }
+
+    fn check_room_role(
+        &self,
+        room_id: Uuid,
+        peer: &PeerIdentity,
+        role: RoomRole,
+    ) -> Result<ChatRoom, ErrorResponse> {
+        let room = self
+            .store
+            .get_room(room_id)
+            .ok_or_else(|| ErrorResponse::new(404, "Room not found"))?;
+        if !room.has_role(peer, role) {
+            return Err(ErrorResponse::new(
+                403,
+                format!("Requires the {} role", role.as_str()),
+            ));
+        }
+        Ok(room)
+    }
+
+    fn handle_create_invite_link(&self, req: CreateInviteLinkRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if let Err(e) = self.check_room_role(req.room_id, &peer, RoomRole::Admin) {
+            return ServerMessage::Error(e);
+        }
+        if req.max_uses == Some(0) {
+            return ServerMessage::Error(ErrorResponse::new(400, "max_uses must be positive"));
+        }
+
+        let mut link = InviteLink::new(req.room_id, peer.clone());
+        link.max_uses = req.max_uses;
+        if let Some(secs) = req.expires_in {
+            let ttl = match i64::try_from(secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+            {
+                Some(ttl) => ttl,
+                None => return ServerMessage::Error(ErrorResponse::new(400, "Invalid expiry")),
+            };
+            link.expires_at = Some(link.created_at + ttl);
+        }
+        let signature = match self.sign_invite_link(&link) {
+            Ok(signature) => signature,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        self.store.add_invite_link(link.clone());
+        ServerMessage::InviteLink(InviteLinkResponse {
+            token: format!("{}.{}", link.id, signature),
+            link,
+        })
+    }
+
+    fn handle_join_with_invite_link(&self, req: JoinWithInviteLinkRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let invalid = || ServerMessage::Error(ErrorResponse::new(404, "Invalid invite link"));
+        let Some((id, signature)) = req.token.split_once('.') else {
+            return invalid();
+        };
+        let Ok(id) = id.parse::<Uuid>() else {
+            return invalid();
+        };
+        let Some(link) = self.store.get_invite_link(id) else {
+            return invalid();
+        };
+        if !self.verify_invite_link(&link, signature) {
+            return invalid();
+        }
+        // Links are revoked when their creator loses the role, but one made
+        // while that was happening may have slipped through.
+        let still_admin = self
+            .store
+            .get_room(link.room_id)
+            .is_some_and(|r| r.has_role(&link.created_by, RoomRole::Admin));
+        if !still_admin {
+            return invalid();
+        }
+        if !link.is_usable(chrono::Utc::now()) {
+            return ServerMessage::Error(ErrorResponse::new(410, "Invite link expired"));
+        }
+
+        let store = &self.store;
+        match self.admit_member(link.room_id, &peer, |_| store.use_invite_link(id).is_some()) {
+            Ok(room) => Self::room_response(room),
+            Err(e) => ServerMessage::Error(e),
+        }
+    }
+
+    /// Signs `link` with the ego holding its creator's zone.
+    fn sign_invite_link(&self, link: &InviteLink) -> Result<String, ErrorResponse> {
+        let zone = self
+            .user_zone(&link.created_by)
+            .ok_or_else(|| ErrorResponse::new(404, "User not found"))?;
+        let ego = self
+            .identity
+            .list_egos()
+            .into_iter()
+            .find(|ego| ego.public_key.as_str() == zone.as_str())
+            .ok_or_else(|| ErrorResponse::new(500, "No signing key for this ego"))?;
+        ego.private_key
+            .sign(SIGNATURE_PURPOSE_INVITE_LINK, &link.signed_data())
+            .map_err(|e| ErrorResponse::new(500, e.to_string()))
+    }
+
+    /// Whether `signature` was made over `link` with its creator's zone key.
+    fn verify_invite_link(&self, link: &InviteLink, signature: &str) -> bool {
+        self.user_zone(&link.created_by)
+            .and_then(|zone| PeerIdentity::from_public_key(&zone).ok())
+            .is_some_and(|key| {
+                key.verify(
+                    SIGNATURE_PURPOSE_INVITE_LINK,
+                    &link.signed_data(),
+                    signature,
+                )
+            })
+    }
+
+    fn handle_list_public_rooms(&self, req: ListPublicRoomsRequest) -> ServerMessage {
+        let limit = req.limit.unwrap_or(50) as usize;
+        let rooms: Vec<ChatRoom> = self
+            .store
+            .get_public_rooms()
+            .into_iter()
+            .filter(|r| req.before.is_none_or(|before| r.created_at < before))
+            .take(limit)
+            .collect();
+
+        ServerMessage::Room(RoomResponse {
+            room: None,
+            rooms: Some(rooms),
+        })
+    }
+
+    fn invite_response(invite: RoomInvite) -> ServerMessage {
+        ServerMessage::RoomInvite(RoomInviteResponse {
+            invite: Some(invite),
+            invites: None,
+        })
+    }
+
+    fn handle_leave_room(&self, req: LeaveRoomRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let mut rooms = self.store.rooms.write();
+        let Some(room) = rooms.get_mut(&req.room_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        };
+        if room.owner_id == peer && room.members.len() > 1 {
+            return ServerMessage::Error(ErrorResponse::new(
+                409,
+                "Transfer ownership before leaving",
+            ));
+        }
+        // Nobody would be left to run the room.
+        if room.owner_id == peer {
+            drop(rooms);
+            return self.remove_room(req.room_id, &peer);
+        }
+        if !room.remove_member(&peer) {
+            return ServerMessage::Error(ErrorResponse::new(404, "Not a member of this room"));
+        }
+        let updated = room.clone();
+        drop(rooms);
+        self.store.revoke_invite_links(&updated);
+        if updated.encrypted && !updated.members.is_empty() {
+            self.request_rekey(&updated);
+        }
+
+        self.emit(
+            Audience::members(&updated).and(&peer),
+            EventMessage::RoomMemberRemoved {
+                room_id: req.room_id,
+                peer_id: peer.to_string(),
+                removed_by: peer.to_string(),
+                banned: false,
+            },
+        );
+        ServerMessage::Room(RoomResponse {
+            room: None,
+            rooms: None,
+        })
+    }
+
+    fn handle_kick_member(&self, req: KickMemberRequest) -> ServerMessage {
+        let (peer, target) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        self.remove_from_room(req.room_id, &peer, &target, false)
+    }
+
+    fn handle_ban_member(&self, req: BanMemberRequest) -> ServerMessage {
+        let (peer, target) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        self.remove_from_room(req.room_id, &peer, &target, true)
+    }
+
+    /// Kicks or bans `target`; moderators may do so to lower roles, and may
+    /// ban peers who are not members.
+    fn remove_from_room(
+        &self,
+        room_id: Uuid,
+        actor: &PeerIdentity,
+        target: &PeerIdentity,
+        ban: bool,
+    ) -> ServerMessage {
+        let mut rooms = self.store.rooms.write();
+        let Some(room) = rooms.get_mut(&room_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        };
+        if let Err(e) = check_outranks(room, actor, target, RoomRole::Moderator) {
+            return ServerMessage::Error(e);
+        }
+        if !ban && !room.is_member(target) {
+            return ServerMessage::Error(ErrorResponse::new(404, "Not a member of this room"));
+        }
+
+        let removed = room.remove_member(target);
+        if ban && !room.is_banned(target) {
+            room.banned.push(target.clone());
+        }
+        let updated = room.clone();
+        drop(rooms);
+        self.store.revoke_invite_links(&updated);
+        if removed && updated.encrypted && !updated.members.is_empty() {
+            self.request_rekey(&updated);
+        }
+
+        self.emit(
+            Audience::members(&updated).and(target),
+            EventMessage::RoomMemberRemoved {
+                room_id,
+                peer_id: target.to_string(),
+                removed_by: actor.to_string(),
+                banned: ban,
+            },
+        );
+        Self::room_response(updated)
+    }
+
+    fn handle_unban_member(&self, req: UnbanMemberRequest) -> ServerMessage {
+        let (peer, target) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let mut rooms = self.store.rooms.write();
+        let Some(room) = rooms.get_mut(&req.room_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        };
+        if !room.has_role(&peer, RoomRole::Moderator) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Requires the moderator role"));
+        }
+        if !room.is_banned(&target) {
+            return ServerMessage::Error(ErrorResponse::new(404, "Not banned from this room"));
+        }
+        room.banned.retain(|p| *p != target);
+        let updated = room.clone();
+        drop(rooms);
+
+        self.emit(
+            Audience::members(&updated).and(&target),
+            EventMessage::RoomMemberUnbanned {
+                room_id: req.room_id,
+                peer_id: target.to_string(),
+                unbanned_by: peer.to_string(),
+            },
+        );
+        Self::room_response(updated)
+    }
+
+    /// Sets a member's role. Admins give roles below their own to members
+    /// below them; ownership only changes hands through
+    /// `TransferOwnership`.
+    fn handle_promote_member(&self, req: PromoteMemberRequest) -> ServerMessage {
+        let (peer, target) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if req.role == RoomRole::Owner {
+            return ServerMessage::Error(ErrorResponse::new(
+                400,
+                "Use TransferOwnership to change the owner",
+            ));
+        }
+
+        let mut rooms = self.store.rooms.write();
+        let Some(room) = rooms.get_mut(&req.room_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        };
+        if let Err(e) = check_outranks(room, &peer, &target, RoomRole::Admin) {
+            return ServerMessage::Error(e);
+        }
+        if !room.is_member(&target) {
+            return ServerMessage::Error(ErrorResponse::new(404, "Not a member of this room"));
+        }
+        if room.role_of(&peer).is_none_or(|role| req.role >= role) {
+            return ServerMessage::Error(ErrorResponse::new(
+                403,
+                "Cannot give a role equal to or above your own",
+            ));
+        }
+        room.set_role(&target, req.role);
+        let updated = room.clone();
+        drop(rooms);
+        self.store.revoke_invite_links(&updated);
+
+        self.emit(
+            Audience::members(&updated),
+            EventMessage::RoomRoleChanged {
+                room_id: req.room_id,
+                peer_id: target.to_string(),
+                role: req.role,
+                changed_by: peer.to_string(),
+            },
+        );
+        Self::room_response(updated)
+    }
+
+    fn handle_update_room(&self, req: UpdateRoomRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let mut rooms = self.store.rooms.write();
+        let Some(room) = rooms.get_mut(&req.room_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        };
+        if !room.has_role(&peer, RoomRole::Admin) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Requires the admin role"));
+        }
+        if let Some(name) = req.name {
+            room.name = name;
+        }
+        if let Some(description) = req.description {
+            room.description = Some(description).filter(|d| !d.is_empty());
+        }
+        if let Some(is_public) = req.is_public {
+            room.is_public = is_public;
+        }
+        let updated = room.clone();
+        drop(rooms);
+        self.store.index_room(&updated);
+
+        self.emit(
+            Audience::members(&updated),
+            EventMessage::RoomUpdated {
+                room: updated.clone(),
+                updated_by: peer.to_string(),
+            },
+        );
+        Self::room_response(updated)
+    }
+
+    fn handle_delete_room(&self, req: DeleteRoomRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        self.remove_room(req.room_id, &peer)
+    }
+
+    fn remove_room(&self, room_id: Uuid, owner: &PeerIdentity) -> ServerMessage {
+        let room = match self.store.delete_room(room_id, owner) {
+            Ok(room) => room,
+            Err(e) => return ServerMessage::Error(Self::room_error(e)),
+        };
+        self.emit(
+            Audience::members(&room),
+            EventMessage::RoomDeleted {
+                room_id,
+                deleted_by: owner.to_string(),
+            },
+        );
+        ServerMessage::Room(RoomResponse {
+            room: None,
+            rooms: None,
+        })
+    }
+
+    /// Makes a member the owner; the previous owner stays on as an admin.
+    fn handle_transfer_ownership(&self, req: TransferOwnershipRequest) -> ServerMessage {
+        let (peer, target) = match self.room_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let mut rooms = self.store.rooms.write();
+        let Some(room) = rooms.get_mut(&req.room_id) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        };
+        if room.owner_id != peer {
+            return ServerMessage::Error(ErrorResponse::new(
+                403,
+                "Only the owner can transfer ownership",
+            ));
+        }
+        if !room.is_member(&target) || target == peer {
+            return ServerMessage::Error(ErrorResponse::new(404, "Not a member of this room"));
+        }
+        room.set_role(&target, RoomRole::Admin);
+        room.owner_id = target.clone();
+        let updated = room.clone();
+        drop(rooms);
+        self.store.revoke_invite_links(&updated);
+
+        self.emit(
+            Audience::members(&updated),
+            EventMessage::RoomOwnershipTransferred {
+                room_id: req.room_id,
+                previous_owner: peer.to_string(),
+                owner: target.to_string(),
+            },
+        );
+        Self::room_response(updated)
+    }
+
+    /// The acting peer and the member `name` refers to.
+    fn room_target(
+        &self,
+        ego: Option<&str>,
+        name: &str,
+    ) -> Result<(PeerIdentity, PeerIdentity), ErrorResponse> {
+        let peer = self.acting_peer(ego)?;
+        let target = self.resolve_peer(&peer, name)?;
+        Ok((peer, target))
+    }
+
+    fn room_response(room: ChatRoom) -> ServerMessage {
+        ServerMessage::Room(RoomResponse {
+            room: Some(room),
+            rooms: None,
+        })
+    }
+
+    fn handle_send_room_message(&self, req: SendRoomMessageRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let room = match self.store.get_room(req.room_id) {
+            Some(r) => r,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Room not found")),
+        };
+        if let Err(e) = check_room_content(&room, &peer, &req.content) {
+            return ServerMessage::Error(e);
+        }
+        if let Err(e) = self.store.media.retain(&req.media_hashes) {
+            return ServerMessage::Error(Self::media_error(e));
+        }
+
+        let mut msg = ChatMessage::new(req.room_id, peer, req.content);
+        msg.media_hashes = req.media_hashes;
+        msg.reply_to = req.reply_to;
+
+        self.store.add_message(msg.clone());
+        self.emit(
+            Audience::members(&room),
+            EventMessage::NewRoomMessage {
+                room_id: req.room_id,
+                message: msg.clone(),
+            },
+        );
+        ServerMessage::RoomMessage(RoomMessageResponse {
+            message: Some(msg),
+            messages: None,
+        })
+    }
+
+    fn handle_edit_room_message(&self, req: EditRoomMessageRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let msg = match self.store.get_message(req.message_id) {
+            Some(m) if !m.is_deleted() => m,
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Message not found")),
+        };
+        if msg.sender_id != peer {
+            return ServerMessage::Error(ErrorResponse::new(
+                403,
+                "Only the sender can edit a message",
+            ));
+        }
+        let room = match self.store.get_room(msg.room_id) {
+            Some(r) => r,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Room not found")),
+        };
+        if let Err(e) = check_room_content(&room, &peer, &req.content) {
+            return ServerMessage::Error(e);
+        }
+
+        let mut messages = self.store.messages.write();
+        let Some(stored) = messages
+            .get_mut(&req.message_id)
+            .filter(|m| !m.is_deleted())
+        else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Message not found"));
+        };
+        stored.edit(req.content);
+        let mut updated = stored.clone();
+        drop(messages);
+
+        self.emit(
+            self.room_audience(updated.room_id),
+            EventMessage::RoomMessageEdited {
+                room_id: updated.room_id,
+                message: updated.clone(),
+            },
+        );
+        self.mark_reacted_messages([&mut updated], Some(&peer));
+        ServerMessage::RoomMessage(RoomMessageResponse {
+            message: Some(updated),
+            messages: None,
+        })
+    }
+
+    fn handle_delete_room_message(&self, req: DeleteRoomMessageRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let msg = match self.store.get_message(req.message_id) {
+            Some(m) if !m.is_deleted() => m,
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Message not found")),
+        };
+        let is_moderator = self
+            .store
+            .get_room(msg.room_id)
+            .is_some_and(|r| r.has_role(&peer, RoomRole::Moderator));
+        if msg.sender_id != peer && !is_moderator {
+            return ServerMessage::Error(ErrorResponse::new(
+                403,
+                "Only the sender or a room moderator can delete a message",
+            ));
+        }
+
+        if self.tombstone_message(msg.id, &peer) {
+            self.federate(&SocialCadetMessage::Tombstone {
+                target: TombstoneTarget::Chat {
+                    room_id: msg.room_id,
+                    message_id: msg.id,
+                },
+            });
+        }
+        ServerMessage::RoomMessage(RoomMessageResponse {
+            message: self.store.get_message(msg.id),
+            messages: None,
+        })
+    }
+
+    fn tombstone_message(&self, message_id: Uuid, deleted_by: &PeerIdentity) -> bool {
+        let Some(previous) = self.store.delete_message(message_id) else {
+            return false;
+        };
+        self.store.media.release(&previous.media_hashes);
+        self.emit(
+            self.room_audience(previous.room_id),
+            EventMessage::RoomMessageDeleted {
+                room_id: previous.room_id,
+                message_id,
+                deleted_by: deleted_by.to_string(),
+            },
+        );
+        true
+    }
+
+    fn handle_rotate_group_key(&self, req: RotateGroupKeyRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let room = match self.encrypted_room_of(req.room_id, &peer) {
+            Ok(r) => r,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        if req.keys.values().any(|k| k.validate().is_err()) {
+            return ServerMessage::Error(ErrorResponse::new(400, "Malformed sealed group key"));
+        }
+
+        let rotated = self.store.rotate_group_key(GroupKeyEpoch {
+            room_id: room.id,
+            epoch: req.epoch,
+            distributed_by: peer.clone(),
+            keys: req.keys,
+            created_at: chrono::Utc::now(),
+        });
+        if let Err(e) = rotated {
+            return ServerMessage::Error(Self::group_key_error(e));
+        }
+
+        self.emit(
+            self.room_audience(room.id),
+            EventMessage::GroupKeyRotated {
+                room_id: room.id,
+                epoch: req.epoch,
+                distributed_by: peer.to_string(),
+            },
+        );
+        self.group_keys(room.id, &peer)
+    }
+
+    fn handle_get_group_keys(&self, req: GetGroupKeysRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if let Err(e) = self.encrypted_room_of(req.room_id, &peer) {
+            return ServerMessage::Error(e);
+        }
+        self.group_keys(req.room_id, &peer)
+    }
+
+    /// Looks up an encrypted room that `peer` currently belongs to.
+    fn encrypted_room_of(
+        &self,
+        room_id: Uuid,
+        peer: &PeerIdentity,
+    ) -> Result<ChatRoom, ErrorResponse> {
+        let room = self
+            .store
+            .get_room(room_id)
+            .ok_or_else(|| ErrorResponse::new(404, "Room not found"))?;
+        if !room.encrypted {
+            return Err(ErrorResponse::new(400, "Room is not encrypted"));
+        }
+        if !room.is_member(peer) {
+            return Err(ErrorResponse::new(403, "Not a member of this room"));
+        }
+        Ok(room)
+    }
+
+    /// The keys sealed to `peer`, with the current members for the next rotation.
+    fn group_keys(&self, room_id: Uuid, peer: &PeerIdentity) -> ServerMessage {
+        let room = match self.store.get_room(room_id) {
+            Some(r) => r,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Room not found")),
+        };
+        let keys = self
+            .store
+            .get_group_keys(room_id, peer)
+            .into_iter()
+            .map(|(epoch, key)| SealedGroupKey { epoch, key })
+            .collect();
+
+        ServerMessage::GroupKey(GroupKeyResponse {
+            room_id,
+            epoch: room.key_epoch,
+            rekey_pending: room.rekey_pending,
+            members: self.group_key_members(&room),
+            keys,
+        })
+    }
+
+    fn group_key_members(&self, room: &ChatRoom) -> Vec<GroupKeyMember> {
+        room.members
+            .iter()
+            .map(|m| GroupKeyMember {
+                peer_id: m.to_string(),
+                zone: self.store.get_user(m).map(|u| u.gns_zone),
+            })
+            .collect()
+    }
+
+    /// Asks the members of `room` to distribute a key for the next epoch.
+    fn request_rekey(&self, room: &ChatRoom) {
+        self.emit(
+            Audience::members(room),
+            EventMessage::GroupKeyRotationRequired {
+                room_id: room.id,
+                epoch: room.key_epoch + 1,
+                members: self.group_key_members(room),
+            },
+        );
+    }
+
+    fn handle_get_room_messages(&self, req: GetRoomMessagesRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let room = match self.store.get_room(req.room_id) {
+            Some(room) if room.is_public || room.is_member(&peer) => room,
+            Some(_) => {
+                return ServerMessage::Error(ErrorResponse::new(403, "Not a member of this room"));
+            }
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Room not found")),
+        };
+        let limit = req.limit.unwrap_or(100) as usize;
+        let mut messages: Vec<ChatMessage> = self
+            .store
+            .get_room_messages(req.room_id)
+            .into_iter()
+            .take(limit)
+            .collect();
+        self.mark_reacted_messages(&mut messages, Some(&peer));
+        if room.is_member(&peer) {
+            let now = chrono::Utc::now();
+            let mut fresh = Vec::new();
+            for msg in messages.iter_mut().filter(|m| !m.is_deleted()) {
+                if msg.advance(&peer, DeliveryState::Delivered, now) {
+                    fresh.push(msg.id);
+                }
+            }
+            self.queue_delivery(req.room_id, &peer, fresh);
+        }
+
+        ServerMessage::RoomMessage(RoomMessageResponse {
+            message: None,
+            messages: Some(messages),
+        })
+    }
+
+    fn handle_request_friend(&self, req: RequestFriendRequest) -> ServerMessage {
+        let (peer, addressee) = match self.friend_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let friendship = match self.store.request_friendship(&peer, &addressee) {
+            Ok(f) => f,
+            Err(e) => return ServerMessage::Error(Self::friendship_error(e)),
+        };
+        match friendship.status {
+            FriendshipStatus::Accepted => self.announce_friendship(&friendship, &peer),
+            _ => {
+                self.emit(
+                    Audience::Peers(vec![peer.clone(), addressee.clone()]),
+                    EventMessage::FriendRequest {
+                        from: peer.to_string(),
+                        friendship: friendship.clone(),
+                    },
+                );
+                self.notify(Notification::new(
+                    addressee,
+                    NotificationKind::FriendRequest,
+                    peer,
+                ));
+            }
+        }
+        Self::friend_response(friendship)
+    }
+
+    fn handle_accept_friend(&self, req: AcceptFriendRequest) -> ServerMessage {
+        let (peer, requester) = match self.friend_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        match self.store.accept_friendship(&peer, &requester) {
+            Ok(friendship) => {
+                self.announce_friendship(&friendship, &peer);
+                Self::friend_response(friendship)
+            }
+            Err(e) => ServerMessage::Error(Self::friendship_error(e)),
+        }
+    }
+
+    fn handle_reject_friend(&self, req: RejectFriendRequest) -> ServerMessage {
+        let (peer, requester) = match self.friend_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let result = self.store.reject_friendship(&peer, &requester);
+        self.end_friendship(result, &peer)
+    }
+
+    fn handle_cancel_friend_request(&self, req: CancelFriendRequestRequest) -> ServerMessage {
+        let (peer, addressee) = match self.friend_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let result = self.store.cancel_friend_request(&peer, &addressee);
+        self.end_friendship(result, &peer)
+    }
+
+    fn handle_unfriend(&self, req: UnfriendRequest) -> ServerMessage {
+        let (peer, friend) = match self.friend_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let result = self.store.unfriend(&peer, &friend);
+        self.end_friendship(result, &peer)
+    }
+
+    fn handle_get_pending_requests(&self, req: GetPendingRequestsRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+
+        ServerMessage::Friend(FriendResponse {
+            friendship: None,
+            friendships: Some(self.store.get_pending_requests(&peer, req.direction)),
+        })
+    }
+
+    /// The acting peer and the peer `name` refers to.
+    fn friend_target(
+        &self,
+        ego: Option<&str>,
+        name: &str,
+    ) -> Result<(PeerIdentity, PeerIdentity), ErrorResponse> {
+        let peer = self.acting_peer(ego)?;
+        let target = self.resolve_peer(&peer, name)?;
+        Ok((peer, target))
+    }
+
+    /// Tells the requester of a just accepted `friendship` that `accepter`
+    /// accepted it.
+    fn announce_friendship(&self, friendship: &Friendship, accepter: &PeerIdentity) {
+        self.emit(
+            Self::friendship_audience(friendship),
+            EventMessage::FriendAccepted {
+                peer_id: accepter.to_string(),
+            },
+        );
+        self.notify(Notification::new(
+            friendship.other(accepter).clone(),
+            NotificationKind::FriendAccepted,
+            accepter.clone(),
+        ));
+    }
+
+    /// Both parties to `friendship`.
+    fn friendship_audience(friendship: &Friendship) -> Audience {
+        Audience::Peers(vec![
+            friendship.requester_id.clone(),
+            friendship.addressee_id.clone(),
+        ])
+    }
+
+    fn end_friendship(
+        &self,
+        result: Result<Friendship, FriendshipError>,
+        peer: &PeerIdentity,
+    ) -> ServerMessage {
+        match result {
+            Ok(friendship) => {
+                self.emit(
+                    Self::friendship_audience(&friendship),
+                    EventMessage::FriendshipEnded {
+                        friendship: friendship.clone(),
+                        ended_by: peer.to_string(),
+                    },
+                );
+                Self::friend_response(friendship)
+            }
+            Err(e) => ServerMessage::Error(Self::friendship_error(e)),
+        }
+    }
+
+    fn friend_response(friendship: Friendship) -> ServerMessage {
+        ServerMessage::Friend(FriendResponse {
+            friendship: Some(friendship),
+            friendships: None,
+        })
+    }
+
+    fn handle_block_user(&self, req: BlockUserRequest) -> ServerMessage {
+        let (peer, target) = match self.relation_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        self.store.block(&peer, &target);
+        self.blocks_response(&peer)
+    }
+
+    fn handle_unblock_user(&self, req: UnblockUserRequest) -> ServerMessage {
+        let (peer, target) = match self.relation_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if !self.store.unblock(&peer, &target) {
+            return ServerMessage::Error(ErrorResponse::new(404, "User not blocked"));
+        }
+        self.blocks_response(&peer)
+    }
+
+    fn handle_mute_user(&self, req: MuteUserRequest) -> ServerMessage {
+        let (peer, target) = match self.relation_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        self.store.mute(&peer, &target);
+        self.blocks_response(&peer)
+    }
+
+    fn handle_unmute_user(&self, req: UnmuteUserRequest) -> ServerMessage {
+        let (peer, target) = match self.relation_target(req.ego.as_deref(), &req.peer_id) {
+            Ok(pair) => pair,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if !self.store.unmute(&peer, &target) {
+            return ServerMessage::Error(ErrorResponse::new(404, "User not muted"));
+        }
+        self.blocks_response(&peer)
+    }
+
+    fn handle_get_blocked_users(&self, req: GetBlockedUsersRequest) -> ServerMessage {
+        match self.acting_peer(req.ego.as_deref()) {
+            Ok(peer) => self.blocks_response(&peer),
+            Err(e) => ServerMessage::Error(e),
+        }
+    }
+
+    /// The acting peer and the other peer of a block or mute, which must
+    /// differ.
+    fn relation_target(
+        &self,
+        ego: Option<&str>,
+        name: &str,
+    ) -> Result<(PeerIdentity, PeerIdentity), ErrorResponse> {
+        let peer = self.acting_peer(ego)?;
+        let target = self.resolve_peer(&peer, name)?;
+        if target == peer {
+            return Err(ErrorResponse::new(400, "Cannot block or mute yourself"));
+        }
+        Ok((peer, target))
+    }
+
+    fn blocks_response(&self, peer: &PeerIdentity) -> ServerMessage {
+        let strings = |peers: Vec<PeerIdentity>| peers.iter().map(|p| p.to_string()).collect();
+        ServerMessage::Blocks(BlocksResponse {
+            blocked: strings(self.store.get_blocked(peer)),
+            muted: strings(self.store.get_muted(peer)),
+        })
+    }
+
+    fn handle_get_friends(&self, _req: GetFriendsRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+
+        ServerMessage::Friend(FriendResponse {
+            friendship: None,
+            friendships: Some(self.store.get_friendships(&peer)),
+        })
+    }
+
+    fn handle_send_private_message(&self, req: SendPrivateMessageRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let recipient = match self.resolve_peer(&peer, &req.recipient_id) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        if let Err(e) = req.content.verify(&peer, &recipient) {
+            return ServerMessage::Error(Self::envelope_error(e));
+        }
+        if self.store.blocked_between(&peer, &recipient) {
+            return ServerMessage::Error(ErrorResponse::new(403, "Blocked"));
+        }
+        if let Err(e) = self.store.media.retain(&req.media_hashes) {
+            return ServerMessage::Error(Self::media_error(e));
+        }
+        let mut msg = PrivateMessage::new(peer, recipient, req.content);
+        msg.media_hashes = req.media_hashes;
+
+        let local = self.store.get_user(&msg.recipient_id).is_some();
+        if local {
+            msg.advance(DeliveryState::Delivered, msg.created_at);
+        }
+        self.store.add_private_message(msg.clone());
+        self.emit(
+            Audience::Peers(vec![msg.sender_id.clone(), msg.recipient_id.clone()]),
+            EventMessage::NewPrivateMessage {
+                message: msg.clone(),
+            },
+        );
+        if !local {
+            self.send_to(
+                &msg.recipient_id,
+                &SocialCadetMessage::PrivateMessage {
+                    message: msg.clone(),
+                },
+            );
+        }
+        ServerMessage::PrivateMessage(PrivateMessageResponse {
+            message: Some(msg),
+            messages: None,
+        })
+    }
+
+    fn handle_get_private_messages(&self, req: GetPrivateMessagesRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+
+        let limit = req.limit.unwrap_or(100) as usize;
+        let messages = match req.peer_id {
+            Some(other_peer) => {
+                let other = match self.resolve_peer(&peer, &other_peer) {
+                    Ok(p) => p,
+                    Err(e) => return ServerMessage::Error(e),
+                };
+                self.store
+                    .get_conversation_messages(&peer, &other, req.before, limit)
+            }
+            None => {
+                let mut messages = self.store.get_private_messages(&peer);
+                messages.truncate(limit);
+                messages
+            }
+        };
+
+        ServerMessage::PrivateMessage(PrivateMessageResponse {
+            message: None,
+            messages: Some(messages),
+        })
+    }
+
+    fn handle_get_conversations(&self, req: GetConversationsRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+        let limit = req.limit.unwrap_or(50) as usize;
+
+        let mut conversations: Vec<ConversationSummary> = self
+            .store
+            .get_conversations(&peer)
+            .into_iter()
+            .filter(|c| req.before.is_none_or(|before| c.last_activity < before))
+            .collect();
+        let has_more = conversations.len() > limit;
+        conversations.truncate(limit);
+
+        ServerMessage::Conversation(ConversationResponse {
+            conversation: None,
+            conversations: Some(conversations),
+            has_more,
+        })
+    }
+
+    fn handle_mark_conversation_read(&self, req: MarkConversationReadRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
+        let other = match self.resolve_peer(&peer, &req.peer_id) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
 
-        let mut rooms = self.store.rooms.write();
-        if let Some(room) = rooms.get_mut(&req.room_id) {
-            if !room.members.contains(&peer) {
-                room.members.push(peer);
+        let marked = self.store.mark_conversation_read(&peer, &other);
+        if let Some(read_at) = marked.first().and_then(|m| m.read_at) {
+            // The sender only hears of it if the reader sends read receipts.
+            let mut audience = vec![peer.clone()];
+            if self.store.sends_read_receipts(&peer) {
+                audience.push(other.clone());
             }
-            let updated = room.clone();
-            drop(rooms);
-            ServerMessage::Room(RoomResponse {
-                room: Some(updated),
-                rooms: None,
-            })
-        } else {
-            ServerMessage::Error(ErrorResponse::new(404, "Room not found"))
+            self.emit(
+                Audience::Peers(audience),
+                EventMessage::ConversationRead {
+                    peer_id: peer.to_string(),
+                    sender_id: other.to_string(),
+                    message_ids: marked.iter().map(|m| m.id).collect(),
+                    read_at,
+                },
+            );
         }
+        self.send_read_receipts(&peer, &marked);
+        let Some(conversation) = self.store.get_conversation(&peer, &other) else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Conversation not found"));
+        };
+        ServerMessage::Conversation(ConversationResponse {
+            conversation: Some(conversation),
+            conversations: None,
+            has_more: false,
+        })
     }
 
-    fn handle_leave_room(&self, req: LeaveRoomRequest) -> ServerMessage {
+    /// Acknowledges that the requester read messages sent to them.
+    fn handle_message_read(&self, req: MessageReadRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        let read = match &req.target {
+            ReceiptTarget::PrivateMessage { message_ids } => {
+                let marked = self.store.mark_private_messages_read(&peer, message_ids);
+                self.send_read_receipts(&peer, &marked);
+                marked.iter().map(|m| m.id).collect()
+            }
+            ReceiptTarget::Room { room_id, .. } => {
+                match self.store.get_room(*room_id) {
+                    Some(room) if room.is_member(&peer) => {}
+                    Some(_) => {
+                        return ServerMessage::Error(ErrorResponse::new(403, "Not a member"));
+                    }
+                    None => {
+                        return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+                    }
+                }
+                match self.store.sends_read_receipts(&peer) {
+                    true => self.apply_receipt(
+                        &req.target,
+                        &peer,
+                        DeliveryState::Read,
+                        chrono::Utc::now(),
+                    ),
+                    false => Vec::new(),
+                }
+            }
+        };
+
+        ServerMessage::Receipt(ReceiptResponse {
+            target: req.target.with_ids(read),
+            state: DeliveryState::Read,
+        })
+    }
+
+    /// Tells the senders of `messages` that `peer` read them, for those
+    /// that reached [`DeliveryState::Read`].
+    fn send_read_receipts(&self, peer: &PeerIdentity, messages: &[PrivateMessage]) {
+        let read: Vec<&PrivateMessage> = messages
+            .iter()
+            .filter(|m| m.state == DeliveryState::Read)
+            .collect();
+        let Some(at) = read.first().and_then(|m| m.read_at) else {
+            return;
+        };
+        let message_ids: Vec<Uuid> = read.iter().map(|m| m.id).collect();
+        let senders = read.iter().map(|m| m.sender_id.clone()).collect();
+        self.emit(
+            Audience::Peers(senders).and(peer),
+            EventMessage::MessageReceipt {
+                target: ReceiptTarget::PrivateMessage {
+                    message_ids: message_ids.clone(),
+                },
+                peer_id: peer.to_string(),
+                state: DeliveryState::Read,
+                at,
+            },
+        );
+
+        let mut remote: HashMap<&PeerIdentity, Vec<Uuid>> = HashMap::new();
+        for msg in read
+            .iter()
+            .filter(|m| self.store.get_user(&m.sender_id).is_none())
+        {
+            remote.entry(&msg.sender_id).or_default().push(msg.id);
+        }
+        for (sender, message_ids) in remote {
+            self.send_to(
+                sender,
+                &SocialCadetMessage::Receipt {
+                    target: ReceiptTarget::PrivateMessage { message_ids },
+                    state: DeliveryState::Read,
+                    at,
+                },
+            );
+        }
+    }
+
+    fn handle_set_read_receipts(&self, req: SetReadReceiptsRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        if let Some(enabled) = req.enabled {
+            self.store.set_read_receipts(&peer, enabled);
+        }
+        ServerMessage::ReadReceipts(ReadReceiptsResponse {
+            enabled: self.store.sends_read_receipts(&peer),
+        })
+    }
+
+    fn handle_edit_private_message(&self, req: EditPrivateMessageRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let mut rooms = self.store.rooms.write();
-        if let Some(room) = rooms.get_mut(&req.room_id) {
-            room.members.retain(|m| m != &peer);
-            drop(rooms);
-            ServerMessage::Room(RoomResponse {
-                room: None,
-                rooms: None,
-            })
-        } else {
-            drop(rooms);
-            ServerMessage::Error(ErrorResponse::new(404, "Room not found"))
+        let mut messages = self.store.private_messages.write();
+        let Some(stored) = messages
+            .get_mut(&req.message_id)
+            .filter(|m| !m.is_deleted())
+        else {
+            return ServerMessage::Error(ErrorResponse::new(404, "Message not found"));
+        };
+        if stored.sender_id != peer {
+            return ServerMessage::Error(ErrorResponse::new(
+                403,
+                "Only the sender can edit a message",
+            ));
+        }
+        if let Err(e) = req.content.verify(&stored.sender_id, &stored.recipient_id) {
+            return ServerMessage::Error(Self::envelope_error(e));
         }
+        stored.edit(req.content);
+        let updated = stored.clone().seen_by(&peer);
+        drop(messages);
+
+        self.emit(
+            Audience::Peers(vec![
+                updated.sender_id.clone(),
+                updated.recipient_id.clone(),
+            ]),
+            EventMessage::PrivateMessageEdited {
+                message: updated.clone(),
+            },
+        );
+        ServerMessage::PrivateMessage(PrivateMessageResponse {
+            message: Some(updated),
+            messages: None,
+        })
     }
 
-    fn handle_send_room_message(&self, req: SendRoomMessageRequest) -> ServerMessage {
+    fn handle_delete_private_message(&self, req: DeletePrivateMessageRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let rooms = self.store.rooms.read();
-        if !rooms.contains_key(&req.room_id) {
-            return ServerMessage::Error(ErrorResponse::new(404, "Room not found"));
+        let recipient = match self.store.get_private_message(req.message_id) {
+            Some(m) if !m.is_deleted() => {
+                if m.sender_id != peer {
+                    return ServerMessage::Error(ErrorResponse::new(
+                        403,
+                        "Only the sender can delete a message",
+                    ));
+                }
+                m.recipient_id
+            }
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Message not found")),
+        };
+
+        if self.tombstone_private_message(req.message_id)
+            && self.store.get_user(&recipient).is_none()
+        {
+            self.send_to(
+                &recipient,
+                &SocialCadetMessage::Tombstone {
+                    target: TombstoneTarget::PrivateMessage {
+                        message_id: req.message_id,
+                    },
+                },
+            );
         }
-        drop(rooms);
+        ServerMessage::PrivateMessage(PrivateMessageResponse {
+            message: self
+                .store
+                .get_private_message(req.message_id)
+                .map(|m| m.seen_by(&peer)),
+            messages: None,
+        })
+    }
 
-        let mut msg = ChatMessage::new(req.room_id, peer, req.content);
-        msg.media_hashes = req.media_hashes;
-        msg.reply_to = req.reply_to;
+    fn tombstone_private_message(&self, message_id: Uuid) -> bool {
+        let Some(previous) = self.store.delete_private_message(message_id) else {
+            return false;
+        };
+        self.store.media.release(&previous.media_hashes);
+        self.emit(
+            Audience::Peers(vec![
+                previous.sender_id.clone(),
+                previous.recipient_id.clone(),
+            ]),
+            EventMessage::PrivateMessageDeleted {
+                message_id,
+                sender_id: previous.sender_id.to_string(),
+                recipient_id: previous.recipient_id.to_string(),
+            },
+        );
+        true
+    }
 
-        self.store.add_message(msg.clone());
-        ServerMessage::RoomMessage(RoomMessageResponse {
-            message: Some(msg),
-            messages: None,
+    fn handle_get_notifications(&self, req: GetNotificationsRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let limit = req.limit.unwrap_or(50) as usize;
+
+        let mut notifications = self
+            .store
+            .get_notifications(&peer, req.before, req.unread_only);
+        let has_more = notifications.len() > limit;
+        notifications.truncate(limit);
+
+        ServerMessage::Notifications(NotificationsResponse {
+            notifications: Some(notifications),
+            has_more,
+            unread: self.store.unread_notifications(&peer),
+            muted: self.store.muted_notification_kinds(&peer),
         })
     }
 
-    fn handle_get_room_messages(&self, req: GetRoomMessagesRequest) -> ServerMessage {
-        let limit = req.limit.unwrap_or(100) as usize;
-        let messages: Vec<ChatMessage> = self
+    fn handle_mark_notifications_read(&self, req: MarkNotificationsReadRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        if self
             .store
-            .get_room_messages(req.room_id)
+            .mark_notifications_read(&peer, req.ids.as_deref())
+            > 0
+        {
+            self.emit_unread_notifications(&peer);
+        }
+        self.notifications_response(&peer)
+    }
+
+    fn handle_set_notification_muted(&self, req: SetNotificationMutedRequest) -> ServerMessage {
+        let peer = match self.acting_peer(req.ego.as_deref()) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        self.store
+            .set_notification_muted(&peer, req.kind, req.muted);
+        self.notifications_response(&peer)
+    }
+
+    fn notifications_response(&self, peer: &PeerIdentity) -> ServerMessage {
+        ServerMessage::Notifications(NotificationsResponse {
+            notifications: None,
+            has_more: false,
+            unread: self.store.unread_notifications(peer),
+            muted: self.store.muted_notification_kinds(peer),
+        })
+    }
+
+    fn handle_get_user(&self, req: GetUserRequest) -> ServerMessage {
+        let user = self.store.get_user(&req.peer_id);
+        ServerMessage::User(UserResponse { user })
+    }
+
+    fn handle_search_users(&self, req: SearchUsersRequest) -> ServerMessage {
+        let viewer = self.current_peer();
+        let limit = req.limit.unwrap_or(20) as usize;
+        let found = self
+            .store
+            .search
+            .read()
+            .search(&req.query, DocumentKind::User);
+
+        let users: Vec<User> = found
+            .into_iter()
+            .filter_map(|doc| match doc {
+                Document::User(id) => self.store.get_user(&id),
+                _ => None,
+            })
+            .filter(|u| {
+                viewer
+                    .as_ref()
+                    .is_none_or(|v| !self.store.blocked_between(v, &u.id))
+            })
+            .take(limit)
+            .collect();
+
+        ServerMessage::SearchUsers(SearchUsersResponse { users })
+    }
+
+    fn handle_search_posts(&self, req: SearchPostsRequest) -> ServerMessage {
+        let viewer = self.current_peer();
+        let limit = req.limit.unwrap_or(20) as usize;
+        let found = self
+            .store
+            .search
+            .read()
+            .search(&req.query, DocumentKind::Post);
+
+        let mut posts: Vec<Post> = found
             .into_iter()
+            .filter_map(|doc| match doc {
+                Document::Post(id) => self.store.get_post(id),
+                _ => None,
+            })
+            .filter(|p| !p.is_deleted() && self.store.can_view(p, viewer.as_ref()))
+            .filter(|p| !self.store.is_hidden_from(&p.author_id, viewer.as_ref()))
             .take(limit)
             .collect();
 
-        ServerMessage::RoomMessage(RoomMessageResponse {
-            message: None,
-            messages: Some(messages),
+        let mut originals = self.repost_originals(&posts, viewer.as_ref());
+        let media = self.post_media(posts.iter().chain(&originals));
+        self.mark_reacted_posts(posts.iter_mut().chain(&mut originals), viewer.as_ref());
+        ServerMessage::SearchPosts(SearchPostsResponse {
+            posts,
+            originals,
+            media,
         })
     }
 
-    fn handle_request_friend(&self, req: RequestFriendRequest) -> ServerMessage {
+    fn handle_search_rooms(&self, req: SearchRoomsRequest) -> ServerMessage {
+        let limit = req.limit.unwrap_or(20) as usize;
+        let found = self
+            .store
+            .search
+            .read()
+            .search(&req.query, DocumentKind::Room);
+
+        let rooms: Vec<ChatRoom> = found
+            .into_iter()
+            .filter_map(|doc| match doc {
+                Document::Room(id) => self.store.get_room(id),
+                _ => None,
+            })
+            .filter(|r| r.is_public)
+            .take(limit)
+            .collect();
+
+        ServerMessage::SearchRooms(SearchRoomsResponse { rooms })
+    }
+
+    fn handle_resolve_name(&self, req: ResolveNameRequest) -> ServerMessage {
+        let zone = self.current_peer().and_then(|peer| self.user_zone(&peer));
+        let user = self
+            .gns
+            .read()
+            .resolve_peer(zone.as_ref(), &req.name)
+            .and_then(|peer| self.store.get_user(&peer));
+        ServerMessage::User(UserResponse { user })
+    }
+
+    fn handle_set_petname(&self, req: SetPetnameRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let addressee = PeerIdentity::new(req.peer_id);
-        let friendship = Friendship::new(peer, addressee);
+        let zone = match self.user_zone(&peer) {
+            Some(z) => z,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "User not found")),
+        };
+        if !is_valid_label(&req.petname) || looks_like_peer_id(&req.petname) {
+            return ServerMessage::Error(ErrorResponse::new(400, "Invalid petname"));
+        }
 
-        self.store.request_friendship(friendship.clone());
-        ServerMessage::Friend(FriendResponse {
-            friendship: Some(friendship),
-            friends: None,
+        let target = match self.resolve_peer(&peer, &req.target) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+        let target_zone = match self.user_zone(&target) {
+            Some(z) => z,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "Target user not found")),
+        };
+
+        self.gns
+            .write()
+            .set_petname(&zone, &req.petname, &target_zone);
+        ServerMessage::Contact(ContactResponse {
+            contact: Some(self.contact(req.petname.to_lowercase(), target_zone)),
+            contacts: None,
         })
     }
 
-    fn handle_accept_friend(&self, req: AcceptFriendRequest) -> ServerMessage {
+    fn handle_remove_petname(&self, req: RemovePetnameRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let requester = PeerIdentity::new(req.peer_id);
-        if self.store.accept_friendship(&peer, &requester) {
-            ServerMessage::Friend(FriendResponse {
-                friendship: None,
-                friends: None,
+        let zone = match self.user_zone(&peer) {
+            Some(z) => z,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "User not found")),
+        };
+
+        if self.gns.write().remove_petname(&zone, &req.petname) {
+            ServerMessage::Contact(ContactResponse {
+                contact: None,
+                contacts: None,
             })
         } else {
-            ServerMessage::Error(ErrorResponse::new(404, "Friend request not found"))
+            ServerMessage::Error(ErrorResponse::new(404, "Petname not found"))
         }
     }
 
-    fn handle_get_friends(&self, _req: GetFriendsRequest) -> ServerMessage {
+    fn handle_list_contacts(&self, _req: ListContactsRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let friends: Vec<String> = self
-            .store
-            .get_friends(&peer)
+        let zone = match self.user_zone(&peer) {
+            Some(z) => z,
+            None => return ServerMessage::Error(ErrorResponse::new(404, "User not found")),
+        };
+
+        let petnames = self.gns.read().petnames(&zone);
+        let contacts = petnames
             .into_iter()
-            .map(|p| p.to_string())
+            .map(|(petname, target)| self.contact(petname, target))
             .collect();
 
-        ServerMessage::Friend(FriendResponse {
-            friendship: None,
-            friends: Some(friends),
+        ServerMessage::Contact(ContactResponse {
+            contact: None,
+            contacts: Some(contacts),
         })
     }
 
-    fn handle_send_private_message(&self, req: SendPrivateMessageRequest) -> ServerMessage {
-        let peer = match self.current_peer() {
+    fn handle_create_ego(&self, req: CreateEgoRequest) -> ServerMessage {
+        let account = match self.session.read().egos.first().cloned() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let recipient = PeerIdentity::new(req.recipient_id);
-        let mut msg = PrivateMessage::new(peer, recipient, req.content);
-        msg.media_hashes = req.media_hashes;
+        let ego = match self.claim_ego(&req.username) {
+            Ok(ego) => ego,
+            Err(e) => return ServerMessage::Error(e),
+        };
 
-        self.store.add_private_message(msg.clone());
-        ServerMessage::PrivateMessage(PrivateMessageResponse {
-            message: Some(msg),
-            messages: None,
+        // Personas are addressed by their ego key so they can't be linked to
+        // the account's peer id.
+        let mut user = User::new(req.username, ego.public_key.as_str().to_string());
+        user.id = match PeerIdentity::from_public_key(&ego.public_key) {
+            Ok(id) => id,
+            Err(e) => return ServerMessage::Error(ErrorResponse::new(500, e.to_string())),
+        };
+        user.display_name = req.display_name.unwrap_or_default();
+        user.bio = req.bio;
+
+        if let Err(e) = self.gns.write().publish_profile(&user) {
+            return Self::gns_error(e);
+        }
+        self.store.add_user(user.clone());
+        self.store.add_persona(&account, user.id.clone());
+
+        let mut session = self.session.write();
+        session.egos.push(user.id.clone());
+        if req.activate {
+            session.active = Some(user.id.clone());
+        }
+        let active = session.active.clone();
+        drop(session);
+
+        ServerMessage::Ego(EgoResponse {
+            ego: Some(self.ego_info(&user.id, active.as_ref())),
+            egos: None,
         })
     }
 
-    fn handle_get_private_messages(&self, req: GetPrivateMessagesRequest) -> ServerMessage {
+    fn handle_use_ego(&self, req: UseEgoRequest) -> ServerMessage {
+        let peer = match self.acting_peer(Some(&req.ego)) {
+            Ok(p) => p,
+            Err(e) => return ServerMessage::Error(e),
+        };
+
+        self.session.write().active = Some(peer.clone());
+        ServerMessage::Ego(EgoResponse {
+            ego: Some(self.ego_info(&peer, Some(&peer))),
+            egos: None,
+        })
+    }
+
+    fn handle_list_egos(&self, _req: ListEgosRequest) -> ServerMessage {
+        let session = self.session.read();
+        if session.egos.is_empty() {
+            return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated"));
+        }
+        let (egos, active) = (session.egos.clone(), session.active.clone());
+        drop(session);
+
+        let egos = egos
+            .iter()
+            .map(|peer| self.ego_info(peer, active.as_ref()))
+            .collect();
+        ServerMessage::Ego(EgoResponse {
+            ego: None,
+            egos: Some(egos),
+        })
+    }
+
+    fn user_zone(&self, peer: &PeerIdentity) -> Option<PublicKey> {
+        self.store
+            .get_user(peer)
+            .map(|u| PublicKey::new(u.gns_zone))
+    }
+
+    /// Resolves a raw peer id, petname or `username.gnu` name to a peer,
+    /// relative to the zone of `peer`. Peer ids are taken as they are, so
+    /// no name can stand in for one.
+    fn resolve_peer(&self, peer: &PeerIdentity, name: &str) -> Result<PeerIdentity, ErrorResponse> {
+        if let Ok(id) = name.parse() {
+            return Ok(id);
+        }
+        let zone = self.user_zone(peer);
+        self.gns
+            .read()
+            .resolve_peer(zone.as_ref(), name)
+            .ok_or_else(|| ErrorResponse::new(404, format!("Unknown peer or name: {}", name)))
+    }
+
+    fn contact(&self, petname: String, zone: PublicKey) -> Contact {
+        let user = self
+            .gns
+            .read()
+            .resolve_peer(Some(&zone), APEX_LABEL)
+            .and_then(|peer| self.store.get_user(&peer));
+        Contact {
+            petname,
+            zone: zone.as_str().to_string(),
+            user,
+        }
+    }
+
+    fn handle_upload_media(&self, req: UploadMediaRequest) -> ServerMessage {
         let peer = match self.current_peer() {
             Some(p) => p,
             None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
         };
 
-        let limit = req.limit.unwrap_or(100) as usize;
-        let mut messages = self.store.get_private_messages(&peer);
+        match self
+            .store
+            .media
+            .start_upload(peer, req.size, req.hash, req.keep_location)
+        {
+            Ok(status) => ServerMessage::Media(MediaResponse {
+                media: None,
+                upload: Some(status),
+                range: None,
+            }),
+            Err(e) => ServerMessage::Error(Self::media_error(e)),
+        }
+    }
+
+    /// Handles a media frame received from the client.
+    pub fn handle_frame(&self, frame: BinaryFrame) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+
+        let BinaryFrame::UploadChunk {
+            upload_id,
+            offset,
+            data,
+        } = frame
+        else {
+            return ServerMessage::Error(ErrorResponse::new(400, "Unexpected frame"));
+        };
 
-        if let Some(other_peer) = req.peer_id {
-            let other = PeerIdentity::new(other_peer);
-            messages.retain(|m| m.sender_id == other || m.recipient_id == other);
+        match self
+            .store
+            .media
+            .write_chunk(&peer, upload_id, offset, &data)
+        {
+            Ok(UploadProgress::Partial(status)) => ServerMessage::Media(MediaResponse {
+                media: None,
+                upload: Some(status),
+                range: None,
+            }),
+            Ok(UploadProgress::Complete(info)) => ServerMessage::Media(MediaResponse {
+                media: Some(info),
+                upload: None,
+                range: None,
+            }),
+            Err(e) => ServerMessage::Error(Self::media_error(e)),
         }
+    }
 
-        messages.truncate(limit);
-        ServerMessage::PrivateMessage(PrivateMessageResponse {
-            message: None,
-            messages: Some(messages),
+    fn handle_get_media(&self, req: GetMediaRequest) -> ServerMessage {
+        let peer = match self.current_peer() {
+            Some(p) => p,
+            None => return ServerMessage::Error(ErrorResponse::new(401, "Not authenticated")),
+        };
+        let info = match self.store.media.info(&req.hash) {
+            Some(info) if self.store.can_view_media(&req.hash, &peer) => info,
+            _ => return ServerMessage::Error(ErrorResponse::new(404, "Media not found")),
+        };
+
+        let offset = req.offset.unwrap_or(0);
+        if offset > info.size {
+            return ServerMessage::Error(ErrorResponse::new(416, "Range not satisfiable"));
+        }
+        let length = req.length.unwrap_or(info.size).min(info.size - offset);
+
+        ServerMessage::Media(MediaResponse {
+            media: Some(info),
+            upload: None,
+            range: Some(MediaRange { offset, length }),
         })
     }
 
-    fn handle_get_user(&self, req: GetUserRequest) -> ServerMessage {
-        let user = self.store.get_user(&req.peer_id);
-        ServerMessage::User(UserResponse { user })
+    /// The binary frames that follow a `GetMedia` response.
+    pub fn media_frames(&self, response: &MediaResponse) -> Vec<BinaryFrame> {
+        let (Some(info), Some(range)) = (&response.media, response.range) else {
+            return Vec::new();
+        };
+        let data = match self
+            .store
+            .media
+            .read(&info.hash, range.offset, range.length)
+        {
+            Ok(data) => data,
+            Err(_) => return Vec::new(),
+        };
+
+        (0..data.len())
+            .step_by(MEDIA_CHUNK_SIZE)
+            .map(|start| BinaryFrame::MediaChunk {
+                hash: info.hash.clone(),
+                offset: range.offset + start as u64,
+                data: data.slice(start..(start + MEDIA_CHUNK_SIZE).min(data.len())),
+            })
+            .collect()
     }
 
-    fn handle_search_users(&self, req: SearchUsersRequest) -> ServerMessage {
-        let limit = req.limit.unwrap_or(20) as usize;
-        let query = req.query.to_lowercase();
+    fn media_error(e: MediaError) -> ErrorResponse {
+        let code = match e {
+            MediaError::TooLarge => 413,
+            MediaError::UnsupportedType => 415,
+            MediaError::TooManyUploads => 429,
+            MediaError::UnknownUpload(_) | MediaError::NotFound => 404,
+            MediaError::UnexpectedOffset { .. } => 409,
+            MediaError::HashMismatch | MediaError::Malformed => 422,
+        };
+        ErrorResponse::new(code, e.to_string())
+    }
 
-        let users: Vec<User> = self
-            .store
-            .users
-            .read()
-            .values()
-            .filter(|u| {
-                u.username.to_lowercase().contains(&query)
-                    || u.display_name.to_lowercase().contains(&query)
-            })
-            .take(limit)
-            .cloned()
-            .collect();
+    fn envelope_error(e: CryptoError) -> ErrorResponse {
+        match e {
+            CryptoError::InvalidSignature => ErrorResponse::new(403, "Invalid signature"),
+            _ => ErrorResponse::new(400, "Malformed encrypted content"),
+        }
+    }
 
-        ServerMessage::SearchUsers(SearchUsersResponse { users })
+    fn friendship_error(e: FriendshipError) -> ErrorResponse {
+        let code = match e {
+            FriendshipError::OwnRequest => 400,
+            FriendshipError::Blocked => 403,
+            FriendshipError::RequestNotFound | FriendshipError::NotFriends => 404,
+            FriendshipError::AlreadyFriends | FriendshipError::AlreadyRequested => 409,
+        };
+        ErrorResponse::new(code, e.to_string())
+    }
+
+    fn group_key_error(e: GroupKeyError) -> ErrorResponse {
+        let code = match e {
+            GroupKeyError::WrongRecipients => 400,
+            GroupKeyError::NotMember => 403,
+            GroupKeyError::RoomNotFound => 404,
+            GroupKeyError::StaleEpoch => 409,
+        };
+        ErrorResponse::new(code, e.to_string())
+    }
+
+    fn room_error(e: RoomError) -> ErrorResponse {
+        let code = match e {
+            RoomError::NotOwner => 403,
+            RoomError::NotFound => 404,
+        };
+        ErrorResponse::new(code, e.to_string())
+    }
+
+    fn gns_error(e: GnsError) -> ServerMessage {
+        let code = match e {
+            GnsError::LabelTaken(_) => 409,
+            GnsError::NoLocalZone => 500,
+        };
+        ServerMessage::Error(ErrorResponse::new(code, e.to_string()))
+    }
+}
+
+/// Rooms take messages from members only: text in plain rooms, and in
+/// encrypted rooms ciphertext under the current key, and nothing while a
+/// rotation is outstanding so that departed members never see a key that
+/// still works.
+fn check_room_content(
+    room: &ChatRoom,
+    sender: &PeerIdentity,
+    content: &MessageContent,
+) -> Result<(), ErrorResponse> {
+    if !room.is_member(sender) {
+        return Err(ErrorResponse::new(403, "Not a member of this room"));
+    }
+    match (room.encrypted, content) {
+        (false, MessageContent::Plain(_)) => Ok(()),
+        (false, MessageContent::Encrypted(_)) => {
+            Err(ErrorResponse::new(400, "Room is not encrypted"))
+        }
+        (true, MessageContent::Plain(_)) => {
+            Err(ErrorResponse::new(400, "Room requires encrypted content"))
+        }
+        (true, MessageContent::Encrypted(ciphertext)) => {
+            if room.rekey_pending {
+                return Err(ErrorResponse::new(409, "Group key rotation pending"));
+            }
+            if ciphertext.epoch != room.key_epoch {
+                return Err(ErrorResponse::new(409, "Stale key epoch"));
+            }
+            ciphertext
+                .validate()
+                .map_err(|_| ErrorResponse::new(400, "Malformed encrypted content"))
+        }
+    }
+}
+
+/// Whether `name` would be read as a peer id, in any case.
+fn looks_like_peer_id(name: &str) -> bool {
+    name.to_ascii_uppercase().parse::<PeerIdentity>().is_ok()
+}
+
+/// Checks that `actor` holds at least `role` in `room` and ranks above
+/// `target`.
+fn check_outranks(
+    room: &ChatRoom,
+    actor: &PeerIdentity,
+    target: &PeerIdentity,
+    role: RoomRole,
+) -> Result<(), ErrorResponse> {
+    if !room.has_role(actor, role) {
+        return Err(ErrorResponse::new(
+            403,
+            format!("Requires the {} role", role.as_str()),
+        ));
+    }
+    if !room.outranks(actor, target) {
+        return Err(ErrorResponse::new(
+            403,
+            "Cannot act on a member of equal or higher role",
+        ));
     }
+    Ok(())
 }
@@ -1,15 +1,19 @@
 use super::handler::MessageHandler;
+use crate::gnunet::{CadetMessage, EgoEvent, IdentityService};
 use crate::protocol::*;
 use crate::social::SocialStore;
+use bytes::Bytes;
+use futures::channel::mpsc::Receiver;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::broadcast;
 
+#[derive(Clone)]
 pub struct MqttServer {
     store: SocialStore,
     handler: Arc<MessageHandler>,
-    event_tx: broadcast::Sender<ServerMessage>,
+    event_tx: broadcast::Sender<Dispatch>,
     connected_peers: Arc<RwLock<HashMap<String, String>>>,
 }
 
@@ -23,7 +27,7 @@ impl MqttServer {
     pub fn new() -> Self {
         let store = SocialStore::new();
         let handler = Arc::new(MessageHandler::new(store.clone()));
-        let (event_tx, _) = broadcast::channel(1024);
+        let event_tx = handler.event_sender();
 
         Self {
             store,
@@ -35,7 +39,7 @@ impl MqttServer {
 
     pub fn with_store(store: SocialStore) -> Self {
         let handler = Arc::new(MessageHandler::new(store.clone()));
-        let (event_tx, _) = broadcast::channel(1024);
+        let event_tx = handler.event_sender();
 
         Self {
             store,
@@ -45,10 +49,74 @@ impl MqttServer {
         }
     }
 
-    pub fn subscribe_events(&self) -> broadcast::Receiver<ServerMessage> {
+    pub fn with_identity(store: SocialStore, identity: IdentityService) -> Self {
+        let handler = Arc::new(MessageHandler::with_identity(store.clone(), identity));
+        let event_tx = handler.event_sender();
+
+        Self {
+            store,
+            handler,
+            event_tx,
+            connected_peers: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The server as seen by a new client connection, with its own session.
+    pub fn connect(&self) -> Self {
+        Self {
+            handler: Arc::new(self.handler.for_connection()),
+            ..self.clone()
+        }
+    }
+
+    pub fn watch_egos(&self) -> Receiver<EgoEvent> {
+        self.handler.watch_egos()
+    }
+
+    pub fn handle_ego_event(&self, event: EgoEvent) {
+        self.handler.handle_ego_event(event);
+    }
+
+    pub fn listen_social(&self) -> Receiver<CadetMessage> {
+        self.handler.listen_social()
+    }
+
+    pub fn handle_cadet_message(&self, msg: CadetMessage) {
+        self.handler.handle_cadet_message(msg);
+    }
+
+    pub fn listen_fileshare(&self) -> Receiver<CadetMessage> {
+        self.handler.listen_fileshare()
+    }
+
+    pub fn handle_fileshare(&self, msg: CadetMessage) {
+        self.handler.handle_fileshare(msg);
+    }
+
+    /// Retries media transfers that stopped getting replies.
+    pub fn sweep_transfers(&self) {
+        self.handler.sweep_transfers();
+    }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Dispatch> {
         self.event_tx.subscribe()
     }
 
+    /// Whether `dispatch` is for this connection.
+    pub fn accepts(&self, dispatch: &Dispatch) -> bool {
+        self.handler.accepts(&dispatch.audience)
+    }
+
+    /// Records that `dispatch` was sent to this connection.
+    pub fn delivered(&self, dispatch: &Dispatch) {
+        self.handler.delivered(&dispatch.event);
+    }
+
+    /// Sends the receipts for room messages delivered since the last call.
+    pub fn flush_deliveries(&self) {
+        self.handler.flush_deliveries();
+    }
+
     pub fn process_message(&self, payload: &[u8]) -> Option<ServerMessage> {
         match serde_json::from_slice::<ClientMessage>(payload) {
             Ok(msg) => {
@@ -72,9 +140,32 @@ impl MqttServer {
         }
     }
 
+    /// Handles a binary frame: media chunks, or JSON sent as binary.
+    pub fn process_frame(&self, frame: &[u8]) -> Option<ServerMessage> {
+        match BinaryFrame::decode(frame) {
+            Some(frame) => Some(self.handler.handle_frame(frame)),
+            None => self.process_message(frame),
+        }
+    }
+
+    /// Encoded media frames to send after `response`, if it starts a download.
+    pub fn media_frames(&self, response: &ServerMessage) -> Vec<Bytes> {
+        match response {
+            ServerMessage::Media(media) => self
+                .handler
+                .media_frames(media)
+                .iter()
+                .map(BinaryFrame::encode)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn broadcast_event(&self, event: EventMessage) {
-        let msg = ServerMessage::Event(event);
-        let _ = self.event_tx.send(msg);
+        let _ = self.event_tx.send(Dispatch {
+            audience: Audience::Everyone,
+            event,
+        });
     }
 
     pub fn get_connected_peers(&self) -> Vec<String> {
@@ -0,0 +1,84 @@
+use crate::gnunet::{HASH_SIZE, HashCode};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use uuid::Uuid;
+
+const UPLOAD_CHUNK: u8 = 1;
+const MEDIA_CHUNK: u8 = 2;
+
+/// Media payload carried in binary WebSocket frames next to the JSON
+/// messages. Each frame is a tag byte, the upload id or media hash, a
+/// big-endian offset and the chunk itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryFrame {
+    /// Client to server: part of an upload opened with `UploadMedia`.
+    UploadChunk {
+        upload_id: Uuid,
+        offset: u64,
+        data: Bytes,
+    },
+    /// Server to client: part of a blob requested with `GetMedia`.
+    MediaChunk {
+        hash: HashCode,
+        offset: u64,
+        data: Bytes,
+    },
+}
+
+impl BinaryFrame {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        match self {
+            Self::UploadChunk {
+                upload_id,
+                offset,
+                data,
+            } => {
+                buf.reserve(1 + 16 + 8 + data.len());
+                buf.put_u8(UPLOAD_CHUNK);
+                buf.put_slice(upload_id.as_bytes());
+                buf.put_u64(*offset);
+                buf.put_slice(data);
+            }
+            Self::MediaChunk { hash, offset, data } => {
+                buf.reserve(1 + HASH_SIZE + 8 + data.len());
+                buf.put_u8(MEDIA_CHUNK);
+                buf.put_slice(hash.as_bytes());
+                buf.put_u64(*offset);
+                buf.put_slice(data);
+            }
+        }
+        buf.freeze()
+    }
+
+    /// Parses a frame; `None` if `frame` is not a media frame, e.g. JSON
+    /// sent as binary.
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        let mut buf = Bytes::copy_from_slice(frame);
+        if !buf.has_remaining() {
+            return None;
+        }
+        match buf.get_u8() {
+            UPLOAD_CHUNK if buf.remaining() >= 16 + 8 => {
+                let mut id = [0u8; 16];
+                buf.copy_to_slice(&mut id);
+                let offset = buf.get_u64();
+                Some(Self::UploadChunk {
+                    upload_id: Uuid::from_bytes(id),
+                    offset,
+                    data: buf,
+                })
+            }
+            MEDIA_CHUNK if buf.remaining() >= HASH_SIZE + 8 => {
+                let mut hash = [0u8; HASH_SIZE];
+                buf.copy_to_slice(&mut hash);
+                let offset = buf.get_u64();
+                Some(Self::MediaChunk {
+                    hash: HashCode::from_bytes(hash),
+                    offset,
+                    data: buf,
+                })
+            }
+            _ => None,
+        }
+    }
+}
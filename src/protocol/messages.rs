@@ -1,6 +1,9 @@
+use crate::gnunet::{HashCode, SealedEnvelope, SealedMessage};
+use crate::media::{MediaInfo, UploadStatus};
 use crate::social::*;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub const TOPIC_PREFIX: &str = "gnunet/social";
@@ -18,25 +21,83 @@ pub enum ClientMessage {
     CreatePost(CreatePostRequest),
     GetFeed(GetFeedRequest),
     GetPost(GetPostRequest),
+    GetHashtagFeed(GetHashtagFeedRequest),
+    GetThread(GetThreadRequest),
+    UndoRepost(UndoRepostRequest),
+    EditPost(EditPostRequest),
+    DeletePost(DeletePostRequest),
     LikePost(LikePostRequest),
+    React(ReactRequest),
+    Unreact(UnreactRequest),
     CreateRoom(CreateRoomRequest),
     GetRooms(GetRoomsRequest),
     JoinRoom(JoinRoomRequest),
     LeaveRoom(LeaveRoomRequest),
+    InviteToRoom(InviteToRoomRequest),
+    AcceptInvite(AcceptInviteRequest),
+    DeclineInvite(DeclineInviteRequest),
+    GetRoomInvites(GetRoomInvitesRequest),
+    RequestJoinRoom(RequestJoinRoomRequest),
+    ApproveJoinRequest(ApproveJoinRequestRequest),
+    DenyJoinRequest(DenyJoinRequestRequest),
+    GetJoinRequests(GetJoinRequestsRequest),
+    CreateInviteLink(CreateInviteLinkRequest),
+    JoinWithInviteLink(JoinWithInviteLinkRequest),
+    ListPublicRooms(ListPublicRoomsRequest),
+    KickMember(KickMemberRequest),
+    BanMember(BanMemberRequest),
+    UnbanMember(UnbanMemberRequest),
+    PromoteMember(PromoteMemberRequest),
+    UpdateRoom(UpdateRoomRequest),
+    DeleteRoom(DeleteRoomRequest),
+    TransferOwnership(TransferOwnershipRequest),
     SendRoomMessage(SendRoomMessageRequest),
     GetRoomMessages(GetRoomMessagesRequest),
+    EditRoomMessage(EditRoomMessageRequest),
+    DeleteRoomMessage(DeleteRoomMessageRequest),
     RequestFriend(RequestFriendRequest),
     AcceptFriend(AcceptFriendRequest),
+    RejectFriend(RejectFriendRequest),
+    CancelFriendRequest(CancelFriendRequestRequest),
+    Unfriend(UnfriendRequest),
     GetFriends(GetFriendsRequest),
+    GetPendingRequests(GetPendingRequestsRequest),
+    BlockUser(BlockUserRequest),
+    UnblockUser(UnblockUserRequest),
+    MuteUser(MuteUserRequest),
+    UnmuteUser(UnmuteUserRequest),
+    GetBlockedUsers(GetBlockedUsersRequest),
     SendPrivateMessage(SendPrivateMessageRequest),
     GetPrivateMessages(GetPrivateMessagesRequest),
+    EditPrivateMessage(EditPrivateMessageRequest),
+    DeletePrivateMessage(DeletePrivateMessageRequest),
+    GetConversations(GetConversationsRequest),
+    MarkConversationRead(MarkConversationReadRequest),
+    MessageRead(MessageReadRequest),
+    SetReadReceipts(SetReadReceiptsRequest),
     GetUser(GetUserRequest),
     SearchUsers(SearchUsersRequest),
+    SearchPosts(SearchPostsRequest),
+    SearchRooms(SearchRoomsRequest),
+    ResolveName(ResolveNameRequest),
+    SetPetname(SetPetnameRequest),
+    RemovePetname(RemovePetnameRequest),
+    ListContacts(ListContactsRequest),
+    CreateEgo(CreateEgoRequest),
+    UseEgo(UseEgoRequest),
+    ListEgos(ListEgosRequest),
+    RotateGroupKey(RotateGroupKeyRequest),
+    GetGroupKeys(GetGroupKeysRequest),
+    UploadMedia(UploadMediaRequest),
+    GetMedia(GetMediaRequest),
+    GetNotifications(GetNotificationsRequest),
+    MarkNotificationsRead(MarkNotificationsReadRequest),
+    SetNotificationMuted(SetNotificationMutedRequest),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthRequest {
-    pub peer_id: String,
+    pub peer_id: PeerId,
     pub token: Option<String>,
 }
 
@@ -51,20 +112,24 @@ pub struct CreateUserRequest {
 pub struct UpdateUserRequest {
     pub display_name: Option<String>,
     pub bio: Option<String>,
+    /// A blob previously uploaded with `UploadMedia`.
+    pub avatar_hash: Option<HashCode>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreatePostRequest {
     pub content: String,
-    pub media_hashes: Vec<String>,
+    pub media_hashes: Vec<HashCode>,
     pub reply_to: Option<Uuid>,
     pub repost_of: Option<Uuid>,
     pub visibility: PostVisibility,
+    /// Ego to post as; the session's active ego when absent.
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetFeedRequest {
-    pub peer_id: String,
+    pub peer_id: PeerId,
     pub limit: Option<u32>,
     pub before: Option<DateTime<Utc>>,
 }
@@ -74,17 +139,81 @@ pub struct GetPostRequest {
     pub post_id: Uuid,
 }
 
+/// Posts tagged `tag`, with or without the `#`, newest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetHashtagFeedRequest {
+    pub tag: String,
+    pub limit: Option<u32>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// Requests a post with the chain of posts it replies to and the replies
+/// below it, `depth` levels deep. Direct replies are paged oldest first,
+/// continuing after the last one received, given by its `after` time and
+/// `after_id`; deeper levels are cut at `limit` replies each, with
+/// `reply_count` telling what was left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetThreadRequest {
+    pub post_id: Uuid,
+    pub depth: Option<u32>,
+    pub limit: Option<u32>,
+    pub after: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub after_id: Option<Uuid>,
+}
+
+/// Removes the requester's plain repost of `post_id`; quotes are deleted
+/// like any other post.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoRepostRequest {
+    pub post_id: Uuid,
+    pub ego: Option<String>,
+}
+
+/// Replaces the text of a post, and its media when given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditPostRequest {
+    pub post_id: Uuid,
+    pub content: String,
+    pub media_hashes: Option<Vec<HashCode>>,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletePostRequest {
+    pub post_id: Uuid,
+    pub ego: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LikePostRequest {
     pub post_id: Uuid,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactRequest {
+    pub target: ReactionTarget,
+    pub emoji: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnreactRequest {
+    pub target: ReactionTarget,
+    pub emoji: String,
+    pub ego: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateRoomRequest {
     pub name: String,
     pub description: Option<String>,
     pub is_group: bool,
     pub is_public: bool,
+    /// Opts the room into end-to-end encryption with rotating group keys.
+    #[serde(default)]
+    pub encrypted: bool,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,19 +222,153 @@ pub struct GetRoomsRequest;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JoinRoomRequest {
     pub room_id: Uuid,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeaveRoomRequest {
     pub room_id: Uuid,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteToRoomRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcceptInviteRequest {
+    pub invite_id: Uuid,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeclineInviteRequest {
+    pub invite_id: Uuid,
+    pub ego: Option<String>,
+}
+
+/// Invitations waiting for the requester.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetRoomInvitesRequest {
+    pub ego: Option<String>,
+}
+
+/// Asks the room's moderators to be let in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestJoinRoomRequest {
+    pub room_id: Uuid,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApproveJoinRequestRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DenyJoinRequestRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetJoinRequestsRequest {
+    pub room_id: Uuid,
+    pub ego: Option<String>,
+}
+
+/// A link valid for `expires_in` seconds and `max_uses` joins, each
+/// unlimited when omitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateInviteLinkRequest {
+    pub room_id: Uuid,
+    pub expires_in: Option<u64>,
+    pub max_uses: Option<u32>,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinWithInviteLinkRequest {
+    pub token: String,
+    pub ego: Option<String>,
+}
+
+/// Public rooms newest first, older than `before` when paging.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListPublicRoomsRequest {
+    pub limit: Option<u32>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KickMemberRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+/// Removes `peer_id` and keeps them from joining again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanMemberRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnbanMemberRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+/// Sets a member's role, which may also be a demotion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromoteMemberRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub role: RoomRole,
+    pub ego: Option<String>,
+}
+
+/// Changes the given fields; an empty description clears it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateRoomRequest {
+    pub room_id: Uuid,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub is_public: Option<bool>,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteRoomRequest {
+    pub room_id: Uuid,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOwnershipRequest {
+    pub room_id: Uuid,
+    pub peer_id: String,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendRoomMessageRequest {
     pub room_id: Uuid,
-    pub content: String,
-    pub media_hashes: Vec<String>,
+    /// Plain text, or a [`GroupCiphertext`](crate::gnunet::GroupCiphertext)
+    /// under the current key epoch in encrypted rooms.
+    pub content: MessageContent,
+    pub media_hashes: Vec<HashCode>,
     pub reply_to: Option<Uuid>,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -113,45 +376,277 @@ pub struct GetRoomMessagesRequest {
     pub room_id: Uuid,
     pub limit: Option<u32>,
     pub before: Option<DateTime<Utc>>,
+    pub ego: Option<String>,
+}
+
+/// Only the sender may edit; content follows the rules of
+/// `SendRoomMessage`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditRoomMessageRequest {
+    pub message_id: Uuid,
+    pub content: MessageContent,
+    pub ego: Option<String>,
+}
+
+/// The sender and room moderators may delete.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteRoomMessageRequest {
+    pub message_id: Uuid,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestFriendRequest {
     pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+/// Ends any friendship with `peer_id` and keeps each of you from seeing or
+/// reaching the other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockUserRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnblockUserRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+/// Hides `peer_id`'s content from your feeds, searches and notifications
+/// without them knowing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MuteUserRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnmuteUserRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetBlockedUsersRequest {
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AcceptFriendRequest {
     pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+/// Turns down the request `peer_id` sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectFriendRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+/// Withdraws the request sent to `peer_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelFriendRequestRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnfriendRequest {
+    pub peer_id: String,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetFriendsRequest;
 
+/// Pending requests in `direction`, or both ways when omitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetPendingRequestsRequest {
+    pub direction: Option<RequestDirection>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendPrivateMessageRequest {
     pub recipient_id: String,
-    pub content: String,
-    pub media_hashes: Vec<String>,
+    /// Sealed and signed with [`SealedEnvelope::seal`].
+    pub content: SealedEnvelope,
+    pub media_hashes: Vec<HashCode>,
+    pub ego: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetPrivateMessagesRequest {
     pub peer_id: Option<String>,
     pub limit: Option<u32>,
+    /// Only with `peer_id`: messages sent before this, for paging.
+    pub before: Option<DateTime<Utc>>,
 }
 
+/// Conversations most recently active first, older than `before` when
+/// paging.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetUserRequest {
+pub struct GetConversationsRequest {
+    pub limit: Option<u32>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// Marks every message `peer_id` sent the requester as read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkConversationReadRequest {
     pub peer_id: String,
 }
 
+/// Acknowledges that the requester read the target messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReadRequest {
+    pub target: ReceiptTarget,
+    pub ego: Option<String>,
+}
+
+/// Turns sending read receipts on or off; reports the setting when
+/// `enabled` is omitted. Delivery receipts are always sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetReadReceiptsRequest {
+    pub enabled: Option<bool>,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditPrivateMessageRequest {
+    pub message_id: Uuid,
+    pub content: SealedEnvelope,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletePrivateMessageRequest {
+    pub message_id: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetUserRequest {
+    pub peer_id: PeerId,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchUsersRequest {
     pub query: String,
     pub limit: Option<u32>,
 }
 
+/// Public posts matching `query` that the requester may see.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchPostsRequest {
+    pub query: String,
+    pub limit: Option<u32>,
+}
+
+/// Public rooms matching `query` by name or description.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchRoomsRequest {
+    pub query: String,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveNameRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetPetnameRequest {
+    pub petname: String,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemovePetnameRequest {
+    pub petname: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListContactsRequest;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateEgoRequest {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub activate: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UseEgoRequest {
+    pub ego: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListEgosRequest;
+
+/// Distributes the key of the next epoch, sealed to every current member
+/// and keyed by their peer id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotateGroupKeyRequest {
+    pub room_id: Uuid,
+    pub epoch: u64,
+    pub keys: HashMap<PeerId, SealedMessage>,
+    pub ego: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetGroupKeysRequest {
+    pub room_id: Uuid,
+    pub ego: Option<String>,
+}
+
+/// Opens an upload; the content follows in `BinaryFrame::UploadChunk`s.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadMediaRequest {
+    pub size: u64,
+    /// Hash the content must match, if the client computed one.
+    pub hash: Option<HashCode>,
+    /// Keeps GPS and XMP metadata in pictures, which are otherwise removed.
+    #[serde(default)]
+    pub keep_location: bool,
+}
+
+/// Notifications newest first, older than `before` when paging.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetNotificationsRequest {
+    pub limit: Option<u32>,
+    pub before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub unread_only: bool,
+    pub ego: Option<String>,
+}
+
+/// Marks `ids` read, or every notification when `ids` is omitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkNotificationsReadRequest {
+    pub ids: Option<Vec<Uuid>>,
+    pub ego: Option<String>,
+}
+
+/// Stops or resumes notifications of `kind`; muted ones are not stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetNotificationMutedRequest {
+    pub kind: NotificationKind,
+    pub muted: bool,
+    pub ego: Option<String>,
+}
+
+/// Requests a blob, or a range of it, as `BinaryFrame::MediaChunk`s.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetMediaRequest {
+    pub hash: HashCode,
+    pub offset: Option<u64>,
+    pub length: Option<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ServerMessage {
@@ -159,13 +654,29 @@ pub enum ServerMessage {
     User(UserResponse),
     Post(PostResponse),
     Feed(FeedResponse),
+    Thread(ThreadResponse),
+    Reactions(ReactionsResponse),
+    Notifications(NotificationsResponse),
     Room(RoomResponse),
+    RoomInvite(RoomInviteResponse),
+    JoinRequest(JoinRequestResponse),
+    InviteLink(InviteLinkResponse),
     RoomMessage(RoomMessageResponse),
     Friend(FriendResponse),
+    Blocks(BlocksResponse),
     PrivateMessage(PrivateMessageResponse),
+    Conversation(ConversationResponse),
+    Receipt(ReceiptResponse),
+    ReadReceipts(ReadReceiptsResponse),
     Error(ErrorResponse),
     Event(EventMessage),
     SearchUsers(SearchUsersResponse),
+    SearchPosts(SearchPostsResponse),
+    SearchRooms(SearchRoomsResponse),
+    Contact(ContactResponse),
+    Ego(EgoResponse),
+    GroupKey(GroupKeyResponse),
+    Media(MediaResponse),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -182,11 +693,54 @@ pub struct UserResponse {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostResponse {
     pub post: Option<Post>,
+    /// The post reposted or quoted by `post`, for embedding.
+    #[serde(default)]
+    pub originals: Vec<Post>,
+    /// Metadata and thumbnails of the attached media that is stored here.
+    #[serde(default)]
+    pub media: Vec<MediaInfo>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FeedResponse {
     pub posts: Vec<Post>,
+    /// Posts reposted or quoted in `posts`, each once.
+    #[serde(default)]
+    pub originals: Vec<Post>,
+    #[serde(default)]
+    pub media: Vec<MediaInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadResponse {
+    pub post: Post,
+    /// From the root of the thread down to the parent of `post`, stopping
+    /// at the first one hidden from the requester.
+    pub ancestors: Vec<Post>,
+    /// Descendants in depth-first order; `reply_to` gives the tree.
+    pub replies: Vec<Post>,
+    /// More direct replies follow the last one returned.
+    pub has_more: bool,
+    #[serde(default)]
+    pub originals: Vec<Post>,
+    #[serde(default)]
+    pub media: Vec<MediaInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionsResponse {
+    pub target: ReactionTarget,
+    pub reactions: Vec<ReactionCount>,
+}
+
+/// `notifications` is only set in reply to `GetNotifications`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationsResponse {
+    pub notifications: Option<Vec<Notification>>,
+    #[serde(default)]
+    pub has_more: bool,
+    pub unread: usize,
+    pub muted: Vec<NotificationKind>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -195,6 +749,25 @@ pub struct RoomResponse {
     pub rooms: Option<Vec<ChatRoom>>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomInviteResponse {
+    pub invite: Option<RoomInvite>,
+    pub invites: Option<Vec<RoomInvite>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequestResponse {
+    pub request: Option<JoinRequest>,
+    pub requests: Option<Vec<JoinRequest>>,
+}
+
+/// `token` is what gets shared; it is shown only when the link is made.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteLinkResponse {
+    pub link: InviteLink,
+    pub token: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomMessageResponse {
     pub message: Option<ChatMessage>,
@@ -204,7 +777,14 @@ pub struct RoomMessageResponse {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FriendResponse {
     pub friendship: Option<Friendship>,
-    pub friends: Option<Vec<String>>,
+    pub friendships: Option<Vec<Friendship>>,
+}
+
+/// Peers the requester has blocked and muted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocksResponse {
+    pub blocked: Vec<String>,
+    pub muted: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -213,6 +793,25 @@ pub struct PrivateMessageResponse {
     pub messages: Option<Vec<PrivateMessage>>,
 }
 
+/// The messages of the acknowledged target that moved to `state`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptResponse {
+    pub target: ReceiptTarget,
+    pub state: DeliveryState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadReceiptsResponse {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationResponse {
+    pub conversation: Option<ConversationSummary>,
+    pub conversations: Option<Vec<ConversationSummary>>,
+    pub has_more: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub code: u16,
@@ -224,16 +823,215 @@ pub struct SearchUsersResponse {
     pub users: Vec<User>,
 }
 
+/// Matches best first, with reposted originals and media as in a feed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchPostsResponse {
+    pub posts: Vec<Post>,
+    #[serde(default)]
+    pub originals: Vec<Post>,
+    #[serde(default)]
+    pub media: Vec<MediaInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchRoomsResponse {
+    pub rooms: Vec<ChatRoom>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    pub petname: String,
+    pub zone: String,
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactResponse {
+    pub contact: Option<Contact>,
+    pub contacts: Option<Vec<Contact>>,
+}
+
+/// An ego held by the session; never carries key material.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EgoInfo {
+    pub name: Option<String>,
+    pub peer_id: String,
+    pub zone: Option<String>,
+    pub active: bool,
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EgoResponse {
+    pub ego: Option<EgoInfo>,
+    pub egos: Option<Vec<EgoInfo>>,
+}
+
+/// Byte range of a blob that follows as binary frames.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MediaRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaResponse {
+    pub media: Option<MediaInfo>,
+    pub upload: Option<UploadStatus>,
+    pub range: Option<MediaRange>,
+}
+
+/// A room member and the zone key its copy of the group key is sealed to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupKeyMember {
+    pub peer_id: String,
+    pub zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedGroupKey {
+    pub epoch: u64,
+    pub key: SealedMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupKeyResponse {
+    pub room_id: Uuid,
+    pub epoch: u64,
+    pub rekey_pending: bool,
+    pub members: Vec<GroupKeyMember>,
+    pub keys: Vec<SealedGroupKey>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "event", rename_all = "snake_case")]
 pub enum EventMessage {
     NewPost {
         post: Post,
     },
+    /// Someone replied to a post of `parent_author`.
+    NewReply {
+        parent_id: Uuid,
+        parent_author: String,
+        post: Post,
+    },
+    /// `peer_id` was mentioned in `post`.
+    Mentioned {
+        peer_id: String,
+        post: Post,
+    },
+    /// Someone reposted or quoted a post of `original_author`.
+    NewRepost {
+        original_id: Uuid,
+        original_author: String,
+        post: Post,
+    },
+    PostEdited {
+        post: Post,
+    },
+    /// `reactions` are the new counts, without `reacted` set.
+    ReactionAdded {
+        target: ReactionTarget,
+        emoji: String,
+        peer_id: String,
+        reactions: Vec<ReactionCount>,
+    },
+    ReactionRemoved {
+        target: ReactionTarget,
+        emoji: String,
+        peer_id: String,
+        reactions: Vec<ReactionCount>,
+    },
+    PostDeleted {
+        post_id: Uuid,
+        author_id: String,
+    },
+    RoomMessageEdited {
+        room_id: Uuid,
+        message: ChatMessage,
+    },
+    RoomMessageDeleted {
+        room_id: Uuid,
+        message_id: Uuid,
+        deleted_by: String,
+    },
+    PrivateMessageEdited {
+        message: PrivateMessage,
+    },
+    /// `peer_id` got the target messages as far as `state`.
+    MessageReceipt {
+        target: ReceiptTarget,
+        peer_id: String,
+        state: DeliveryState,
+        at: DateTime<Utc>,
+    },
+    /// `peer_id` read the messages `sender_id` sent them.
+    ConversationRead {
+        peer_id: String,
+        sender_id: String,
+        message_ids: Vec<Uuid>,
+        read_at: DateTime<Utc>,
+    },
+    PrivateMessageDeleted {
+        message_id: Uuid,
+        sender_id: String,
+        recipient_id: String,
+    },
     NewRoomMessage {
         room_id: Uuid,
         message: ChatMessage,
     },
+    RoomMemberJoined {
+        room_id: Uuid,
+        peer_id: String,
+    },
+    RoomInvited {
+        invite: RoomInvite,
+    },
+    RoomInviteDeclined {
+        invite: RoomInvite,
+    },
+    RoomJoinRequested {
+        request: JoinRequest,
+    },
+    RoomJoinRequestAnswered {
+        room_id: Uuid,
+        peer_id: String,
+        approved: bool,
+        answered_by: String,
+    },
+    /// `peer_id` was kicked, or banned if `banned`. It left on its own
+    /// when `removed_by` is `peer_id`.
+    RoomMemberRemoved {
+        room_id: Uuid,
+        peer_id: String,
+        removed_by: String,
+        banned: bool,
+    },
+    RoomMemberUnbanned {
+        room_id: Uuid,
+        peer_id: String,
+        unbanned_by: String,
+    },
+    RoomRoleChanged {
+        room_id: Uuid,
+        peer_id: String,
+        role: RoomRole,
+        changed_by: String,
+    },
+    RoomUpdated {
+        room: ChatRoom,
+        updated_by: String,
+    },
+    RoomDeleted {
+        room_id: Uuid,
+        deleted_by: String,
+    },
+    RoomOwnershipTransferred {
+        room_id: Uuid,
+        previous_owner: String,
+        owner: String,
+    },
     NewPrivateMessage {
         message: PrivateMessage,
     },
@@ -244,12 +1042,75 @@ pub enum EventMessage {
     FriendAccepted {
         peer_id: String,
     },
+    /// A request was rejected or cancelled, or a friendship ended;
+    /// `friendship` is the record as it was.
+    FriendshipEnded {
+        friendship: Friendship,
+        ended_by: String,
+    },
+    NewNotification {
+        notification: Notification,
+    },
+    /// `peer_id`'s count of unread notifications changed.
+    UnreadNotifications {
+        peer_id: String,
+        unread: usize,
+    },
     UserOnline {
         peer_id: String,
     },
     UserOffline {
         peer_id: String,
     },
+    /// Membership of an encrypted room changed; a member must distribute the
+    /// key for `epoch` before messages are accepted again.
+    GroupKeyRotationRequired {
+        room_id: Uuid,
+        epoch: u64,
+        members: Vec<GroupKeyMember>,
+    },
+    GroupKeyRotated {
+        room_id: Uuid,
+        epoch: u64,
+        distributed_by: String,
+    },
+}
+
+/// The connections an event is for.
+#[derive(Debug, Clone)]
+pub enum Audience {
+    Everyone,
+    /// Connections with any of these peers in their session.
+    Peers(Vec<PeerId>),
+    /// Connections with a peer in their session that may see the post.
+    PostViewers(Box<Post>),
+}
+
+impl Audience {
+    /// The members of `room`.
+    pub fn members(room: &ChatRoom) -> Self {
+        Self::Peers(room.members.clone())
+    }
+
+    /// Also addresses `peer`, for events about someone outside the room.
+    pub fn and(self, peer: &PeerId) -> Self {
+        match self {
+            Self::Peers(mut peers) => {
+                if !peers.contains(peer) {
+                    peers.push(peer.clone());
+                }
+                Self::Peers(peers)
+            }
+            audience => audience,
+        }
+    }
+}
+
+/// An event raised while handling a request, with whom it is for.
+#[derive(Debug, Clone)]
+pub struct Dispatch {
+    pub audience: Audience,
+    pub event: EventMessage,
 }
 
 impl ErrorResponse {
@@ -1,3 +1,5 @@
+pub mod frames;
 pub mod messages;
 
+pub use frames::*;
 pub use messages::*;
@@ -0,0 +1,44 @@
+use super::{PeerId, PrivateMessage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The direct messages between two peers, as an index into the private
+/// message store.
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    /// Ids of the messages, oldest first.
+    pub message_ids: Vec<Uuid>,
+    /// When the latest message was sent.
+    pub last_activity: DateTime<Utc>,
+}
+
+impl Conversation {
+    pub fn new(last_activity: DateTime<Utc>) -> Self {
+        Self {
+            message_ids: Vec::new(),
+            last_activity,
+        }
+    }
+
+    /// Adds a message, keeping `message_ids` in the order messages were
+    /// sent even when federation delivers them out of order.
+    pub fn insert(&mut self, msg: &PrivateMessage, sent_at: impl Fn(&Uuid) -> DateTime<Utc>) {
+        let at = self
+            .message_ids
+            .partition_point(|id| sent_at(id) <= msg.created_at);
+        self.message_ids.insert(at, msg.id);
+        self.last_activity = self.last_activity.max(msg.created_at);
+    }
+}
+
+/// A conversation as seen by one of its peers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub peer_id: PeerId,
+    /// The latest message that has not been deleted.
+    pub last_message: Option<PrivateMessage>,
+    pub last_activity: DateTime<Utc>,
+    /// Messages from `peer_id` not read yet.
+    pub unread: usize,
+}
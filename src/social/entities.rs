@@ -0,0 +1,125 @@
+use super::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Longest hashtag indexed, in characters.
+pub const MAX_HASHTAG_LEN: usize = 64;
+
+/// Trailing characters that end a sentence rather than a link.
+const URL_TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}', '\'', '"'];
+
+/// A span of post content with a meaning of its own. Offsets count UTF-16
+/// code units, as JavaScript strings do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entity {
+    pub start: usize,
+    pub end: usize,
+    #[serde(flatten)]
+    pub kind: EntityKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EntityKind {
+    /// `tag` is lowercased and without the `#`.
+    Hashtag {
+        tag: String,
+    },
+    /// `name` as written after the `@`; `peer_id` once resolved.
+    Mention {
+        name: String,
+        peer_id: Option<PeerId>,
+    },
+    Link {
+        url: String,
+    },
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    is_word_char(c) || c == '.' || c == '-'
+}
+
+/// Lowercases a hashtag and strips its `#`; `None` if it is not one.
+pub fn normalize_hashtag(tag: &str) -> Option<String> {
+    let tag = tag.strip_prefix('#').unwrap_or(tag);
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_HASHTAG_LEN
+        && tag.chars().all(is_word_char)
+        && !tag.chars().all(|c| c.is_ascii_digit());
+    valid.then(|| tag.to_lowercase())
+}
+
+/// Finds hashtags, mentions and `http(s)` links in `content`. Mentions are
+/// left unresolved. Markers only count at the start of a word, and nothing
+/// inside a link is taken for a hashtag or mention.
+pub fn parse_entities(content: &str) -> Vec<Entity> {
+    let utf16 = |byte: usize| content[..byte].encode_utf16().count();
+    let mut entities = Vec::new();
+    let mut i = 0;
+
+    while let Some(c) = content[i..].chars().next() {
+        let rest = &content[i..];
+        let at_word_start = content[..i]
+            .chars()
+            .next_back()
+            .is_none_or(|p| !is_word_char(p));
+        if !at_word_start {
+            i += c.len_utf8();
+            continue;
+        }
+
+        if rest.starts_with("https://") || rest.starts_with("http://") {
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let url = rest[..len].trim_end_matches(URL_TRAILING);
+            if url.len() > "https://".len() {
+                entities.push(Entity {
+                    start: utf16(i),
+                    end: utf16(i + url.len()),
+                    kind: EntityKind::Link {
+                        url: url.to_string(),
+                    },
+                });
+                i += url.len();
+                continue;
+            }
+        }
+
+        let body = &rest[c.len_utf8()..];
+        let kind = match c {
+            '#' => {
+                let len = body.find(|c| !is_word_char(c)).unwrap_or(body.len());
+                normalize_hashtag(&body[..len]).map(|tag| (len, EntityKind::Hashtag { tag }))
+            }
+            '@' => {
+                let len = body.find(|c| !is_name_char(c)).unwrap_or(body.len());
+                let name = body[..len].trim_end_matches(['.', '-']);
+                (!name.is_empty()).then(|| {
+                    (
+                        name.len(),
+                        EntityKind::Mention {
+                            name: name.to_string(),
+                            peer_id: None,
+                        },
+                    )
+                })
+            }
+            _ => None,
+        };
+        match kind {
+            Some((len, kind)) => {
+                let end = i + c.len_utf8() + len;
+                entities.push(Entity {
+                    start: utf16(i),
+                    end: utf16(end),
+                    kind,
+                });
+                i = end;
+            }
+            None => i += c.len_utf8(),
+        }
+    }
+    entities
+}
@@ -0,0 +1,92 @@
+use super::PeerId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An invitation for `invitee_id` to join a room, until accepted or
+/// declined.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomInvite {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub inviter_id: PeerId,
+    pub invitee_id: PeerId,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RoomInvite {
+    pub fn new(room_id: Uuid, inviter_id: PeerId, invitee_id: PeerId) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            room_id,
+            inviter_id,
+            invitee_id,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// A peer asking to be let into a private room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub room_id: Uuid,
+    pub peer_id: PeerId,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A link anyone holding its token may join a room through, while it has
+/// uses left and has not expired. Tokens are signed with the ego key of
+/// the admin who made the link, and the link is revoked once that admin
+/// loses the role.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteLink {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub created_by: PeerId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl InviteLink {
+    pub fn new(room_id: Uuid, created_by: PeerId) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            room_id,
+            created_by,
+            created_at: Utc::now(),
+            expires_at: None,
+            max_uses: None,
+            uses: 0,
+        }
+    }
+
+    /// The fields a token signs; `uses` is left out as it changes.
+    pub fn signed_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(self.id.as_bytes());
+        data.extend_from_slice(self.room_id.as_bytes());
+        data.extend_from_slice(self.created_by.as_bytes());
+        match self.expires_at {
+            Some(t) => {
+                data.push(1);
+                data.extend_from_slice(&t.timestamp_micros().to_be_bytes());
+            }
+            None => data.push(0),
+        }
+        match self.max_uses {
+            Some(n) => {
+                data.push(1);
+                data.extend_from_slice(&n.to_be_bytes());
+            }
+            None => data.push(0),
+        }
+        data
+    }
+
+    /// Whether the link may still be used at `now`.
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|t| now < t) && self.max_uses.is_none_or(|n| self.uses < n)
+    }
+}
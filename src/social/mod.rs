@@ -1,11 +1,26 @@
-use crate::gnunet::PeerIdentity;
+use crate::gnunet::{GroupCiphertext, HashCode, PeerIdentity, SealedEnvelope, SealedMessage};
+use crate::media::MediaStore;
 use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use uuid::Uuid;
 
+pub mod conversations;
+pub mod entities;
+pub mod invites;
+pub mod notifications;
+pub mod receipts;
+pub mod search;
+
+pub use conversations::*;
+pub use entities::*;
+pub use invites::*;
+pub use notifications::*;
+pub use receipts::*;
+pub use search::*;
+
 pub type PeerId = PeerIdentity;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,7 +29,7 @@ pub struct User {
     pub username: String,
     pub display_name: String,
     pub bio: Option<String>,
-    pub avatar_hash: Option<String>,
+    pub avatar_hash: Option<HashCode>,
     pub gns_zone: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -41,13 +56,42 @@ pub struct Post {
     pub id: Uuid,
     pub author_id: PeerId,
     pub content: String,
-    pub media_hashes: Vec<String>,
+    /// Hashtags, mentions and links found in `content`.
+    #[serde(default)]
+    pub entities: Vec<Entity>,
+    pub media_hashes: Vec<HashCode>,
     pub reply_to: Option<Uuid>,
     pub repost_of: Option<Uuid>,
     pub visibility: PostVisibility,
     pub created_at: DateTime<Utc>,
-    pub likes: Vec<String>,
+    /// Reactions by emoji; `reacted` is only set in responses, for the
+    /// requester.
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+    /// Plain reposts of this post stored on this server.
     pub reposts: u64,
+    /// Posts quoting this one stored on this server.
+    #[serde(default)]
+    pub quotes: u64,
+    /// Direct replies stored on this server.
+    #[serde(default)]
+    pub reply_count: u64,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Earlier versions, oldest first.
+    #[serde(default)]
+    pub history: Vec<Revision<String>>,
+    /// Set once deleted; the post stays as a tombstone so threads keep
+    /// their shape.
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A replaced version of an edited post or message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision<C> {
+    pub content: C,
+    pub replaced_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -64,14 +108,74 @@ impl Post {
             id: Uuid::new_v4(),
             author_id,
             content,
+            entities: Vec::new(),
             media_hashes: Vec::new(),
             reply_to: None,
             repost_of: None,
             visibility: PostVisibility::Public,
             created_at: Utc::now(),
-            likes: Vec::new(),
+            reactions: Vec::new(),
             reposts: 0,
+            quotes: 0,
+            reply_count: 0,
+            edited_at: None,
+            history: Vec::new(),
+            deleted_at: None,
+        }
+    }
+
+    /// Replaces the text, keeping the previous one in the history.
+    pub fn edit(&mut self, content: String) {
+        let now = Utc::now();
+        let previous = std::mem::replace(&mut self.content, content);
+        self.history.push(Revision {
+            content: previous,
+            replaced_at: now,
+        });
+        self.edited_at = Some(now);
+    }
+
+    pub fn hashtags(&self) -> impl Iterator<Item = &str> {
+        self.entities.iter().filter_map(|e| match &e.kind {
+            EntityKind::Hashtag { tag } => Some(tag.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Resolved mentions, each peer once.
+    pub fn mentioned_peers(&self) -> Vec<PeerId> {
+        let mut peers = Vec::new();
+        for entity in &self.entities {
+            if let EntityKind::Mention {
+                peer_id: Some(peer),
+                ..
+            } = &entity.kind
+                && !peers.contains(peer)
+            {
+                peers.push(peer.clone());
+            }
         }
+        peers
+    }
+
+    /// Clears everything but the id and thread position.
+    pub fn tombstone(&mut self) {
+        self.content.clear();
+        self.entities.clear();
+        self.media_hashes.clear();
+        self.repost_of = None;
+        self.reactions.clear();
+        self.history.clear();
+        self.deleted_at = Some(Utc::now());
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// A repost without comment, as opposed to a quote.
+    pub fn is_repost(&self) -> bool {
+        self.repost_of.is_some() && self.content.is_empty() && self.media_hashes.is_empty()
     }
 }
 
@@ -81,10 +185,24 @@ pub struct ChatRoom {
     pub name: String,
     pub description: Option<String>,
     pub owner_id: PeerId,
+    /// Includes the owner.
     pub admins: Vec<PeerId>,
+    #[serde(default)]
+    pub moderators: Vec<PeerId>,
     pub members: Vec<PeerId>,
+    /// Peers kept from joining again.
+    #[serde(default)]
+    pub banned: Vec<PeerId>,
     pub is_group: bool,
     pub is_public: bool,
+    /// Messages are encrypted under a group key that members rotate whenever
+    /// membership changes.
+    pub encrypted: bool,
+    /// Epoch of the group key messages must currently be encrypted under.
+    pub key_epoch: u64,
+    /// Set by a membership change; no messages are accepted until a member
+    /// distributes the key for the next epoch.
+    pub rekey_pending: bool,
     pub created_at: DateTime<Utc>,
 }
 
@@ -96,12 +214,130 @@ impl ChatRoom {
             description: None,
             owner_id: owner_id.clone(),
             admins: vec![owner_id.clone()],
+            moderators: Vec::new(),
             members: vec![owner_id],
+            banned: Vec::new(),
             is_group,
             is_public: false,
+            encrypted: false,
+            key_epoch: 0,
+            rekey_pending: false,
             created_at: Utc::now(),
         }
     }
+
+    pub fn new_encrypted(name: String, owner_id: PeerId, is_group: bool) -> Self {
+        Self {
+            encrypted: true,
+            rekey_pending: true,
+            ..Self::new(name, owner_id, is_group)
+        }
+    }
+
+    pub fn is_member(&self, peer: &PeerId) -> bool {
+        self.members.contains(peer)
+    }
+
+    /// `peer`'s role, or `None` if not a member.
+    pub fn role_of(&self, peer: &PeerId) -> Option<RoomRole> {
+        if !self.is_member(peer) {
+            None
+        } else if self.owner_id == *peer {
+            Some(RoomRole::Owner)
+        } else if self.admins.contains(peer) {
+            Some(RoomRole::Admin)
+        } else if self.moderators.contains(peer) {
+            Some(RoomRole::Moderator)
+        } else {
+            Some(RoomRole::Member)
+        }
+    }
+
+    /// Whether `peer` is a member with at least `role`.
+    pub fn has_role(&self, peer: &PeerId, role: RoomRole) -> bool {
+        self.role_of(peer).is_some_and(|r| r >= role)
+    }
+
+    /// Whether `actor` ranks above `target`; every member outranks
+    /// non-members.
+    pub fn outranks(&self, actor: &PeerId, target: &PeerId) -> bool {
+        self.role_of(actor) > self.role_of(target)
+    }
+
+    /// Gives a member a role below owner.
+    pub fn set_role(&mut self, peer: &PeerId, role: RoomRole) {
+        self.admins.retain(|p| p != peer);
+        self.moderators.retain(|p| p != peer);
+        match role {
+            RoomRole::Admin => self.admins.push(peer.clone()),
+            RoomRole::Moderator => self.moderators.push(peer.clone()),
+            RoomRole::Member | RoomRole::Owner => {}
+        }
+    }
+
+    /// Removes `peer` along with any role, returning whether it was a
+    /// member.
+    pub fn remove_member(&mut self, peer: &PeerId) -> bool {
+        if !self.is_member(peer) {
+            return false;
+        }
+        self.members.retain(|p| p != peer);
+        self.admins.retain(|p| p != peer);
+        self.moderators.retain(|p| p != peer);
+        self.require_rekey();
+        true
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.banned.contains(peer)
+    }
+
+    /// Marks the current group key as stale after a membership change.
+    pub fn require_rekey(&mut self) {
+        if self.encrypted {
+            self.rekey_pending = true;
+        }
+    }
+}
+
+/// Roles in a room, lowest first. Each role may do what the ones below it
+/// can:
+/// - members post,
+/// - moderators delete messages and kick or ban lower roles,
+/// - admins also edit the room and give roles below their own,
+/// - the owner also deletes the room and hands it over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomRole {
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+impl RoomRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomRole::Member => "member",
+            RoomRole::Moderator => "moderator",
+            RoomRole::Admin => "admin",
+            RoomRole::Owner => "owner",
+        }
+    }
+}
+
+/// Body of a room message: plain text, or ciphertext in encrypted rooms.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Plain(String),
+    Encrypted(GroupCiphertext),
+}
+
+impl From<String> for MessageContent {
+    fn from(content: String) -> Self {
+        Self::Plain(content)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,23 +345,87 @@ pub struct ChatMessage {
     pub id: Uuid,
     pub room_id: Uuid,
     pub sender_id: PeerId,
-    pub content: String,
-    pub media_hashes: Vec<String>,
+    pub content: MessageContent,
+    pub media_hashes: Vec<HashCode>,
     pub reply_to: Option<Uuid>,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub history: Vec<Revision<MessageContent>>,
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Receipts from the other members, by member.
+    #[serde(default)]
+    pub receipts: HashMap<PeerId, Receipt>,
 }
 
 impl ChatMessage {
-    pub fn new(room_id: Uuid, sender_id: PeerId, content: String) -> Self {
+    pub fn new(room_id: Uuid, sender_id: PeerId, content: impl Into<MessageContent>) -> Self {
         Self {
             id: Uuid::new_v4(),
             room_id,
             sender_id,
-            content,
+            content: content.into(),
             media_hashes: Vec::new(),
             reply_to: None,
             created_at: Utc::now(),
+            reactions: Vec::new(),
+            edited_at: None,
+            history: Vec::new(),
+            deleted_at: None,
+            receipts: HashMap::new(),
+        }
+    }
+
+    /// Records that `peer` got the message as far as `state`, returning
+    /// whether that is news.
+    pub fn advance(&mut self, peer: &PeerId, state: DeliveryState, at: DateTime<Utc>) -> bool {
+        if *peer == self.sender_id || self.state_for(peer) >= state {
+            return false;
         }
+        self.receipts.insert(peer.clone(), Receipt { state, at });
+        true
+    }
+
+    pub fn state_for(&self, peer: &PeerId) -> DeliveryState {
+        self.receipts
+            .get(peer)
+            .map_or(DeliveryState::Sent, |r| r.state)
+    }
+
+    /// The state every one of `members` other than the sender has reached.
+    pub fn state_among(&self, members: &[PeerId]) -> DeliveryState {
+        members
+            .iter()
+            .filter(|m| **m != self.sender_id)
+            .map(|m| self.state_for(m))
+            .min()
+            .unwrap_or_default()
+    }
+
+    pub fn edit(&mut self, content: MessageContent) {
+        let now = Utc::now();
+        let previous = std::mem::replace(&mut self.content, content);
+        self.history.push(Revision {
+            content: previous,
+            replaced_at: now,
+        });
+        self.edited_at = Some(now);
+    }
+
+    pub fn tombstone(&mut self) {
+        self.content = MessageContent::Plain(String::new());
+        self.media_hashes.clear();
+        self.reactions.clear();
+        self.history.clear();
+        self.deleted_at = Some(Utc::now());
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
     }
 }
 
@@ -161,21 +461,95 @@ impl Friendship {
         self.status = FriendshipStatus::Accepted;
         self.updated_at = Utc::now();
     }
+
+    /// The peer on the other side from `user_id`.
+    pub fn other(&self, user_id: &PeerId) -> &PeerId {
+        if self.requester_id == *user_id {
+            &self.addressee_id
+        } else {
+            &self.requester_id
+        }
+    }
+
+    pub fn involves(&self, user_id: &PeerId) -> bool {
+        self.requester_id == *user_id || self.addressee_id == *user_id
+    }
+}
+
+/// Which pending requests of a user to list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestDirection {
+    /// Sent to the user, awaiting their answer.
+    Incoming,
+    /// Sent by the user.
+    Outgoing,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FriendshipError {
+    #[error("cannot befriend yourself")]
+    OwnRequest,
+    #[error("already friends")]
+    AlreadyFriends,
+    #[error("friend request already sent")]
+    AlreadyRequested,
+    #[error("blocked")]
+    Blocked,
+    #[error("friend request not found")]
+    RequestNotFound,
+    #[error("not friends")]
+    NotFriends,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GroupKeyError {
+    #[error("room not found")]
+    RoomNotFound,
+    #[error("not a member of this room")]
+    NotMember,
+    #[error("stale key epoch")]
+    StaleEpoch,
+    #[error("group key must be sealed to exactly the current members")]
+    WrongRecipients,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoomError {
+    #[error("room not found")]
+    NotFound,
+    #[error("only the owner can delete a room")]
+    NotOwner,
 }
 
+/// A direct message. The sender's client seals the content to both peers and
+/// signs it; servers only check the signature and route the ciphertext.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PrivateMessage {
     pub id: Uuid,
     pub sender_id: PeerId,
     pub recipient_id: PeerId,
-    pub content: String,
-    pub media_hashes: Vec<String>,
+    pub content: SealedEnvelope,
+    pub media_hashes: Vec<HashCode>,
     pub created_at: DateTime<Utc>,
+    /// What the sender knows of the message's progress.
+    #[serde(default)]
+    pub state: DeliveryState,
+    #[serde(default)]
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// When the recipient read it. The sender only sees this once a read
+    /// receipt arrives; see [`PrivateMessage::seen_by`].
     pub read_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub history: Vec<Revision<SealedEnvelope>>,
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl PrivateMessage {
-    pub fn new(sender_id: PeerId, recipient_id: PeerId, content: String) -> Self {
+    pub fn new(sender_id: PeerId, recipient_id: PeerId, content: SealedEnvelope) -> Self {
         Self {
             id: Uuid::new_v4(),
             sender_id,
@@ -183,22 +557,271 @@ impl PrivateMessage {
             content,
             media_hashes: Vec::new(),
             created_at: Utc::now(),
+            state: DeliveryState::Sent,
+            delivered_at: None,
             read_at: None,
+            edited_at: None,
+            history: Vec::new(),
+            deleted_at: None,
+        }
+    }
+
+    /// Moves the message on to `state`, returning whether it changed.
+    pub fn advance(&mut self, state: DeliveryState, at: DateTime<Utc>) -> bool {
+        if state <= self.state {
+            return false;
+        }
+        self.delivered_at.get_or_insert(at);
+        if state == DeliveryState::Read {
+            self.read_at.get_or_insert(at);
+        }
+        self.state = state;
+        true
+    }
+
+    /// The message as `viewer` may see it: a recipient who does not send
+    /// read receipts keeps when they read it to themselves.
+    pub fn seen_by(mut self, viewer: &PeerId) -> Self {
+        if self.sender_id == *viewer
+            && self.recipient_id != *viewer
+            && self.state < DeliveryState::Read
+        {
+            self.read_at = None;
+        }
+        self
+    }
+
+    pub fn edit(&mut self, content: SealedEnvelope) {
+        let now = Utc::now();
+        let previous = std::mem::replace(&mut self.content, content);
+        self.history.push(Revision {
+            content: previous,
+            replaced_at: now,
+        });
+        self.edited_at = Some(now);
+    }
+
+    pub fn tombstone(&mut self) {
+        self.content = SealedEnvelope::default();
+        self.media_hashes.clear();
+        self.history.clear();
+        self.deleted_at = Some(Utc::now());
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+/// Longest reaction accepted, in bytes; enough for emoji sequences.
+pub const MAX_REACTION_LEN: usize = 32;
+/// Most different reactions one peer may leave on a post or message.
+pub const MAX_REACTIONS_PER_PEER: usize = 8;
+/// The reaction `LikePost` toggles.
+pub const LIKE_REACTION: &str = "\u{2764}\u{fe0f}";
+
+/// What a reaction is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReactionTarget {
+    Post { post_id: Uuid },
+    Message { message_id: Uuid },
+}
+
+impl ReactionTarget {
+    pub fn id(&self) -> Uuid {
+        match self {
+            Self::Post { post_id } => *post_id,
+            Self::Message { message_id } => *message_id,
         }
     }
 }
 
-pub type UserStore = Arc<RwLock<HashMap<String, User>>>;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+    /// Whether the requester is among the reactors.
+    #[serde(default)]
+    pub reacted: bool,
+}
+
+/// Accepts an emoji sequence, or a short code such as `:party_parrot:` of
+/// lowercase ASCII letters, digits, `_`, `+` and `-`.
+pub fn is_valid_reaction(emoji: &str) -> bool {
+    if emoji.is_empty() || emoji.len() > MAX_REACTION_LEN {
+        return false;
+    }
+    if let Some(code) = emoji.strip_prefix(':').and_then(|e| e.strip_suffix(':')) {
+        return !code.is_empty()
+            && code
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_+-".contains(&b));
+    }
+    // Keycaps are the only sequences to start with plain ASCII, e.g. "1\u{fe0f}\u{20e3}".
+    let keycap = emoji.ends_with('\u{20e3}');
+    emoji.chars().any(|c| is_emoji(c) || c == '\u{20e3}')
+        && emoji.chars().all(|c| {
+            is_emoji(c) || is_emoji_modifier(c) || (keycap && matches!(c, '0'..='9' | '#' | '*'))
+        })
+}
+
+/// Pictographs and symbols that render as emoji.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1f000}'..='\u{1faff}'
+            | '\u{2190}'..='\u{21ff}'
+            | '\u{2300}'..='\u{23ff}'
+            | '\u{25a0}'..='\u{27bf}'
+            | '\u{2b00}'..='\u{2bff}'
+            | '\u{a9}'
+            | '\u{ae}'
+            | '\u{203c}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{24c2}'
+            | '\u{2934}'
+            | '\u{2935}'
+            | '\u{3030}'
+            | '\u{303d}'
+            | '\u{3297}'
+            | '\u{3299}'
+    )
+}
+
+/// Characters that only join or adjust the emoji around them.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200d}' | '\u{fe0e}' | '\u{fe0f}' | '\u{20e3}' | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// Reactors of one post or message, by emoji.
+pub type Reactions = HashMap<String, HashSet<PeerId>>;
+
+fn reaction_counts(reactions: &Reactions) -> Vec<ReactionCount> {
+    let mut counts: Vec<ReactionCount> = reactions
+        .iter()
+        .map(|(emoji, reactors)| ReactionCount {
+            emoji: emoji.clone(),
+            count: reactors.len() as u64,
+            reacted: false,
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.emoji.cmp(&b.emoji)));
+    counts
+}
+
+/// The group key of one epoch, sealed separately to each member at the time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupKeyEpoch {
+    pub room_id: Uuid,
+    pub epoch: u64,
+    pub distributed_by: PeerId,
+    pub keys: HashMap<PeerId, SealedMessage>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub type UserStore = Arc<RwLock<HashMap<PeerId, User>>>;
 pub type PostStore = Arc<RwLock<HashMap<Uuid, Post>>>;
 pub type RoomStore = Arc<RwLock<HashMap<Uuid, ChatRoom>>>;
 pub type MessageStore = Arc<RwLock<HashMap<Uuid, ChatMessage>>>;
-pub type FriendshipStore = Arc<RwLock<HashMap<String, Friendship>>>;
+pub type FriendshipStore = Arc<RwLock<HashMap<(PeerId, PeerId), Friendship>>>;
 pub type PrivateMessageStore = Arc<RwLock<HashMap<Uuid, PrivateMessage>>>;
+/// Direct messages by the pair of peers exchanging them. Lock
+/// `private_messages` first when holding both.
+pub type ConversationIndex = Arc<RwLock<HashMap<(PeerId, PeerId), Conversation>>>;
+pub type PersonaStore = Arc<RwLock<HashMap<PeerId, Vec<PeerId>>>>;
+pub type GroupKeyStore = Arc<RwLock<HashMap<Uuid, Vec<GroupKeyEpoch>>>>;
+pub type ReactionStore = Arc<RwLock<HashMap<Uuid, Reactions>>>;
+/// Posts by lowercased hashtag.
+pub type HashtagIndex = Arc<RwLock<HashMap<String, HashSet<Uuid>>>>;
+pub type RoomInviteStore = Arc<RwLock<HashMap<Uuid, RoomInvite>>>;
+/// Pending join requests by room, oldest first.
+pub type JoinRequestStore = Arc<RwLock<HashMap<Uuid, Vec<JoinRequest>>>>;
+pub type InviteLinkStore = Arc<RwLock<HashMap<Uuid, InviteLink>>>;
+/// Peers each user has blocked or muted.
+pub type PeerSetStore = Arc<RwLock<HashMap<PeerId, HashSet<PeerId>>>>;
+/// Each user's notifications, oldest first.
+pub type NotificationStore = Arc<RwLock<HashMap<PeerId, Vec<Notification>>>>;
+pub type NotificationMuteStore = Arc<RwLock<HashMap<PeerId, HashSet<NotificationKind>>>>;
+pub type SearchStore = Arc<RwLock<SearchIndex>>;
+/// Users who do not send read receipts.
+pub type ReceiptOptOutStore = Arc<RwLock<HashSet<PeerId>>>;
+/// Ids of the direct replies to each post, in arrival order.
+pub type ReplyIndex = Arc<RwLock<HashMap<Uuid, Vec<Uuid>>>>;
+/// Id of each plain repost, by reposter and the post reposted.
+pub type RepostIndex = Arc<RwLock<HashMap<(PeerId, Uuid), Uuid>>>;
+
+fn remove_peer(store: &PeerSetStore, user_id: &PeerId, peer: &PeerId) -> bool {
+    let mut sets = store.write();
+    let Some(peers) = sets.get_mut(user_id) else {
+        return false;
+    };
+    let removed = peers.remove(peer);
+    if peers.is_empty() {
+        sets.remove(user_id);
+    }
+    removed
+}
+
+fn list_peers(store: &PeerSetStore, user_id: &PeerId) -> Vec<PeerId> {
+    let mut peers: Vec<PeerId> = store
+        .read()
+        .get(user_id)
+        .map(|peers| peers.iter().cloned().collect())
+        .unwrap_or_default();
+    peers.sort();
+    peers
+}
+
+fn is_unread_by(msg: &PrivateMessage, user_id: &PeerId, peer: &PeerId) -> bool {
+    msg.recipient_id == *user_id
+        && msg.sender_id == *peer
+        && msg.read_at.is_none()
+        && !msg.is_deleted()
+}
+
+fn summarize(
+    conversation: &Conversation,
+    user_id: &PeerId,
+    peer: &PeerId,
+    messages: &HashMap<Uuid, PrivateMessage>,
+) -> ConversationSummary {
+    let sent: Vec<&PrivateMessage> = conversation
+        .message_ids
+        .iter()
+        .filter_map(|id| messages.get(id))
+        .collect();
+    ConversationSummary {
+        peer_id: peer.clone(),
+        last_message: sent
+            .iter()
+            .rev()
+            .find(|m| !m.is_deleted())
+            .map(|m| (*m).clone().seen_by(user_id)),
+        last_activity: conversation.last_activity,
+        unread: sent
+            .iter()
+            .filter(|m| is_unread_by(m, user_id, peer))
+            .count(),
+    }
+}
+
+fn friendship_key(a: &PeerId, b: &PeerId) -> (PeerId, PeerId) {
+    if a <= b {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    }
+}
 
-fn friendship_key(a: &PeerId, b: &PeerId) -> String {
-    let mut ids = vec![a.as_str(), b.as_str()];
-    ids.sort();
-    ids.join(":")
+/// Conversations are keyed by the unordered pair, like friendships.
+fn conversation_key(a: &PeerId, b: &PeerId) -> (PeerId, PeerId) {
+    friendship_key(a, b)
 }
 
 #[derive(Debug, Clone)]
@@ -209,6 +832,23 @@ pub struct SocialStore {
     pub messages: MessageStore,
     pub friendships: FriendshipStore,
     pub private_messages: PrivateMessageStore,
+    pub conversations: ConversationIndex,
+    pub personas: PersonaStore,
+    pub group_keys: GroupKeyStore,
+    pub replies: ReplyIndex,
+    pub reposts: RepostIndex,
+    pub reactions: ReactionStore,
+    pub hashtags: HashtagIndex,
+    pub search: SearchStore,
+    pub room_invites: RoomInviteStore,
+    pub join_requests: JoinRequestStore,
+    pub invite_links: InviteLinkStore,
+    pub blocks: PeerSetStore,
+    pub mutes: PeerSetStore,
+    pub notifications: NotificationStore,
+    pub muted_notifications: NotificationMuteStore,
+    pub read_receipts_disabled: ReceiptOptOutStore,
+    pub media: MediaStore,
 }
 
 impl Default for SocialStore {
@@ -226,27 +866,283 @@ impl SocialStore {
             messages: Arc::new(RwLock::new(HashMap::new())),
             friendships: Arc::new(RwLock::new(HashMap::new())),
             private_messages: Arc::new(RwLock::new(HashMap::new())),
+            conversations: Arc::new(RwLock::new(HashMap::new())),
+            personas: Arc::new(RwLock::new(HashMap::new())),
+            group_keys: Arc::new(RwLock::new(HashMap::new())),
+            replies: Arc::new(RwLock::new(HashMap::new())),
+            reposts: Arc::new(RwLock::new(HashMap::new())),
+            reactions: Arc::new(RwLock::new(HashMap::new())),
+            hashtags: Arc::new(RwLock::new(HashMap::new())),
+            search: Arc::new(RwLock::new(SearchIndex::new())),
+            room_invites: Arc::new(RwLock::new(HashMap::new())),
+            join_requests: Arc::new(RwLock::new(HashMap::new())),
+            invite_links: Arc::new(RwLock::new(HashMap::new())),
+            blocks: Arc::new(RwLock::new(HashMap::new())),
+            mutes: Arc::new(RwLock::new(HashMap::new())),
+            notifications: Arc::new(RwLock::new(HashMap::new())),
+            muted_notifications: Arc::new(RwLock::new(HashMap::new())),
+            read_receipts_disabled: Arc::new(RwLock::new(HashSet::new())),
+            media: MediaStore::new(),
         }
     }
 
     pub fn add_user(&self, user: User) {
-        self.users
+        self.index_user(&user);
+        self.users.write().insert(user.id.clone(), user);
+    }
+
+    /// Makes `user` findable by username, display name and bio.
+    pub fn index_user(&self, user: &User) {
+        let bio = user.bio.as_deref().unwrap_or_default();
+        let fields = [
+            (user.username.as_str(), 3),
+            (user.display_name.as_str(), 2),
+            (bio, 1),
+        ];
+        self.search
             .write()
-            .insert(user.id.as_str().to_string(), user);
+            .insert(Document::User(user.id.clone()), &fields, user.created_at);
+    }
+
+    /// Indexes `post` for search if it is public and not deleted, and
+    /// unindexes it otherwise.
+    pub fn index_post(&self, post: &Post) {
+        let doc = Document::Post(post.id);
+        let mut search = self.search.write();
+        match post.visibility == PostVisibility::Public && !post.is_deleted() {
+            true => search.insert(doc, &[(post.content.as_str(), 1)], post.created_at),
+            false => search.remove(&doc),
+        }
+    }
+
+    /// Indexes `room` for search if it is public, and unindexes it
+    /// otherwise.
+    pub fn index_room(&self, room: &ChatRoom) {
+        let doc = Document::Room(room.id);
+        let mut search = self.search.write();
+        match room.is_public {
+            true => {
+                let description = room.description.as_deref().unwrap_or_default();
+                search.insert(
+                    doc,
+                    &[(room.name.as_str(), 2), (description, 1)],
+                    room.created_at,
+                );
+            }
+            false => search.remove(&doc),
+        }
     }
 
-    pub fn get_user(&self, id: &str) -> Option<User> {
+    pub fn get_user(&self, id: &PeerId) -> Option<User> {
         self.users.read().get(id).cloned()
     }
 
-    pub fn add_post(&self, post: Post) {
-        self.posts.write().insert(post.id, post);
+    /// Stores `post`, indexes it under its parent and counts it on the post
+    /// it reposts. Replies may arrive before their parent, so reply counts
+    /// come from the index. Returns false, storing nothing, if `post` is a
+    /// second plain repost of the same post by its author.
+    pub fn add_post(&self, mut post: Post) -> bool {
+        let mut posts = self.posts.write();
+        let mut replies = self.replies.write();
+        if !posts.contains_key(&post.id) {
+            if let Some(original_id) = post.repost_of
+                && post.is_repost()
+            {
+                let mut reposts = self.reposts.write();
+                let key = (post.author_id.clone(), original_id);
+                if reposts.contains_key(&key) {
+                    return false;
+                }
+                reposts.insert(key, post.id);
+            }
+            if let Some(parent_id) = post.reply_to {
+                let siblings = replies.entry(parent_id).or_default();
+                siblings.push(post.id);
+                if let Some(parent) = posts.get_mut(&parent_id) {
+                    parent.reply_count = siblings.len() as u64;
+                }
+            }
+            if let Some(original) = post.repost_of.and_then(|id| posts.get_mut(&id)) {
+                match post.is_repost() {
+                    true => original.reposts += 1,
+                    false => original.quotes += 1,
+                }
+            }
+        }
+        post.reply_count = replies.get(&post.id).map_or(0, |r| r.len() as u64);
+        self.index_hashtags(&post);
+        self.index_post(&post);
+        posts.insert(post.id, post);
+        true
+    }
+
+    /// `reposter`'s plain repost of `original_id`, if any.
+    pub fn find_repost(&self, reposter: &PeerId, original_id: Uuid) -> Option<Uuid> {
+        self.reposts
+            .read()
+            .get(&(reposter.clone(), original_id))
+            .copied()
+    }
+
+    pub fn index_hashtags(&self, post: &Post) {
+        let mut hashtags = self.hashtags.write();
+        for tag in post.hashtags() {
+            hashtags.entry(tag.to_string()).or_default().insert(post.id);
+        }
+    }
+
+    pub fn unindex_hashtags(&self, post: &Post) {
+        let mut hashtags = self.hashtags.write();
+        for tag in post.hashtags() {
+            if let Some(ids) = hashtags.get_mut(tag) {
+                ids.remove(&post.id);
+                if ids.is_empty() {
+                    hashtags.remove(tag);
+                }
+            }
+        }
+    }
+
+    /// Posts tagged with the normalized `tag`, newest first.
+    pub fn get_posts_by_hashtag(&self, tag: &str) -> Vec<Post> {
+        let ids = self.hashtags.read().get(tag).cloned().unwrap_or_default();
+        let posts = self.posts.read();
+        let mut tagged: Vec<Post> = ids.iter().filter_map(|id| posts.get(id).cloned()).collect();
+        tagged.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+        tagged
+    }
+
+    /// Removes a post, undoing what [`add_post`](Self::add_post) counted.
+    /// Replies to it stay, pointing at a missing parent.
+    pub fn remove_post(&self, id: Uuid) -> Option<Post> {
+        self.remove_post_locked(&mut self.posts.write(), id)
+    }
+
+    fn remove_post_locked(&self, posts: &mut HashMap<Uuid, Post>, id: Uuid) -> Option<Post> {
+        let mut replies = self.replies.write();
+        let post = posts.remove(&id)?;
+        self.unindex_hashtags(&post);
+        self.search.write().remove(&Document::Post(id));
+        if let Some(parent_id) = post.reply_to
+            && let Some(siblings) = replies.get_mut(&parent_id)
+        {
+            siblings.retain(|reply| *reply != id);
+            if let Some(parent) = posts.get_mut(&parent_id) {
+                parent.reply_count = siblings.len() as u64;
+            }
+        }
+        if let Some(original_id) = post.repost_of
+            && post.is_repost()
+        {
+            self.reposts
+                .write()
+                .remove(&(post.author_id.clone(), original_id));
+        }
+        if let Some(original) = post.repost_of.and_then(|id| posts.get_mut(&id)) {
+            match post.is_repost() {
+                true => original.reposts = original.reposts.saturating_sub(1),
+                false => original.quotes = original.quotes.saturating_sub(1),
+            }
+        }
+        Some(post)
+    }
+
+    /// Deletes a post: plain reposts are removed, anything else becomes a
+    /// tombstone. Returns the post as it was, whose media the caller
+    /// releases.
+    pub fn delete_post(&self, id: Uuid) -> Option<Post> {
+        let mut posts = self.posts.write();
+        let post = posts.get(&id).filter(|p| !p.is_deleted())?.clone();
+        if post.is_repost() {
+            return self.remove_post_locked(&mut posts, id);
+        }
+
+        if let Some(original) = post.repost_of.and_then(|id| posts.get_mut(&id)) {
+            original.quotes = original.quotes.saturating_sub(1);
+        }
+        posts.get_mut(&id)?.tombstone();
+        drop(posts);
+        self.unindex_hashtags(&post);
+        self.search.write().remove(&Document::Post(id));
+        self.reactions.write().remove(&id);
+        Some(post)
     }
 
     pub fn get_post(&self, id: Uuid) -> Option<Post> {
         self.posts.read().get(&id).cloned()
     }
 
+    /// Direct replies to `id`, oldest first.
+    pub fn get_replies(&self, id: Uuid) -> Vec<Post> {
+        let ids = self.replies.read().get(&id).cloned().unwrap_or_default();
+        let posts = self.posts.read();
+        let mut replies: Vec<Post> = ids.iter().filter_map(|id| posts.get(id).cloned()).collect();
+        replies.sort_by_key(|p| (p.created_at, p.id));
+        replies
+    }
+
+    /// Whether `viewer` may see `post`; anonymous viewers only see public
+    /// posts, and nobody sees posts across a block. Friendship is mutual, so
+    /// followers and mutuals are the same.
+    pub fn can_view(&self, post: &Post, viewer: Option<&PeerId>) -> bool {
+        if viewer.is_some_and(|v| self.blocked_between(v, &post.author_id)) {
+            return false;
+        }
+        match post.visibility {
+            PostVisibility::Public => true,
+            _ if viewer == Some(&post.author_id) => true,
+            PostVisibility::FollowersOnly | PostVisibility::MutualsOnly => {
+                viewer.is_some_and(|v| self.are_friends(v, &post.author_id))
+            }
+            PostVisibility::Private => false,
+        }
+    }
+
+    /// Whether `viewer` may download the blob `hash`: they uploaded it, or
+    /// it or the blob it was derived from belongs to an avatar, a post they
+    /// can see, a message in a room open to them or one of their direct
+    /// messages.
+    pub fn can_view_media(&self, hash: &HashCode, viewer: &PeerId) -> bool {
+        if self.media.uploaded_by(hash, viewer) {
+            return true;
+        }
+        let sources = self.media.sources(hash);
+        let attached = |hashes: &[HashCode]| hashes.iter().any(|h| sources.contains(h));
+
+        if self
+            .users
+            .read()
+            .values()
+            .any(|u| u.avatar_hash.as_ref().is_some_and(|h| sources.contains(h)))
+        {
+            return true;
+        }
+        if self
+            .posts
+            .read()
+            .values()
+            .any(|p| attached(&p.media_hashes) && self.can_view(p, Some(viewer)))
+        {
+            return true;
+        }
+        let room_ids: HashSet<Uuid> = self
+            .messages
+            .read()
+            .values()
+            .filter(|m| attached(&m.media_hashes))
+            .map(|m| m.room_id)
+            .collect();
+        if room_ids.iter().any(|id| {
+            self.get_room(*id)
+                .is_some_and(|r| r.is_public || r.is_member(viewer))
+        }) {
+            return true;
+        }
+        self.private_messages.read().values().any(|m| {
+            attached(&m.media_hashes) && (m.sender_id == *viewer || m.recipient_id == *viewer)
+        })
+    }
+
     pub fn get_posts_by_author(&self, author_id: &PeerId) -> Vec<Post> {
         self.posts
             .read()
@@ -257,6 +1153,7 @@ impl SocialStore {
     }
 
     pub fn add_room(&self, room: ChatRoom) {
+        self.index_room(&room);
         self.rooms.write().insert(room.id, room);
     }
 
@@ -264,10 +1161,250 @@ impl SocialStore {
         self.rooms.read().get(&id).cloned()
     }
 
+    /// Removes a room `owner` owns with its messages, keys and invitations,
+    /// releasing the messages' media.
+    pub fn delete_room(&self, id: Uuid, owner: &PeerId) -> Result<ChatRoom, RoomError> {
+        let mut rooms = self.rooms.write();
+        match rooms.get(&id) {
+            Some(room) if room.owner_id == *owner => {}
+            Some(_) => return Err(RoomError::NotOwner),
+            None => return Err(RoomError::NotFound),
+        }
+        let room = rooms.remove(&id).ok_or(RoomError::NotFound)?;
+        drop(rooms);
+        self.search.write().remove(&Document::Room(id));
+        self.group_keys.write().remove(&id);
+        self.room_invites.write().retain(|_, i| i.room_id != id);
+        self.join_requests.write().remove(&id);
+        self.invite_links.write().retain(|_, l| l.room_id != id);
+
+        let mut messages = self.messages.write();
+        let removed: Vec<ChatMessage> = messages
+            .extract_if(|_, m| m.room_id == id)
+            .map(|(_, m)| m)
+            .collect();
+        drop(messages);
+        let mut reactions = self.reactions.write();
+        for message in &removed {
+            reactions.remove(&message.id);
+        }
+        drop(reactions);
+        let media: Vec<HashCode> = removed.into_iter().flat_map(|m| m.media_hashes).collect();
+        self.media.release(&media);
+        Ok(room)
+    }
+
+    /// Public rooms, newest first.
+    pub fn get_public_rooms(&self) -> Vec<ChatRoom> {
+        let mut rooms: Vec<ChatRoom> = self
+            .rooms
+            .read()
+            .values()
+            .filter(|r| r.is_public)
+            .cloned()
+            .collect();
+        rooms.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        rooms
+    }
+
+    /// Stores `invite`, or returns the one already pending for the same
+    /// room and invitee.
+    pub fn add_room_invite(&self, invite: RoomInvite) -> RoomInvite {
+        let mut invites = self.room_invites.write();
+        if let Some(existing) = invites
+            .values()
+            .find(|i| i.room_id == invite.room_id && i.invitee_id == invite.invitee_id)
+        {
+            return existing.clone();
+        }
+        invites.insert(invite.id, invite.clone());
+        invite
+    }
+
+    pub fn get_room_invite(&self, id: Uuid) -> Option<RoomInvite> {
+        self.room_invites.read().get(&id).cloned()
+    }
+
+    pub fn remove_room_invite(&self, id: Uuid) -> Option<RoomInvite> {
+        self.room_invites.write().remove(&id)
+    }
+
+    /// Invitations waiting for `user_id`, newest first.
+    pub fn get_room_invites(&self, user_id: &PeerId) -> Vec<RoomInvite> {
+        let mut invites: Vec<RoomInvite> = self
+            .room_invites
+            .read()
+            .values()
+            .filter(|i| i.invitee_id == *user_id)
+            .cloned()
+            .collect();
+        invites.sort_by_key(|i| std::cmp::Reverse(i.created_at));
+        invites
+    }
+
+    /// Drops invitations and join requests made moot by `peer` joining
+    /// `room_id`.
+    pub fn clear_room_admission(&self, room_id: Uuid, peer: &PeerId) {
+        self.room_invites
+            .write()
+            .retain(|_, i| i.room_id != room_id || i.invitee_id != *peer);
+        self.take_join_request(room_id, peer);
+    }
+
+    /// Records a join request unless one is already pending; returns
+    /// whether it is new.
+    pub fn add_join_request(&self, request: JoinRequest) -> bool {
+        let mut requests = self.join_requests.write();
+        let pending = requests.entry(request.room_id).or_default();
+        if pending.iter().any(|r| r.peer_id == request.peer_id) {
+            return false;
+        }
+        pending.push(request);
+        true
+    }
+
+    pub fn take_join_request(&self, room_id: Uuid, peer: &PeerId) -> Option<JoinRequest> {
+        let mut requests = self.join_requests.write();
+        let pending = requests.get_mut(&room_id)?;
+        let index = pending.iter().position(|r| r.peer_id == *peer)?;
+        let request = pending.remove(index);
+        if pending.is_empty() {
+            requests.remove(&room_id);
+        }
+        Some(request)
+    }
+
+    pub fn get_join_requests(&self, room_id: Uuid) -> Vec<JoinRequest> {
+        self.join_requests
+            .read()
+            .get(&room_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn add_invite_link(&self, link: InviteLink) {
+        self.invite_links.write().insert(link.id, link);
+    }
+
+    pub fn get_invite_link(&self, id: Uuid) -> Option<InviteLink> {
+        self.invite_links.read().get(&id).cloned()
+    }
+
+    /// Drops the links of `room` whose creator is no longer an admin there.
+    pub fn revoke_invite_links(&self, room: &ChatRoom) {
+        self.invite_links
+            .write()
+            .retain(|_, l| l.room_id != room.id || room.has_role(&l.created_by, RoomRole::Admin));
+    }
+
+    /// Counts a use of the link if it is still usable, dropping it once
+    /// used up.
+    pub fn use_invite_link(&self, id: Uuid) -> Option<InviteLink> {
+        let mut links = self.invite_links.write();
+        let link = links.get_mut(&id).filter(|l| l.is_usable(Utc::now()))?;
+        link.uses += 1;
+        let used = link.clone();
+        if used.max_uses.is_some_and(|n| used.uses >= n) {
+            links.remove(&id);
+        }
+        Some(used)
+    }
+
     pub fn add_message(&self, msg: ChatMessage) {
         self.messages.write().insert(msg.id, msg);
     }
 
+    pub fn get_message(&self, id: Uuid) -> Option<ChatMessage> {
+        self.messages.read().get(&id).cloned()
+    }
+
+    /// Turns a message into a tombstone, returning it as it was.
+    pub fn delete_message(&self, id: Uuid) -> Option<ChatMessage> {
+        let mut messages = self.messages.write();
+        let msg = messages.get_mut(&id).filter(|m| !m.is_deleted())?;
+        let previous = msg.clone();
+        msg.tombstone();
+        drop(messages);
+        self.reactions.write().remove(&id);
+        Some(previous)
+    }
+
+    /// Adds or removes `peer`'s `emoji` on `target` and refreshes the counts
+    /// kept on it. Returns the new counts, or `None` if the target is gone
+    /// or the reaction was already in that state.
+    pub fn set_reaction(
+        &self,
+        target: ReactionTarget,
+        emoji: &str,
+        peer: &PeerId,
+        reacted: bool,
+    ) -> Option<Vec<ReactionCount>> {
+        let mut reactions = self.reactions.write();
+        let update = |by_emoji: &mut Reactions| {
+            let changed = if reacted {
+                let mine = by_emoji.values().filter(|r| r.contains(peer)).count();
+                if mine >= MAX_REACTIONS_PER_PEER {
+                    return None;
+                }
+                by_emoji
+                    .entry(emoji.to_string())
+                    .or_default()
+                    .insert(peer.clone())
+            } else {
+                let removed = by_emoji.get_mut(emoji).is_some_and(|r| r.remove(peer));
+                by_emoji.retain(|_, reactors| !reactors.is_empty());
+                removed
+            };
+            changed.then(|| reaction_counts(by_emoji))
+        };
+
+        let id = target.id();
+        let counts = match target {
+            ReactionTarget::Post { .. } => {
+                let mut posts = self.posts.write();
+                let post = posts.get_mut(&id).filter(|p| !p.is_deleted())?;
+                let counts = update(reactions.entry(id).or_default());
+                if let Some(counts) = &counts {
+                    post.reactions = counts.clone();
+                }
+                counts
+            }
+            ReactionTarget::Message { .. } => {
+                let mut messages = self.messages.write();
+                let msg = messages.get_mut(&id).filter(|m| !m.is_deleted())?;
+                let counts = update(reactions.entry(id).or_default());
+                if let Some(counts) = &counts {
+                    msg.reactions = counts.clone();
+                }
+                counts
+            }
+        };
+        if reactions.get(&id).is_some_and(|r| r.is_empty()) {
+            reactions.remove(&id);
+        }
+        counts
+    }
+
+    /// Sets `reacted` on the counts of `id` for `viewer`.
+    pub fn mark_reacted(&self, id: Uuid, counts: &mut [ReactionCount], viewer: &PeerId) {
+        let reactions = self.reactions.read();
+        let Some(by_emoji) = reactions.get(&id) else {
+            return;
+        };
+        for count in counts {
+            count.reacted = by_emoji
+                .get(&count.emoji)
+                .is_some_and(|reactors| reactors.contains(viewer));
+        }
+    }
+
+    /// How many different reactions `peer` left on `id`.
+    pub fn reactions_by(&self, id: Uuid, peer: &PeerId) -> usize {
+        self.reactions.read().get(&id).map_or(0, |by_emoji| {
+            by_emoji.values().filter(|r| r.contains(peer)).count()
+        })
+    }
+
     pub fn get_room_messages(&self, room_id: Uuid) -> Vec<ChatMessage> {
         self.messages
             .read()
@@ -277,9 +1414,36 @@ impl SocialStore {
             .collect()
     }
 
-    pub fn request_friendship(&self, friendship: Friendship) {
-        let key = friendship_key(&friendship.requester_id, &friendship.addressee_id);
-        self.friendships.write().insert(key, friendship);
+    /// Sends a friend request. A request crossing one already sent the
+    /// other way is taken as accepting it.
+    pub fn request_friendship(
+        &self,
+        requester: &PeerId,
+        addressee: &PeerId,
+    ) -> Result<Friendship, FriendshipError> {
+        if requester == addressee {
+            return Err(FriendshipError::OwnRequest);
+        }
+        let key = friendship_key(requester, addressee);
+        let mut friendships = self.friendships.write();
+        match friendships.get_mut(&key) {
+            None => {
+                let friendship = Friendship::new(requester.clone(), addressee.clone());
+                friendships.insert(key, friendship.clone());
+                Ok(friendship)
+            }
+            Some(f) => match f.status {
+                FriendshipStatus::Blocked => Err(FriendshipError::Blocked),
+                FriendshipStatus::Accepted => Err(FriendshipError::AlreadyFriends),
+                FriendshipStatus::Pending if f.requester_id == *requester => {
+                    Err(FriendshipError::AlreadyRequested)
+                }
+                FriendshipStatus::Pending => {
+                    f.accept();
+                    Ok(f.clone())
+                }
+            },
+        }
     }
 
     pub fn get_friendship(&self, a: &PeerId, b: &PeerId) -> Option<Friendship> {
@@ -287,42 +1451,544 @@ impl SocialStore {
         self.friendships.read().get(&key).cloned()
     }
 
-    pub fn accept_friendship(&self, a: &PeerId, b: &PeerId) -> bool {
+    pub fn are_friends(&self, a: &PeerId, b: &PeerId) -> bool {
+        self.get_friendship(a, b)
+            .is_some_and(|f| f.status == FriendshipStatus::Accepted)
+    }
+
+    /// Accepts the request `requester` sent to `addressee`.
+    pub fn accept_friendship(
+        &self,
+        addressee: &PeerId,
+        requester: &PeerId,
+    ) -> Result<Friendship, FriendshipError> {
+        let key = friendship_key(addressee, requester);
+        match self.friendships.write().get_mut(&key) {
+            Some(f) if f.status == FriendshipStatus::Pending && f.addressee_id == *addressee => {
+                f.accept();
+                Ok(f.clone())
+            }
+            _ => Err(FriendshipError::RequestNotFound),
+        }
+    }
+
+    /// Turns down the request `requester` sent to `addressee`.
+    pub fn reject_friendship(
+        &self,
+        addressee: &PeerId,
+        requester: &PeerId,
+    ) -> Result<Friendship, FriendshipError> {
+        self.remove_friendship(addressee, requester, |f| {
+            f.status == FriendshipStatus::Pending && f.addressee_id == *addressee
+        })
+        .ok_or(FriendshipError::RequestNotFound)
+    }
+
+    /// Withdraws the request `requester` sent to `addressee`.
+    pub fn cancel_friend_request(
+        &self,
+        requester: &PeerId,
+        addressee: &PeerId,
+    ) -> Result<Friendship, FriendshipError> {
+        self.remove_friendship(requester, addressee, |f| {
+            f.status == FriendshipStatus::Pending && f.requester_id == *requester
+        })
+        .ok_or(FriendshipError::RequestNotFound)
+    }
+
+    pub fn unfriend(&self, a: &PeerId, b: &PeerId) -> Result<Friendship, FriendshipError> {
+        self.remove_friendship(a, b, |f| f.status == FriendshipStatus::Accepted)
+            .ok_or(FriendshipError::NotFriends)
+    }
+
+    fn remove_friendship(
+        &self,
+        a: &PeerId,
+        b: &PeerId,
+        allowed: impl FnOnce(&Friendship) -> bool,
+    ) -> Option<Friendship> {
         let key = friendship_key(a, b);
-        if let Some(f) = self.friendships.write().get_mut(&key) {
-            f.accept();
-            true
+        let mut friendships = self.friendships.write();
+        if !friendships.get(&key).is_some_and(allowed) {
+            return None;
+        }
+        friendships.remove(&key)
+    }
+
+    /// Pending requests to or from `user_id`, newest first.
+    pub fn get_pending_requests(
+        &self,
+        user_id: &PeerId,
+        direction: Option<RequestDirection>,
+    ) -> Vec<Friendship> {
+        let mut pending: Vec<Friendship> = self
+            .friendships
+            .read()
+            .values()
+            .filter(|f| f.status == FriendshipStatus::Pending)
+            .filter(|f| match direction {
+                Some(RequestDirection::Incoming) => f.addressee_id == *user_id,
+                Some(RequestDirection::Outgoing) => f.requester_id == *user_id,
+                None => f.involves(user_id),
+            })
+            .cloned()
+            .collect();
+        pending.sort_by_key(|f| std::cmp::Reverse(f.created_at));
+        pending
+    }
+
+    /// Blocks `blocked` for `blocker`, ending any friendship or request
+    /// between them.
+    pub fn block(&self, blocker: &PeerId, blocked: &PeerId) {
+        self.blocks
+            .write()
+            .entry(blocker.clone())
+            .or_default()
+            .insert(blocked.clone());
+        let mut friendship = Friendship::new(blocker.clone(), blocked.clone());
+        friendship.status = FriendshipStatus::Blocked;
+        self.friendships
+            .write()
+            .insert(friendship_key(blocker, blocked), friendship);
+    }
+
+    /// Lifts `blocker`'s block. The pair stays blocked if `blocked` has
+    /// blocked `blocker` too, and otherwise starts over as strangers.
+    pub fn unblock(&self, blocker: &PeerId, blocked: &PeerId) -> bool {
+        if !remove_peer(&self.blocks, blocker, blocked) {
+            return false;
+        }
+        let key = friendship_key(blocker, blocked);
+        let mut friendships = self.friendships.write();
+        if self.is_blocked(blocked, blocker) {
+            if let Some(f) = friendships.get_mut(&key) {
+                f.requester_id = blocked.clone();
+                f.addressee_id = blocker.clone();
+                f.updated_at = Utc::now();
+            }
         } else {
-            false
+            friendships.remove(&key);
         }
+        true
+    }
+
+    pub fn is_blocked(&self, blocker: &PeerId, blocked: &PeerId) -> bool {
+        self.blocks
+            .read()
+            .get(blocker)
+            .is_some_and(|peers| peers.contains(blocked))
+    }
+
+    /// Whether either peer has blocked the other.
+    pub fn blocked_between(&self, a: &PeerId, b: &PeerId) -> bool {
+        self.is_blocked(a, b) || self.is_blocked(b, a)
+    }
+
+    pub fn mute(&self, muter: &PeerId, muted: &PeerId) {
+        self.mutes
+            .write()
+            .entry(muter.clone())
+            .or_default()
+            .insert(muted.clone());
+    }
+
+    pub fn unmute(&self, muter: &PeerId, muted: &PeerId) -> bool {
+        remove_peer(&self.mutes, muter, muted)
+    }
+
+    pub fn is_muted(&self, muter: &PeerId, muted: &PeerId) -> bool {
+        self.mutes
+            .read()
+            .get(muter)
+            .is_some_and(|peers| peers.contains(muted))
+    }
+
+    /// Whether content by `author` should be left out of what `viewer`
+    /// lists: feeds and search results.
+    pub fn is_hidden_from(&self, author: &PeerId, viewer: Option<&PeerId>) -> bool {
+        viewer.is_some_and(|v| self.blocked_between(v, author) || self.is_muted(v, author))
+    }
+
+    pub fn get_blocked(&self, user_id: &PeerId) -> Vec<PeerId> {
+        list_peers(&self.blocks, user_id)
+    }
+
+    pub fn get_muted(&self, user_id: &PeerId) -> Vec<PeerId> {
+        list_peers(&self.mutes, user_id)
     }
 
     pub fn get_friends(&self, user_id: &PeerId) -> Vec<PeerId> {
+        self.get_friendships(user_id)
+            .iter()
+            .map(|f| f.other(user_id).clone())
+            .collect()
+    }
+
+    /// Accepted friendships of `user_id`.
+    pub fn get_friendships(&self, user_id: &PeerId) -> Vec<Friendship> {
         self.friendships
             .read()
             .values()
-            .filter(|f| f.status == FriendshipStatus::Accepted)
-            .filter(|f| f.requester_id == *user_id || f.addressee_id == *user_id)
-            .map(|f| {
-                if f.requester_id == *user_id {
-                    f.addressee_id.clone()
-                } else {
-                    f.requester_id.clone()
-                }
-            })
+            .filter(|f| f.status == FriendshipStatus::Accepted && f.involves(user_id))
+            .cloned()
             .collect()
     }
 
     pub fn add_private_message(&self, msg: PrivateMessage) {
-        self.private_messages.write().insert(msg.id, msg);
+        let mut messages = self.private_messages.write();
+        if !messages.contains_key(&msg.id) {
+            let key = conversation_key(&msg.sender_id, &msg.recipient_id);
+            self.conversations
+                .write()
+                .entry(key)
+                .or_insert_with(|| Conversation::new(msg.created_at))
+                .insert(&msg, |id| {
+                    messages
+                        .get(id)
+                        .map_or(DateTime::<Utc>::MIN_UTC, |m| m.created_at)
+                });
+        }
+        messages.insert(msg.id, msg);
     }
 
+    pub fn get_private_message(&self, id: Uuid) -> Option<PrivateMessage> {
+        self.private_messages.read().get(&id).cloned()
+    }
+
+    /// Turns a private message into a tombstone, returning it as it was.
+    pub fn delete_private_message(&self, id: Uuid) -> Option<PrivateMessage> {
+        let mut messages = self.private_messages.write();
+        let msg = messages.get_mut(&id).filter(|m| !m.is_deleted())?;
+        let previous = msg.clone();
+        msg.tombstone();
+        Some(previous)
+    }
+
+    /// Every direct message `user_id` sent or received, newest first.
     pub fn get_private_messages(&self, user_id: &PeerId) -> Vec<PrivateMessage> {
-        self.private_messages
+        let messages = self.private_messages.read();
+        let mut found: Vec<PrivateMessage> = self
+            .conversations
             .read()
-            .values()
-            .filter(|m| m.sender_id == *user_id || m.recipient_id == *user_id)
-            .cloned()
+            .iter()
+            .filter(|((a, b), _)| a == user_id || b == user_id)
+            .flat_map(|(_, c)| c.message_ids.iter())
+            .filter_map(|id| messages.get(id).cloned())
+            .map(|m| m.seen_by(user_id))
+            .collect();
+        found.sort_by_key(|m| std::cmp::Reverse(m.created_at));
+        found
+    }
+
+    /// Messages between `user_id` and `peer`, newest first, sent before
+    /// `before` if given.
+    pub fn get_conversation_messages(
+        &self,
+        user_id: &PeerId,
+        peer: &PeerId,
+        before: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Vec<PrivateMessage> {
+        let messages = self.private_messages.read();
+        let conversations = self.conversations.read();
+        let Some(conversation) = conversations.get(&conversation_key(user_id, peer)) else {
+            return Vec::new();
+        };
+        conversation
+            .message_ids
+            .iter()
+            .rev()
+            .filter_map(|id| messages.get(id))
+            .filter(|m| before.is_none_or(|before| m.created_at < before))
+            .take(limit)
+            .map(|m| m.clone().seen_by(user_id))
+            .collect()
+    }
+
+    /// `user_id`'s conversations, most recently active first.
+    pub fn get_conversations(&self, user_id: &PeerId) -> Vec<ConversationSummary> {
+        let messages = self.private_messages.read();
+        let mut summaries: Vec<ConversationSummary> = self
+            .conversations
+            .read()
+            .iter()
+            .filter_map(|((a, b), conversation)| {
+                let peer = if a == user_id {
+                    b
+                } else if b == user_id {
+                    a
+                } else {
+                    return None;
+                };
+                Some(summarize(conversation, user_id, peer, &messages))
+            })
+            .collect();
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.last_activity));
+        summaries
+    }
+
+    pub fn get_conversation(&self, user_id: &PeerId, peer: &PeerId) -> Option<ConversationSummary> {
+        let messages = self.private_messages.read();
+        let conversations = self.conversations.read();
+        let conversation = conversations.get(&conversation_key(user_id, peer))?;
+        Some(summarize(conversation, user_id, peer, &messages))
+    }
+
+    /// Marks what `peer` sent `user_id` as read. See
+    /// [`SocialStore::mark_private_messages_read`].
+    pub fn mark_conversation_read(&self, user_id: &PeerId, peer: &PeerId) -> Vec<PrivateMessage> {
+        let ids = match self
+            .conversations
+            .read()
+            .get(&conversation_key(user_id, peer))
+        {
+            Some(conversation) => conversation.message_ids.clone(),
+            None => return Vec::new(),
+        };
+        self.mark_private_messages_read(user_id, &ids)
+    }
+
+    /// Marks the messages among `ids` sent to `user_id` as read, moving
+    /// them to [`DeliveryState::Read`] unless `user_id` keeps that to
+    /// themselves. Returns the messages that changed.
+    pub fn mark_private_messages_read(
+        &self,
+        user_id: &PeerId,
+        ids: &[Uuid],
+    ) -> Vec<PrivateMessage> {
+        let send_receipt = self.sends_read_receipts(user_id);
+        let now = Utc::now();
+        let mut messages = self.private_messages.write();
+        let mut marked = Vec::new();
+        for id in ids {
+            let Some(msg) = messages
+                .get_mut(id)
+                .filter(|m| m.recipient_id == *user_id && !m.is_deleted())
+            else {
+                continue;
+            };
+            let newly_read = msg.read_at.is_none();
+            msg.read_at.get_or_insert(now);
+            let receipted = send_receipt && msg.advance(DeliveryState::Read, now);
+            if newly_read || receipted {
+                marked.push(msg.clone());
+            }
+        }
+        marked
+    }
+
+    /// Applies a receipt from `recipient` to the messages among `ids` sent
+    /// to them, returning the ids that moved on.
+    pub fn advance_private_messages(
+        &self,
+        recipient: &PeerId,
+        ids: &[Uuid],
+        state: DeliveryState,
+        at: DateTime<Utc>,
+    ) -> Vec<Uuid> {
+        let mut messages = self.private_messages.write();
+        ids.iter()
+            .filter(|id| {
+                messages
+                    .get_mut(id)
+                    .filter(|m| m.recipient_id == *recipient && !m.is_deleted())
+                    .is_some_and(|m| m.advance(state, at))
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Applies a receipt from `peer` to the messages among `ids` in
+    /// `room_id`, returning the ids that moved on.
+    pub fn advance_room_messages(
+        &self,
+        room_id: Uuid,
+        peer: &PeerId,
+        ids: &[Uuid],
+        state: DeliveryState,
+        at: DateTime<Utc>,
+    ) -> Vec<Uuid> {
+        let mut messages = self.messages.write();
+        ids.iter()
+            .filter(|id| {
+                messages
+                    .get_mut(id)
+                    .filter(|m| m.room_id == room_id && !m.is_deleted())
+                    .is_some_and(|m| m.advance(peer, state, at))
+            })
+            .copied()
             .collect()
     }
+
+    pub fn set_read_receipts(&self, user_id: &PeerId, enabled: bool) {
+        let mut disabled = self.read_receipts_disabled.write();
+        if enabled {
+            disabled.remove(user_id);
+        } else {
+            disabled.insert(user_id.clone());
+        }
+    }
+
+    pub fn sends_read_receipts(&self, user_id: &PeerId) -> bool {
+        !self.read_receipts_disabled.read().contains(user_id)
+    }
+
+    /// Records `persona` as an additional ego of `account`.
+    pub fn add_persona(&self, account: &PeerId, persona: PeerId) {
+        let mut personas = self.personas.write();
+        let list = personas.entry(account.clone()).or_default();
+        if !list.contains(&persona) {
+            list.push(persona);
+        }
+    }
+
+    pub fn get_personas(&self, account: &PeerId) -> Vec<PeerId> {
+        self.personas
+            .read()
+            .get(account)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Records the key of a new epoch and makes it current for the room.
+    /// Fails unless the distributor is a member, `epoch` follows the room's
+    /// current epoch and the key is sealed to exactly the current members.
+    pub fn rotate_group_key(&self, key: GroupKeyEpoch) -> Result<(), GroupKeyError> {
+        let mut rooms = self.rooms.write();
+        let room = rooms
+            .get_mut(&key.room_id)
+            .ok_or(GroupKeyError::RoomNotFound)?;
+        if !room.is_member(&key.distributed_by) {
+            return Err(GroupKeyError::NotMember);
+        }
+        if key.epoch != room.key_epoch + 1 {
+            return Err(GroupKeyError::StaleEpoch);
+        }
+        let members: HashSet<&PeerId> = room.members.iter().collect();
+        let recipients: HashSet<&PeerId> = key.keys.keys().collect();
+        if members != recipients {
+            return Err(GroupKeyError::WrongRecipients);
+        }
+        room.key_epoch = key.epoch;
+        room.rekey_pending = false;
+        self.group_keys
+            .write()
+            .entry(key.room_id)
+            .or_default()
+            .push(key);
+        Ok(())
+    }
+
+    /// Returns `(epoch, sealed key)` for every epoch in which `member` was
+    /// given the room's key.
+    pub fn get_group_keys(&self, room_id: Uuid, member: &PeerId) -> Vec<(u64, SealedMessage)> {
+        self.group_keys
+            .read()
+            .get(&room_id)
+            .map(|epochs| {
+                epochs
+                    .iter()
+                    .filter_map(|e| e.keys.get(member).map(|k| (e.epoch, k.clone())))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Stores `notification` unless it is about the recipient's own action,
+    /// the recipient has no account here, has muted its kind or blocked or
+    /// muted its actor.
+    pub fn add_notification(&self, notification: Notification) -> bool {
+        let recipient = &notification.recipient_id;
+        if notification.actor_id == *recipient
+            || !self.users.read().contains_key(recipient)
+            || self.is_hidden_from(&notification.actor_id, Some(recipient))
+            || self.is_notification_muted(recipient, notification.kind)
+        {
+            return false;
+        }
+
+        let mut notifications = self.notifications.write();
+        let list = notifications.entry(recipient.clone()).or_default();
+        list.push(notification);
+        if list.len() > MAX_NOTIFICATIONS {
+            list.drain(..list.len() - MAX_NOTIFICATIONS);
+        }
+        true
+    }
+
+    /// `user_id`'s notifications older than `before`, newest first.
+    pub fn get_notifications(
+        &self,
+        user_id: &PeerId,
+        before: Option<DateTime<Utc>>,
+        unread_only: bool,
+    ) -> Vec<Notification> {
+        self.notifications
+            .read()
+            .get(user_id)
+            .map(|list| {
+                list.iter()
+                    .rev()
+                    .filter(|n| before.is_none_or(|before| n.created_at < before))
+                    .filter(|n| !unread_only || !n.is_read())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Marks the given notifications of `user_id` read, or all of them if
+    /// `ids` is `None`. Returns how many were unread.
+    pub fn mark_notifications_read(&self, user_id: &PeerId, ids: Option<&[Uuid]>) -> usize {
+        let now = Utc::now();
+        let mut notifications = self.notifications.write();
+        let Some(list) = notifications.get_mut(user_id) else {
+            return 0;
+        };
+        let mut marked = 0;
+        for notification in list.iter_mut().filter(|n| !n.is_read()) {
+            if ids.is_none_or(|ids| ids.contains(&notification.id)) {
+                notification.read_at = Some(now);
+                marked += 1;
+            }
+        }
+        marked
+    }
+
+    pub fn unread_notifications(&self, user_id: &PeerId) -> usize {
+        self.notifications
+            .read()
+            .get(user_id)
+            .map_or(0, |list| list.iter().filter(|n| !n.is_read()).count())
+    }
+
+    pub fn set_notification_muted(&self, user_id: &PeerId, kind: NotificationKind, muted: bool) {
+        let mut mutes = self.muted_notifications.write();
+        if muted {
+            mutes.entry(user_id.clone()).or_default().insert(kind);
+        } else if let Some(kinds) = mutes.get_mut(user_id) {
+            kinds.remove(&kind);
+            if kinds.is_empty() {
+                mutes.remove(user_id);
+            }
+        }
+    }
+
+    pub fn is_notification_muted(&self, user_id: &PeerId, kind: NotificationKind) -> bool {
+        self.muted_notifications
+            .read()
+            .get(user_id)
+            .is_some_and(|kinds| kinds.contains(&kind))
+    }
+
+    pub fn muted_notification_kinds(&self, user_id: &PeerId) -> Vec<NotificationKind> {
+        let mut kinds: Vec<NotificationKind> = self
+            .muted_notifications
+            .read()
+            .get(user_id)
+            .map(|kinds| kinds.iter().copied().collect())
+            .unwrap_or_default();
+        kinds.sort();
+        kinds
+    }
 }
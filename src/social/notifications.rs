@@ -0,0 +1,59 @@
+use super::PeerId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Notifications kept per user; the oldest are dropped beyond this.
+pub const MAX_NOTIFICATIONS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Mention,
+    Reply,
+    Repost,
+    Reaction,
+    FriendRequest,
+    FriendAccepted,
+    RoomInvite,
+    JoinRequest,
+}
+
+/// Something that happened to `recipient_id` because of `actor_id`. Which
+/// of the optional fields are set depends on `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: Uuid,
+    pub recipient_id: PeerId,
+    pub kind: NotificationKind,
+    pub actor_id: PeerId,
+    /// The mentioning post, the reply, the repost or the post reacted to.
+    pub post_id: Option<Uuid>,
+    pub room_id: Option<Uuid>,
+    /// The room message reacted to.
+    pub message_id: Option<Uuid>,
+    pub emoji: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl Notification {
+    pub fn new(recipient_id: PeerId, kind: NotificationKind, actor_id: PeerId) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            recipient_id,
+            kind,
+            actor_id,
+            post_id: None,
+            room_id: None,
+            message_id: None,
+            emoji: None,
+            created_at: Utc::now(),
+            read_at: None,
+        }
+    }
+
+    pub fn is_read(&self) -> bool {
+        self.read_at.is_some()
+    }
+}
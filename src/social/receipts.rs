@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How far a message has got towards a recipient. States only move
+/// forward.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// Stored on the sender's server.
+    #[default]
+    Sent,
+    /// Stored on the recipient's server.
+    Delivered,
+    Read,
+}
+
+/// A recipient's latest receipt for a room message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub state: DeliveryState,
+    pub at: DateTime<Utc>,
+}
+
+/// The messages a receipt or acknowledgement covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReceiptTarget {
+    PrivateMessage {
+        message_ids: Vec<Uuid>,
+    },
+    Room {
+        room_id: Uuid,
+        message_ids: Vec<Uuid>,
+    },
+}
+
+impl ReceiptTarget {
+    pub fn message_ids(&self) -> &[Uuid] {
+        match self {
+            Self::PrivateMessage { message_ids } | Self::Room { message_ids, .. } => message_ids,
+        }
+    }
+
+    /// The same target, narrowed to `message_ids`.
+    pub fn with_ids(&self, message_ids: Vec<Uuid>) -> Self {
+        match self {
+            Self::PrivateMessage { .. } => Self::PrivateMessage { message_ids },
+            Self::Room { room_id, .. } => Self::Room {
+                room_id: *room_id,
+                message_ids,
+            },
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use super::PeerId;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use uuid::Uuid;
+
+/// Tokens longer than this, in characters, are not indexed.
+pub const MAX_TOKEN_LEN: usize = 64;
+/// Query tokens beyond this many are ignored.
+pub const MAX_QUERY_TOKENS: usize = 8;
+/// Shorter query tokens only match whole terms, not prefixes.
+const MIN_PREFIX_LEN: usize = 2;
+/// Score of a prefix match relative to a whole-term match.
+const PREFIX_WEIGHT: f64 = 0.5;
+/// A new document scores up to this much more than an old one...
+const RECENCY_WEIGHT: f64 = 1.0;
+/// ...and the boost halves every this many days.
+const RECENCY_HALF_LIFE_DAYS: f64 = 7.0;
+
+/// Something that can be found by searching.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Document {
+    User(PeerId),
+    Post(Uuid),
+    Room(Uuid),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    User,
+    Post,
+    Room,
+}
+
+impl Document {
+    pub fn kind(&self) -> DocumentKind {
+        match self {
+            Document::User(_) => DocumentKind::User,
+            Document::Post(_) => DocumentKind::Post,
+            Document::Room(_) => DocumentKind::Room,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Indexed {
+    terms: Vec<String>,
+    timestamp: DateTime<Utc>,
+}
+
+/// Inverted index from case-folded terms to the documents containing them,
+/// weighted by how often and in which field each term appears. Terms are
+/// kept sorted so a query token also matches the terms it is a prefix of.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    terms: BTreeMap<String, HashMap<Document, u32>>,
+    documents: HashMap<Document, Indexed>,
+}
+
+/// Case-folds `text` so that matching ignores case in any script.
+pub fn fold_case(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            _ => folded.extend(c.to_lowercase()),
+        }
+    }
+    folded
+}
+
+/// Splits `text` into case-folded words.
+pub fn tokenize(text: &str) -> Vec<String> {
+    fold_case(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty() && t.chars().count() <= MAX_TOKEN_LEN)
+        .map(str::to_string)
+        .collect()
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes `doc` from `(text, weight)` fields, replacing what was
+    /// indexed for it before.
+    pub fn insert(&mut self, doc: Document, fields: &[(&str, u32)], timestamp: DateTime<Utc>) {
+        self.remove(&doc);
+
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for (text, weight) in fields {
+            for token in tokenize(text) {
+                *counts.entry(token).or_default() += weight;
+            }
+        }
+        if counts.is_empty() {
+            return;
+        }
+
+        let terms = counts.keys().cloned().collect();
+        for (term, count) in counts {
+            self.terms
+                .entry(term)
+                .or_default()
+                .insert(doc.clone(), count);
+        }
+        self.documents.insert(doc, Indexed { terms, timestamp });
+    }
+
+    pub fn remove(&mut self, doc: &Document) {
+        let Some(indexed) = self.documents.remove(doc) else {
+            return;
+        };
+        for term in indexed.terms {
+            if let Some(postings) = self.terms.get_mut(&term) {
+                postings.remove(doc);
+                if postings.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Documents of `kind` matching every word of `query`, best first. A
+    /// word matches terms equal to it or, less strongly, starting with it.
+    /// Rarer terms count for more, and newer documents are boosted.
+    pub fn search(&self, query: &str, kind: DocumentKind) -> Vec<Document> {
+        let mut tokens = tokenize(query);
+        tokens.truncate(MAX_QUERY_TOKENS);
+        tokens.sort();
+        tokens.dedup();
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+
+        let total = self.documents.len() as f64;
+        let mut scores: HashMap<&Document, (usize, f64)> = HashMap::new();
+        for token in &tokens {
+            let mut best: HashMap<&Document, f64> = HashMap::new();
+            for (term, postings) in self.matching_terms(token) {
+                let weight = if term == token { 1.0 } else { PREFIX_WEIGHT };
+                let idf = (1.0 + total / postings.len() as f64).ln();
+                for (doc, count) in postings.iter().filter(|(d, _)| d.kind() == kind) {
+                    let score = weight * idf * (1.0 + (*count as f64).ln());
+                    let entry = best.entry(doc).or_default();
+                    *entry = entry.max(score);
+                }
+            }
+            for (doc, score) in best {
+                let entry = scores.entry(doc).or_default();
+                entry.0 += 1;
+                entry.1 += score;
+            }
+        }
+
+        let now = Utc::now();
+        let mut ranked: Vec<(&Document, f64)> = scores
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == tokens.len())
+            .map(|(doc, (_, score))| (doc, score * self.recency_boost(doc, now)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.into_iter().map(|(doc, _)| doc.clone()).collect()
+    }
+
+    fn matching_terms<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a HashMap<Document, u32>)> {
+        let prefix = token.chars().count() >= MIN_PREFIX_LEN;
+        self.terms
+            .range::<str, _>((Bound::Included(token), Bound::Unbounded))
+            .take_while(move |(term, _)| match prefix {
+                true => term.starts_with(token),
+                false => term.as_str() == token,
+            })
+    }
+
+    fn recency_boost(&self, doc: &Document, now: DateTime<Utc>) -> f64 {
+        let Some(indexed) = self.documents.get(doc) else {
+            return 1.0;
+        };
+        let age_days = (now - indexed.timestamp).num_seconds().max(0) as f64 / 86_400.0;
+        1.0 + RECENCY_WEIGHT * 0.5f64.powf(age_days / RECENCY_HALF_LIFE_DAYS)
+    }
+}